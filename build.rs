@@ -0,0 +1,66 @@
+use std::env;
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+// Decodes the tray icons at build time, so the binary only embeds raw RGBA
+// pixels and does not need a PNG decoder at runtime.
+const ICONS: &[(&str, &str)] = &[
+    ("ENABLED_ICON", "assets/enabled.png"),
+    ("DISABLED_ICON", "assets/disabled.png"),
+];
+
+// Output of `program`, or "unknown" when it fails, e.g. outside of a git
+// checkout
+fn get_output(program: &str, args: &[&str]) -> String {
+    match Command::new(program).args(args).output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        _ => "unknown".to_string(),
+    }
+}
+
+// Shown by `version --verbose`
+fn emit_build_info() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    println!("cargo:rerun-if-env-changed=SOURCE_DATE_EPOCH");
+
+    let commit = get_output("git", &["rev-parse", "--short", "HEAD"]);
+    // Reproducible builds pin the date
+    let date = match env::var("SOURCE_DATE_EPOCH") {
+        Ok(epoch) => get_output("date", &["-u", "-d", &format!("@{}", epoch), "+%Y-%m-%d"]),
+        Err(_) => get_output("date", &["-u", "+%Y-%m-%d"]),
+    };
+
+    println!("cargo:rustc-env=BUILD_GIT_COMMIT={}", commit);
+    println!("cargo:rustc-env=BUILD_DATE={}", date);
+}
+
+fn main() {
+    emit_build_info();
+
+    let out_dir = env::var("OUT_DIR").unwrap();
+    let mut generated = String::new();
+
+    for (name, path) in ICONS {
+        println!("cargo:rerun-if-changed={}", path);
+
+        let image_buff = match image::open(path) {
+            Ok(image_dyn) => image_dyn.into_rgba8(),
+            Err(e) => panic!("Failed to load icon {}: {}", path, e),
+        };
+        let (width, height) = image_buff.dimensions();
+
+        let rgba_path = Path::new(&out_dir).join(format!("{}.rgba", name.to_lowercase()));
+        fs::write(&rgba_path, image_buff.into_raw()).unwrap();
+
+        generated.push_str(&format!(
+            "const {name}_RGBA: &[u8] = include_bytes!({rgba_path:?});\n\
+             const {name}_SIZE: (u32, u32) = ({width}, {height});\n"
+        ));
+    }
+
+    fs::write(Path::new(&out_dir).join("icons.rs"), generated).unwrap();
+}
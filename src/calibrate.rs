@@ -0,0 +1,211 @@
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+
+use crate::IDENTITY_TEMPERATURE;
+use crate::config::Config;
+use crate::control::send_control_command;
+
+const STEP: i32 = 100;
+
+// Long enough to think it over, short enough that an aborted calibration
+// doesn't leave the screen orange for long
+const PREVIEW_SECONDS: u64 = 60;
+
+// Puts the terminal into non-canonical mode, so single key presses can be
+// read, until dropped
+struct RawTerminal;
+
+impl RawTerminal {
+    fn stty(args: &[&str]) -> Result<()> {
+        let tty = File::open("/dev/tty").context("Failed to open the terminal")?;
+        match Command::new("stty").args(args).stdin(tty).status() {
+            Ok(status) if status.success() => Ok(()),
+            Ok(status) => Err(anyhow!("stty exited with {}", status)),
+            Err(e) => Err(e).context("Failed to run stty"),
+        }
+    }
+
+    fn enable() -> Result<Self> {
+        Self::stty(&["-icanon", "-echo", "min", "1"])?;
+        Ok(Self)
+    }
+}
+
+impl Drop for RawTerminal {
+    fn drop(&mut self) {
+        let _ = Self::stty(&["icanon", "echo"]);
+    }
+}
+
+enum Key {
+    Next,
+    Previous,
+    Accept,
+    Quit,
+}
+
+fn read_key() -> Result<Key> {
+    let mut stdin = std::io::stdin();
+    let mut byte = [0];
+    loop {
+        if stdin.read(&mut byte).context("Failed to read key")? == 0 {
+            return Ok(Key::Quit);
+        }
+
+        match byte[0] {
+            b'n' | b'-' => return Ok(Key::Next),
+            b'p' | b'+' => return Ok(Key::Previous),
+            b'a' | b'\n' => return Ok(Key::Accept),
+            b'q' | 0x1b => return Ok(Key::Quit),
+            _ => {}
+        };
+    }
+}
+
+// Lets the user step through temperatures, starting at `temperature`.
+// `None` if the calibration was aborted.
+fn pick_temperature(label: &str, temperature: i32, config: &Config) -> Result<Option<i32>> {
+    let mut temperature = temperature;
+
+    loop {
+        send_control_command(&format!(
+            "preview {} --seconds {}",
+            temperature, PREVIEW_SECONDS
+        ))?;
+
+        print!(
+            "\r{}: {}K  [n]ext (warmer), [p]revious (cooler), [a]ccept, [q]uit ",
+            label, temperature
+        );
+        std::io::stdout().flush()?;
+
+        temperature = match read_key()? {
+            Key::Next => temperature - STEP,
+            Key::Previous => temperature + STEP,
+            Key::Accept => {
+                println!();
+                return Ok(Some(temperature));
+            }
+            Key::Quit => {
+                println!();
+                return Ok(None);
+            }
+        }
+        .clamp(config.min_temperature, config.max_temperature);
+    }
+}
+
+// Sets a top level `key` in the config file, keeping everything else,
+// including comments. `None` removes the key.
+pub fn set_top_level_value(contents: &str, key: &str, value: Option<&str>) -> String {
+    let mut lines: Vec<String> = Vec::new();
+    let mut replaced = false;
+    let mut in_table = false;
+    // Where a new key goes, after the last top level line
+    let mut insert_at = 0;
+
+    for line in contents.lines() {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') {
+            in_table = true;
+        }
+
+        let is_key = !in_table
+            && trimmed
+                .strip_prefix(key)
+                .is_some_and(|rest| rest.trim_start().starts_with('='));
+        if is_key {
+            if let (Some(value), false) = (value, replaced) {
+                lines.push(format!("{} = {}", key, value));
+                replaced = true;
+            }
+        } else {
+            lines.push(line.to_string());
+        }
+
+        if !in_table && !trimmed.is_empty() {
+            insert_at = lines.len();
+        }
+    }
+
+    if let (Some(value), false) = (value, replaced) {
+        lines.insert(insert_at, format!("{} = {}", key, value));
+    }
+
+    lines.join("\n") + "\n"
+}
+
+// Steps through night and day temperatures on the running daemon, then
+// writes the accepted ones to the config file
+pub fn calibrate() -> Result<()> {
+    let config_path = Config::get_path()?;
+    let config = Config::read(&config_path, None)?;
+
+    println!("Pick the temperatures that feel comfortable. Each one is shown on your screen.");
+
+    let picked = {
+        let _terminal = RawTerminal::enable()?;
+
+        let night = pick_temperature("Night", config.temperature, &config)?;
+        let day = match night {
+            Some(_) => pick_temperature(
+                "Day",
+                config.day_temperature.unwrap_or(IDENTITY_TEMPERATURE),
+                &config,
+            )?,
+            None => None,
+        };
+
+        night.zip(day)
+    };
+
+    send_control_command("preview off")?;
+
+    let (night, day) = match picked {
+        Some(picked) => picked,
+        None => {
+            println!("Calibration aborted, the config is unchanged");
+            return Ok(());
+        }
+    };
+
+    let contents = match fs::read_to_string(&config_path) {
+        Ok(contents) => contents,
+        Err(e) => return Err(e).context(format!("Failed to read {:?}", config_path)),
+    };
+    let contents = set_top_level_value(&contents, "temperature", Some(&night.to_string()));
+    // The filter is simply turned off during the day unless asked otherwise
+    let day_value = (day < IDENTITY_TEMPERATURE).then(|| day.to_string());
+    let contents = set_top_level_value(&contents, "day_temperature", day_value.as_deref());
+
+    Config::parse(&contents).context("Calibration produced an invalid config")?;
+    match fs::write(&config_path, contents) {
+        Ok(_) => {}
+        Err(e) => return Err(e).context(format!("Failed to write {:?}", config_path)),
+    };
+
+    println!("Saved to {}", config_path.display());
+    Ok(())
+}
+
+#[test]
+fn test_set_top_level_value() {
+    let contents = "temperature = 3000\n# Nairobi\nlatitude = -1.2921\n\n[[schedule.points]]\ntime = \"22:00\"\ntemperature = 2800\n";
+
+    let updated = set_top_level_value(contents, "temperature", Some("2700"));
+    assert!(updated.starts_with("temperature = 2700\n# Nairobi\n"));
+    assert!(updated.contains("temperature = 2800"));
+
+    let updated = set_top_level_value(&updated, "day_temperature", Some("5500"));
+    assert!(updated.contains("latitude = -1.2921\nday_temperature = 5500\n\n[[schedule.points]]"));
+
+    let updated = set_top_level_value(&updated, "day_temperature", None);
+    assert!(!updated.contains("day_temperature"));
+    assert_eq!(
+        updated,
+        set_top_level_value(contents, "temperature", Some("2700"))
+    );
+}
@@ -0,0 +1,1200 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use anyhow::{Context, Result, anyhow};
+use chrono::NaiveTime;
+use chrono::format::{Item, StrftimeItems};
+use hyprsunset_overdrive::error::ConfigError;
+use serde::de::Visitor;
+use serde::{Deserialize, Deserializer};
+use toml::Table;
+use tracing::{info, warn};
+
+use crate::location::{Axis, check_coordinate, find_city, parse_coordinate};
+use crate::notification::NotificationSettings;
+use crate::schedule::{Schedule, TimeFormat, deserialize_optional_time, deserialize_temperatures};
+
+const METERS_PER_FOOT: f64 = 0.3048;
+
+// From the shore of the Dead Sea to the top of Mount Everest
+const MIN_ALTITUDE: f64 = -500.0;
+const MAX_ALTITUDE: f64 = 9000.0;
+
+// Higher than any city, likely feet entered as meters
+const SUSPICIOUS_ALTITUDE: f64 = 5000.0;
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Altitude {
+    Meters(f64),
+    Text(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Coordinate {
+    Degrees(f64),
+    Text(String),
+}
+
+fn deserialize_coordinate<'de, D>(deserializer: D, axis: Axis) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let coordinate = match Coordinate::deserialize(deserializer)? {
+        Coordinate::Degrees(degrees) => check_coordinate(degrees, axis),
+        Coordinate::Text(text) => parse_coordinate(&text, axis),
+    };
+    coordinate.map_err(|e| serde::de::Error::custom(format!("{:#}", e)))
+}
+
+// Decimal degrees, or a string such as "52°31'N"
+fn deserialize_latitude<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_coordinate(deserializer, Axis::Latitude)
+}
+
+fn deserialize_longitude<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    deserialize_coordinate(deserializer, Axis::Longitude)
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct DayModeTemperature {
+    temperature: i32,
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum DayMode {
+    Kelvin(i32),
+    Name(String),
+    Table(DayModeTemperature),
+}
+
+// `day_temperature = 5500`, or `day_mode = "identity"` and
+// `day_mode = { temperature = 6500 }`. A fixed temperature looks the same
+// with every backend, where identity is up to the display pipeline.
+fn deserialize_day_mode<'de, D>(deserializer: D) -> std::result::Result<Option<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match DayMode::deserialize(deserializer)? {
+        DayMode::Kelvin(temperature) | DayMode::Table(DayModeTemperature { temperature }) => {
+            Ok(Some(temperature))
+        }
+        DayMode::Name(name) if name == "identity" => Ok(None),
+        DayMode::Name(name) => Err(serde::de::Error::custom(format!(
+            "invalid day mode \"{}\", expected \"identity\" or {{ temperature = 6500 }}",
+            name
+        ))),
+    }
+}
+
+// "1609", "1609m" or "5280ft", in meters
+fn parse_altitude(altitude: &str) -> Option<f64> {
+    let altitude = altitude.trim();
+    let (value, factor) = match altitude.strip_suffix("ft") {
+        Some(feet) => (feet, METERS_PER_FOOT),
+        None => (altitude.strip_suffix('m').unwrap_or(altitude), 1.0),
+    };
+
+    value.trim().parse::<f64>().ok().map(|value| value * factor)
+}
+
+// A number of meters, or a string with the unit
+fn deserialize_altitude<'de, D>(deserializer: D) -> std::result::Result<f64, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Altitude::deserialize(deserializer)? {
+        Altitude::Meters(meters) => Ok(meters),
+        Altitude::Text(text) => parse_altitude(&text).ok_or_else(|| {
+            serde::de::Error::custom(format!(
+                "invalid altitude \"{}\", expected meters or e.g. \"5280ft\"",
+                text
+            ))
+        }),
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    // hyprsunset under Hyprland, the gamma command elsewhere
+    #[default]
+    Auto,
+    Hyprsunset,
+    Command,
+    // Only records the commands, see `NullBackend`
+    Null,
+}
+
+// What the screen is left at when the program exits
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ExitState {
+    // Whatever is applied, with a transition finished at once
+    #[default]
+    Keep,
+    Identity,
+    Night,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub temperature: i32,
+    // Applied during the day instead of turning the filter off. Also read
+    // from `day_mode`, see `deserialize_day_mode`.
+    #[serde(alias = "day_mode", deserialize_with = "deserialize_day_mode")]
+    pub day_temperature: Option<i32>,
+    // Looked up in the bundled city list. Takes precedence over latitude,
+    // longitude and altitude.
+    pub city: Option<String>,
+    #[serde(deserialize_with = "deserialize_latitude")]
+    pub latitude: f64,
+    #[serde(deserialize_with = "deserialize_longitude")]
+    pub longitude: f64,
+    // In meters, see `deserialize_altitude`
+    #[serde(deserialize_with = "deserialize_altitude")]
+    pub altitude: f64,
+    // Look up `altitude` from the coordinates on startup instead
+    pub lookup_altitude: bool,
+    // Use the location of the system timezone instead, for traveling
+    pub follow_timezone: bool,
+    // Bounds for manual and relative temperature adjustments
+    pub min_temperature: i32,
+    pub max_temperature: i32,
+    // Local time at which the filter should be fully off, even if the sun
+    // is not up yet. The fade starts `wake_fade_minutes` earlier.
+    #[serde(deserialize_with = "deserialize_optional_time")]
+    pub wake_time: Option<NaiveTime>,
+    pub wake_fade_minutes: u32,
+    // Enable the filter while the sun is up instead, for night shift work
+    pub invert_schedule: bool,
+    // Pushes sunset back on Friday and Saturday nights
+    pub weekend_sunset_delay_minutes: u32,
+    // Astronomy mode preserves dark adaptation at the telescope
+    pub astro_temperature: i32,
+    // Percentage passed to hyprsunset's `gamma` command
+    pub astro_gamma: i32,
+    // Reading mode is for long text sessions, whatever the time of day
+    pub reading_temperature: i32,
+    pub reading_gamma: i32,
+    // Moves the night temperature with the moon phase
+    pub lunar: bool,
+    // Stepped through by `cycle`. `None` is identity.
+    #[serde(deserialize_with = "deserialize_temperatures")]
+    pub cycle: Vec<Option<i32>>,
+    // Applied at night by `wind-down on`, e.g. from hypridle shortly before
+    // the screen locks
+    pub wind_down_temperature: Option<i32>,
+    // Replaces the sunset/sunrise schedule when it has any points
+    pub schedule: Schedule,
+    // Used for times shown to the user
+    pub time_format: TimeFormat,
+    // The line for `status --format` and the tray tooltip, see
+    // `format_status_line`
+    pub status_format: String,
+    // Write the log to $XDG_RUNTIME_DIR as well as to the terminal
+    pub log_to_file: bool,
+    // Where the files go instead of the XDG directories, see `paths`
+    pub runtime_dir: Option<PathBuf>,
+    pub state_dir: Option<PathBuf>,
+    pub log_dir: Option<PathBuf>,
+    // strftime format for times in the log, in local time
+    pub log_time_format: String,
+    // Hold off at session start so bars and hyprsunset come up first
+    pub startup_delay_seconds: u64,
+    // Wait for a bar that shows tray icons before creating ours
+    pub wait_for_tray_host: bool,
+    // Draw the temperature in hundreds of Kelvin onto the tray icon
+    pub tray_badge: bool,
+    // Icons to use instead of the embedded ones, see `icon_theme`
+    pub icon_theme_dir: Option<PathBuf>,
+    // Size of tray icons on the panel, before the monitor scale
+    pub tray_icon_size: u32,
+    // Changes arriving faster than this are held back and only the last one
+    // is applied, e.g. from a held down keybind
+    pub min_command_interval_ms: u64,
+    pub notifications: NotificationSettings,
+    // Rhai script deciding the temperature, needs the `scripting` feature
+    pub script: Option<PathBuf>,
+    // What applies the temperature
+    pub backend: BackendKind,
+    pub on_exit: ExitState,
+    // Run outside of Hyprland to apply the temperature, and kept running
+    // while the filter is on
+    pub gamma_command: String,
+    // Apply the schedule to every running Hyprland instance, not only the one
+    // we were started in
+    pub all_instances: bool,
+    // Not supported, since hyprsunset and the gamma command apply one color
+    // transform to every monitor. Only read to reject it with a clear error.
+    pub exclude_monitors: Vec<String>,
+    // Processes that live exactly as long as the screen is locked, as named
+    // in /proc/PID/comm
+    pub screen_lockers: Vec<String>,
+    // Overrides applied while connected to the Wi-Fi network in their `ssid`,
+    // or when picked from the tray
+    pub profiles: Table,
+    // Which layer the coordinates came from
+    #[serde(skip)]
+    pub location_provider: String,
+    // The profile applied, if any
+    #[serde(skip)]
+    pub active_profile: Option<String>,
+}
+
+fn read_file(path: &Path) -> Result<String> {
+    match fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(source) => Err(ConfigError::Read {
+            path: path.to_path_buf(),
+            source,
+        }
+        .into()),
+    }
+}
+
+fn parse_table(contents: &str, path: &Path) -> Result<Table> {
+    match toml::from_str(contents) {
+        Ok(table) => Ok(table),
+        Err(e) => Err(ConfigError::Parse {
+            path: path.to_path_buf(),
+            message: e.to_string(),
+        }
+        .into()),
+    }
+}
+
+// $XDG_CONFIG_HOME, or ~/.config if it is unset or not an absolute path as
+// the spec requires
+fn get_config_home() -> Result<PathBuf> {
+    if let Some(dir) = std::env::var_os("XDG_CONFIG_HOME").map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return Ok(dir);
+    }
+
+    match dirs::home_dir() {
+        Some(home) => Ok(home.join(".config")),
+        None => Err(anyhow!("Failed to find config directory")),
+    }
+}
+
+// In order of precedence
+fn get_candidate_paths(config_home: &Path) -> [PathBuf; 2] {
+    [
+        config_home.join("hypr").join("hyprsunset-overdrive.toml"),
+        config_home.join("hyprsunset-overdrive").join("config.toml"),
+    ]
+}
+
+fn find_config_path(config_home: &Path) -> Option<PathBuf> {
+    let candidates = get_candidate_paths(config_home);
+    let existing: Vec<&PathBuf> = candidates.iter().filter(|path| path.exists()).collect();
+
+    let (used, ignored) = existing.split_first()?;
+    for path in ignored {
+        warn!("Ignoring {:?}, {:?} takes precedence", path, used);
+    }
+
+    Some(used.to_path_buf())
+}
+
+const ENV_PREFIX: &str = "HYPRSUNSET_OVERDRIVE_";
+
+// Between the keys of a nested table in a variable name
+const ENV_SEPARATOR: &str = "__";
+
+// The keys a struct takes, aliases included, as its derived `Deserialize`
+// hands them to the deserializer
+fn get_field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldNames<'_> {
+        type Error = serde::de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(
+            self,
+            _visitor: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            Err(serde::de::Error::custom("not a struct"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> std::result::Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(serde::de::Error::custom("only the field names are read"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+// Whether the `path` of keys from a variable names a config field. Profiles
+// take any key and are checked when they are merged in.
+fn is_config_field(path: &[String]) -> bool {
+    if path.iter().any(String::is_empty) {
+        return false;
+    }
+
+    match path {
+        [key] => get_field_names::<Config>().contains(&key.as_str()),
+        [table, key] if table == "notifications" => {
+            get_field_names::<NotificationSettings>().contains(&key.as_str())
+        }
+        [table, key] if table == "schedule" => {
+            get_field_names::<Schedule>().contains(&key.as_str())
+        }
+        [table, _, ..] => table == "profiles",
+        [] => false,
+    }
+}
+
+// HYPRSUNSET_OVERDRIVE_WAKE_TIME=06:30 sets `wake_time`, and
+// HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__MORNING=false sets `morning` in the
+// `[notifications]` table. Values are read as TOML, falling back to a plain
+// string, so both numbers and times work. A variable that doesn't name a
+// field is left out with a warning, where it would fail the whole config.
+fn get_env_overrides(vars: impl Iterator<Item = (String, String)>) -> Table {
+    let mut table = Table::new();
+
+    for (name, value) in vars {
+        let path: Vec<String> = match name.strip_prefix(ENV_PREFIX) {
+            Some(key) => key.split(ENV_SEPARATOR).map(str::to_lowercase).collect(),
+            None => continue,
+        };
+        if !is_config_field(&path) {
+            warn!("Ignoring {}, which doesn't name a config key", name);
+            continue;
+        }
+
+        let value = match toml::from_str::<Table>(&format!("value = {}", value)) {
+            Ok(mut parsed) => parsed.remove("value").unwrap_or(toml::Value::String(value)),
+            Err(_) => toml::Value::String(value),
+        };
+
+        // HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__MORNING=false is
+        // HYPRSUNSET_OVERDRIVE_NOTIFICATIONS="{ morning = false }"
+        let overlay = path.into_iter().rev().fold(value, |value, key| {
+            toml::Value::Table(Table::from_iter([(key, value)]))
+        });
+        if let toml::Value::Table(overlay) = overlay {
+            merge_tables(&mut table, overlay);
+        }
+    }
+
+    table
+}
+
+// hyprsunset-overdrive.toml -> hyprsunset-overdrive.d
+fn get_drop_in_dir(config_path: &Path) -> PathBuf {
+    config_path.with_extension("d")
+}
+
+// The *.toml files in the drop-in directory, in lexical order
+fn get_drop_in_paths(dir: &Path) -> Result<Vec<PathBuf>> {
+    if !dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let entries = match fs::read_dir(dir) {
+        Ok(entries) => entries,
+        Err(e) => return Err(e).context(format!("Failed to read {:?}", dir)),
+    };
+
+    let mut paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+        .filter(|path| path.is_file() && path.extension().is_some_and(|ext| ext == "toml"))
+        .collect();
+    paths.sort();
+
+    Ok(paths)
+}
+
+// Tables are merged key by key. Any other value, including arrays such as
+// the schedule points, replaces the one underneath.
+fn merge_tables(base: &mut Table, overlay: Table) {
+    for (key, value) in overlay {
+        match (base.get_mut(&key), value) {
+            (Some(toml::Value::Table(base)), toml::Value::Table(overlay)) => {
+                merge_tables(base, overlay)
+            }
+            (_, value) => {
+                base.insert(key, value);
+            }
+        };
+    }
+}
+
+// The profile `picked` from the tray, or else the one for the network
+// `ssid`, with its name. Profiles are checked even when not connected, so
+// mistakes show up right away.
+fn get_network_profile(
+    layers: &[(String, Table)],
+    ssid: Option<&str>,
+    picked: Option<&str>,
+) -> Result<Option<(String, Table)>> {
+    let mut profiles = Table::new();
+    for (_, table) in layers {
+        if let Some(toml::Value::Table(layer)) = table.get("profiles") {
+            merge_tables(&mut profiles, layer.clone());
+        }
+    }
+
+    let mut matching = None;
+    let mut found = None;
+    for (name, profile) in profiles {
+        let mut profile = match profile {
+            toml::Value::Table(profile) => profile,
+            _ => return Err(anyhow!("profile {} must be a table", name)),
+        };
+
+        match profile.remove("ssid") {
+            Some(toml::Value::String(profile_ssid)) => {
+                if Some(name.as_str()) == picked {
+                    found = Some((name, profile));
+                } else if matching.is_none() && Some(profile_ssid.as_str()) == ssid {
+                    matching = Some((name, profile));
+                }
+            }
+            _ => return Err(anyhow!("profile {} needs an ssid", name)),
+        };
+    }
+
+    if let Some(picked) = picked.filter(|_| found.is_none()) {
+        warn!("Profile {} no longer exists, picking by network", picked);
+    }
+
+    Ok(found.or(matching))
+}
+
+// Every field is optional. Missing fields are taken from here, which
+// matches the default config file.
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            temperature: 3000,
+            day_temperature: None,
+            city: None,
+            // Nairobi, Kenya
+            latitude: -1.2921,
+            longitude: 36.8219,
+            altitude: 1795.0,
+            lookup_altitude: false,
+            follow_timezone: false,
+            // The range hyprsunset accepts
+            min_temperature: 1000,
+            max_temperature: 20000,
+            wake_time: None,
+            wake_fade_minutes: 30,
+            invert_schedule: false,
+            weekend_sunset_delay_minutes: 0,
+            astro_temperature: 1000,
+            lunar: false,
+            cycle: vec![None, Some(4500), Some(3500), Some(2500)],
+            wind_down_temperature: None,
+            backend: BackendKind::default(),
+            on_exit: ExitState::default(),
+            gamma_command: "gammastep -m wayland -O {temperature} -b {brightness}".to_string(),
+            all_instances: false,
+            exclude_monitors: Vec::new(),
+            screen_lockers: ["hyprlock", "swaylock", "gtklock"]
+                .map(String::from)
+                .to_vec(),
+            astro_gamma: 50,
+            reading_temperature: 4500,
+            reading_gamma: 90,
+            schedule: Schedule::default(),
+            time_format: TimeFormat::default(),
+            status_format: "{icon} {temp}K".to_string(),
+            log_to_file: true,
+            runtime_dir: None,
+            state_dir: None,
+            log_dir: None,
+            log_time_format: "%Y-%m-%d %H:%M:%S %Z".to_string(),
+            startup_delay_seconds: 0,
+            wait_for_tray_host: false,
+            tray_badge: false,
+            icon_theme_dir: None,
+            tray_icon_size: 24,
+            min_command_interval_ms: 100,
+            notifications: NotificationSettings::default(),
+            script: None,
+            profiles: Table::new(),
+            location_provider: "default".to_string(),
+            active_profile: None,
+        }
+    }
+}
+
+impl Config {
+    // The first config that exists, or where the default config is created
+    pub fn get_path() -> Result<PathBuf> {
+        let config_home = get_config_home()?;
+        if let Some(path) = find_config_path(&config_home) {
+            return Ok(path);
+        }
+
+        let default_path = get_candidate_paths(&config_home)[0].clone();
+
+        // Configs left in ~/.config are not picked up once XDG_CONFIG_HOME
+        // points somewhere else
+        if let Some(default_home) = dirs::home_dir().map(|home| home.join(".config"))
+            && default_home != config_home
+        {
+            for path in get_candidate_paths(&default_home) {
+                if path.exists() {
+                    warn!(
+                        "Ignoring {:?} because XDG_CONFIG_HOME is set. Move it to {:?}",
+                        path, default_path
+                    );
+                }
+            }
+        }
+
+        Ok(default_path)
+    }
+
+    pub fn load(ssid: Option<&str>, profile: Option<&str>) -> Result<Self> {
+        let config_path = Self::get_path()?;
+
+        if !config_path.exists() {
+            if let Some(parent) = config_path.parent() {
+                std::fs::create_dir_all(parent).context("Failed to create config directory")?;
+            };
+
+            let default_config = r#"temperature = 3000
+# Coordinates for Nairobi, Kenya
+latitude = -1.2921
+longitude = 36.8219
+# Altitude of Nairobi, Kenya in meters. You can set it as 0.
+altitude = 1795
+            "#;
+
+            match fs::write(&config_path, default_config) {
+                Ok(_) => info!("Created default config file"),
+                Err(e) => return Err(e).context("Failed to create default config file"),
+            };
+        }
+
+        let config = Self::read_with_profile(&config_path, ssid, profile, std::env::vars())?;
+
+        info!("Config loaded");
+
+        Ok(config)
+    }
+
+    // Reads the config file, then layers the drop-in files next to it, the
+    // profile for the Wi-Fi network `ssid` and the environment overrides on
+    // top
+    pub fn read(config_path: &Path, ssid: Option<&str>) -> Result<Self> {
+        Self::read_with_profile(config_path, ssid, None, std::env::vars())
+    }
+
+    // Like `read`, with the `profile` picked from the tray taking the place
+    // of the one for the network, and the environment `vars` to take the
+    // overrides from
+    pub fn read_with_profile(
+        config_path: &Path,
+        ssid: Option<&str>,
+        profile: Option<&str>,
+        vars: impl Iterator<Item = (String, String)>,
+    ) -> Result<Self> {
+        let config_contents = read_file(config_path)?;
+
+        let mut layers = vec![(
+            config_path.display().to_string(),
+            parse_table(&config_contents, config_path)?,
+        )];
+        for path in get_drop_in_paths(&get_drop_in_dir(config_path))? {
+            let table = parse_table(&read_file(&path)?, &path)?;
+            layers.push((path.display().to_string(), table));
+        }
+        let mut active_profile = None;
+        if let Some((name, table)) = get_network_profile(&layers, ssid, profile)? {
+            if profile == Some(name.as_str()) {
+                info!("Using profile {}", name);
+            } else {
+                info!(
+                    "Using profile {} for network {:?}",
+                    name,
+                    ssid.unwrap_or_default()
+                );
+            }
+            layers.push((format!("profile {}", name), table));
+            active_profile = Some(name);
+        }
+        let env_overrides = get_env_overrides(vars);
+        if !env_overrides.is_empty() {
+            layers.push(("environment".to_string(), env_overrides));
+        }
+
+        let location_provider = layers
+            .iter()
+            .rev()
+            .find(|(_, table)| table.contains_key("latitude") || table.contains_key("longitude"))
+            .map(|(source, _)| source.clone());
+
+        // Parsing the file directly keeps line numbers in the errors
+        let mut config = if layers.len() == 1 {
+            Self::parse(&config_contents)?
+        } else {
+            let mut layers = layers.into_iter().map(|(_, table)| table);
+            let mut table = layers.next().unwrap_or_default();
+            for layer in layers {
+                merge_tables(&mut table, layer);
+            }
+
+            let config: Config = match toml::Value::Table(table).try_into() {
+                Ok(config) => config,
+                Err(e) => return Err(e).context("Failed to parse merged config"),
+            };
+            config.validate()?;
+            config
+        };
+
+        if let Some(location_provider) = location_provider {
+            config.location_provider = location_provider;
+        }
+        config.active_profile = active_profile;
+
+        config.resolve_city()?;
+
+        Ok(config)
+    }
+
+    // Errors from the TOML parser include the line and field
+    pub fn parse(config_contents: &str) -> Result<Self> {
+        let config: Config = match toml::from_str(config_contents) {
+            Ok(config) => config,
+            Err(e) => return Err(e).context("Failed to parse config file"),
+        };
+
+        config.validate()?;
+        Ok(config)
+    }
+
+    fn resolve_city(&mut self) -> Result<()> {
+        let city = match &self.city {
+            Some(city) => find_city(city)?,
+            None => return Ok(()),
+        };
+
+        self.latitude = city.latitude;
+        self.longitude = city.longitude;
+        self.altitude = city.elevation;
+        self.location_provider = format!("city {}", city.get_full_name());
+
+        Ok(())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Err(e) = self.schedule.validate() {
+            return Err(e).context("Invalid schedule");
+        }
+
+        if StrftimeItems::new(&self.log_time_format).any(|item| item == Item::Error) {
+            return Err(anyhow!(
+                "invalid log_time_format \"{}\"",
+                self.log_time_format
+            ));
+        }
+
+        if !(MIN_ALTITUDE..=MAX_ALTITUDE).contains(&self.altitude) {
+            return Err(anyhow!(
+                "altitude {}m is not between {}m and {}m. Add \"ft\" for feet, e.g. \"5280ft\"",
+                self.altitude,
+                MIN_ALTITUDE,
+                MAX_ALTITUDE
+            ));
+        }
+        if self.altitude > SUSPICIOUS_ALTITUDE {
+            warn!(
+                "altitude {}m is higher than any city. If that is in feet, set altitude = \"{}ft\"",
+                self.altitude, self.altitude
+            );
+        }
+
+        if !self.exclude_monitors.is_empty() {
+            return Err(anyhow!(
+                "exclude_monitors is not supported, hyprsunset and the gamma command apply the temperature to every monitor"
+            ));
+        }
+
+        if self.min_temperature > self.max_temperature {
+            return Err(anyhow!(
+                "min_temperature ({}) is greater than max_temperature ({})",
+                self.min_temperature,
+                self.max_temperature
+            ));
+        }
+
+        Ok(())
+    }
+
+    // Where the coordinates came from, for `hyprsunset-overdrive where`
+    pub fn get_location_provider(&self) -> &str {
+        &self.location_provider
+    }
+
+    // Still the shipped coordinates of Nairobi, most likely never set
+    pub fn has_default_location(&self) -> bool {
+        let default = Config::default();
+        self.city.is_none()
+            && !self.follow_timezone
+            && self.latitude == default.latitude
+            && self.longitude == default.longitude
+    }
+
+    pub fn clamp_temperature(&self, temperature: i32) -> i32 {
+        temperature.clamp(self.min_temperature, self.max_temperature)
+    }
+}
+
+#[test]
+fn test_clamp_temperature() {
+    let config: Config = toml::from_str(
+        r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0
+min_temperature = 2500
+max_temperature = 6000"#,
+    )
+    .unwrap();
+
+    assert_eq!(config.clamp_temperature(1000), 2500);
+    assert_eq!(config.clamp_temperature(3200), 3200);
+    assert_eq!(config.clamp_temperature(6500), 6000);
+}
+
+#[test]
+fn test_partial_config() {
+    let config: Config = toml::from_str("temperature = 2800").unwrap();
+
+    assert_eq!(config.temperature, 2800);
+    assert_eq!(config.latitude, Config::default().latitude);
+    assert_eq!(config.max_temperature, 20000);
+
+    let config: Config = toml::from_str("").unwrap();
+    assert_eq!(config.temperature, 3000);
+}
+
+#[test]
+fn test_wake_time_config() {
+    let config: Config = toml::from_str(
+        r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0
+wake_time = "06:30""#,
+    )
+    .unwrap();
+
+    assert_eq!(config.wake_time, NaiveTime::from_hms_opt(6, 30, 0));
+    assert_eq!(config.wake_fade_minutes, 30);
+
+    assert!(
+        toml::from_str::<Config>(
+            r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0
+wake_time = "half past six""#,
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_parse_config() {
+    let error = Config::parse(
+        r#"temperature = 3000
+latitude = 0
+longitude = "east"
+altitude = 0"#,
+    )
+    .unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("line 3"), "{}", message);
+    assert!(message.contains("longitude"), "{}", message);
+
+    assert!(
+        Config::parse(
+            r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0
+min_temperature = 5000
+max_temperature = 4000"#,
+        )
+        .is_err()
+    );
+
+    // A typo would otherwise be silently left at the default
+    let error = Config::parse("latitude = 0\ntemprature = 3000").unwrap_err();
+    let message = format!("{:#}", error);
+    assert!(message.contains("line 2"), "{}", message);
+    assert!(message.contains("temprature"), "{}", message);
+
+    let error = Config::parse("exclude_monitors = [\"DP-2\"]").unwrap_err();
+    assert!(
+        error
+            .to_string()
+            .contains("exclude_monitors is not supported")
+    );
+    assert!(Config::parse("exclude_monitors = []").is_ok());
+}
+
+#[test]
+fn test_merge_tables() {
+    let mut base: Table = toml::from_str(
+        r#"temperature = 3000
+latitude = 1.0
+
+[[schedule.points]]
+time = "22:00"
+temperature = 2800"#,
+    )
+    .unwrap();
+    let overlay: Table = toml::from_str(
+        r#"latitude = 2.0
+schedule = { points = [] }"#,
+    )
+    .unwrap();
+
+    merge_tables(&mut base, overlay);
+    let config: Config = toml::Value::Table(base).try_into().unwrap();
+
+    assert_eq!(config.temperature, 3000);
+    assert_eq!(config.latitude, 2.0);
+    assert!(config.schedule.points.is_empty());
+}
+
+#[test]
+fn test_drop_in_config() {
+    let dir =
+        std::env::temp_dir().join(format!("hyprsunset-overdrive-test-{}", std::process::id()));
+    let config_path = dir.join("hyprsunset-overdrive.toml");
+    let drop_in_dir = get_drop_in_dir(&config_path);
+    fs::create_dir_all(&drop_in_dir).unwrap();
+
+    fs::write(&config_path, "temperature = 3000\nlatitude = 1.0").unwrap();
+    fs::write(drop_in_dir.join("10-location.toml"), "latitude = 2.0").unwrap();
+    fs::write(drop_in_dir.join("20-location.toml"), "latitude = 3.0").unwrap();
+    fs::write(drop_in_dir.join("README"), "not toml").unwrap();
+
+    let config = Config::read_with_profile(&config_path, None, None, std::iter::empty()).unwrap();
+    assert_eq!(config.temperature, 3000);
+    assert_eq!(config.latitude, 3.0);
+
+    fs::write(
+        drop_in_dir.join("30-profiles.toml"),
+        "[profiles.office]\nssid = \"ACME-Corp\"\nlatitude = 4.0",
+    )
+    .unwrap();
+    let config =
+        Config::read_with_profile(&config_path, Some("ACME-Corp"), None, std::iter::empty())
+            .unwrap();
+    assert_eq!(config.latitude, 4.0);
+    assert_eq!(config.get_location_provider(), "profile office");
+    assert_eq!(config.active_profile.as_deref(), Some("office"));
+    let config =
+        Config::read_with_profile(&config_path, Some("Cafe"), None, std::iter::empty()).unwrap();
+    assert_eq!(config.latitude, 3.0);
+
+    fs::write(drop_in_dir.join("40-broken.toml"), "latitude = ").unwrap();
+    let e = Config::read_with_profile(&config_path, None, None, std::iter::empty()).unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<ConfigError>(),
+        Some(ConfigError::Parse { path, .. }) if path.ends_with("40-broken.toml")
+    ));
+    let e = Config::read_with_profile(&dir.join("missing.toml"), None, None, std::iter::empty())
+        .unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<ConfigError>(),
+        Some(ConfigError::Read { .. })
+    ));
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn test_env_overrides() {
+    let vars = [
+        ("HYPRSUNSET_OVERDRIVE_TEMPERATURE", "2800"),
+        ("HYPRSUNSET_OVERDRIVE_LATITUDE", "-1.5"),
+        ("HYPRSUNSET_OVERDRIVE_WAKE_TIME", "06:30"),
+        ("HYPRSUNSET_OVERDRIVE_INVERT_SCHEDULE", "true"),
+        ("HYPRSUNSET_OVERDRIVE_", "ignored"),
+        ("HOME", "/home/user"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+
+    let overrides = get_env_overrides(vars);
+    assert_eq!(overrides.len(), 4);
+
+    let config: Config = toml::Value::Table(overrides).try_into().unwrap();
+    assert_eq!(config.temperature, 2800);
+    assert_eq!(config.latitude, -1.5);
+    assert_eq!(config.wake_time, NaiveTime::from_hms_opt(6, 30, 0));
+    assert!(config.invert_schedule);
+}
+
+#[test]
+fn test_nested_env_overrides() {
+    let vars = [
+        ("HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__MORNING", "false"),
+        ("HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__TIMEOUT_MS", "3000"),
+        (
+            "HYPRSUNSET_OVERDRIVE_SCHEDULE__POINTS",
+            r#"[{ time = "21:00", temperature = 3000 }]"#,
+        ),
+        ("HYPRSUNSET_OVERDRIVE_PROFILES__WORK__TEMPERATURE", "4000"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+
+    let mut table = parse_table("[notifications]\nevening = false", Path::new("config")).unwrap();
+    merge_tables(&mut table, get_env_overrides(vars));
+
+    let config: Config = toml::Value::Table(table).try_into().unwrap();
+    assert!(!config.notifications.evening);
+    assert!(!config.notifications.morning);
+    assert_eq!(config.notifications.timeout_ms, Some(3000));
+    assert_eq!(config.schedule.points.len(), 1);
+    assert_eq!(config.schedule.points[0].temperature, Some(3000));
+    assert_eq!(
+        config.profiles["work"]["temperature"],
+        toml::Value::Integer(4000)
+    );
+}
+
+#[test]
+fn test_unknown_env_overrides() {
+    let vars = [
+        ("HYPRSUNSET_OVERDRIVE_TEMPERATURE", "2800"),
+        ("HYPRSUNSET_OVERDRIVE_TEMPRATURE", "3000"),
+        ("HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__MORNNG", "false"),
+        ("HYPRSUNSET_OVERDRIVE_TEMPERATURE__KELVIN", "3000"),
+        ("HYPRSUNSET_OVERDRIVE_NOTIFICATIONS__", "false"),
+        ("HYPRSUNSET_OVERDRIVE_LOCATION_PROVIDER", "environment"),
+    ]
+    .into_iter()
+    .map(|(name, value)| (name.to_string(), value.to_string()));
+
+    // Left out, where they would fail the parse
+    let overrides = get_env_overrides(vars);
+    assert_eq!(overrides.len(), 1);
+
+    let config: Config = toml::Value::Table(overrides).try_into().unwrap();
+    assert_eq!(config.temperature, 2800);
+}
+
+#[test]
+fn test_find_config_path() {
+    let config_home = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-home-{}",
+        std::process::id()
+    ));
+    let [nested, alternative] = get_candidate_paths(&config_home);
+
+    assert_eq!(find_config_path(&config_home), None);
+
+    fs::create_dir_all(alternative.parent().unwrap()).unwrap();
+    fs::write(&alternative, "").unwrap();
+    assert_eq!(find_config_path(&config_home), Some(alternative.clone()));
+
+    // The config nested under hypr/ wins when both exist
+    fs::create_dir_all(nested.parent().unwrap()).unwrap();
+    fs::write(&nested, "").unwrap();
+    assert_eq!(find_config_path(&config_home), Some(nested));
+
+    fs::remove_dir_all(&config_home).unwrap();
+}
+
+#[test]
+fn test_city_config() {
+    let mut config = Config::parse(r#"city = "Berlin""#).unwrap();
+    config.resolve_city().unwrap();
+
+    assert_eq!(config.latitude, 52.52);
+    assert_eq!(config.altitude, 34.0);
+    assert_eq!(config.get_location_provider(), "city Berlin, DE");
+
+    let mut config = Config::parse(r#"city = "Portland""#).unwrap();
+    assert!(config.resolve_city().is_err());
+}
+
+#[test]
+fn test_network_profile() {
+    let layers = [(
+        "config".to_string(),
+        toml::from_str::<Table>(
+            r#"[profiles.office]
+ssid = "ACME-Corp"
+city = "Berlin"
+
+[profiles.home]
+ssid = "Home"
+latitude = 1.0"#,
+        )
+        .unwrap(),
+    )];
+
+    let (name, profile) = get_network_profile(&layers, Some("ACME-Corp"), None)
+        .unwrap()
+        .unwrap();
+    assert_eq!(name, "office");
+    assert!(profile.contains_key("city"));
+    assert!(!profile.contains_key("ssid"));
+    assert!(get_network_profile(&layers, None, None).unwrap().is_none());
+
+    // Picked from the tray, whatever the network
+    let (name, _) = get_network_profile(&layers, Some("ACME-Corp"), Some("home"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(name, "home");
+    let (name, _) = get_network_profile(&layers, Some("ACME-Corp"), Some("gone"))
+        .unwrap()
+        .unwrap();
+    assert_eq!(name, "office");
+
+    let invalid = [(
+        "config".to_string(),
+        toml::from_str::<Table>("[profiles.office]\nlatitude = 1.0").unwrap(),
+    )];
+    assert!(get_network_profile(&invalid, None, None).is_err());
+}
+
+#[test]
+fn test_cycle_config() {
+    let config = Config::parse(r#"cycle = ["identity", 4000, 2700]"#).unwrap();
+    assert_eq!(config.cycle, [None, Some(4000), Some(2700)]);
+
+    assert!(Config::parse(r#"cycle = [4000, "warm"]"#).is_err());
+}
+
+#[test]
+fn test_log_time_format() {
+    assert!(Config::parse(r#"log_time_format = "%H:%M %Z""#).is_ok());
+    assert!(Config::parse(r#"log_time_format = "%H:%Q""#).is_err());
+}
+
+#[test]
+fn test_backend_config() {
+    assert_eq!(Config::default().backend, BackendKind::Auto);
+    assert_eq!(
+        Config::parse(r#"backend = "null""#).unwrap().backend,
+        BackendKind::Null
+    );
+    assert!(Config::parse(r#"backend = "x11""#).is_err());
+}
+
+#[test]
+fn test_has_default_location() {
+    assert!(Config::default().has_default_location());
+    assert!(
+        Config::parse("temperature = 2800")
+            .unwrap()
+            .has_default_location()
+    );
+    assert!(
+        !Config::parse("latitude = 52.52")
+            .unwrap()
+            .has_default_location()
+    );
+    assert!(
+        !Config::parse("follow_timezone = true")
+            .unwrap()
+            .has_default_location()
+    );
+}
+
+#[test]
+fn test_altitude_units() {
+    assert_eq!(parse_altitude("1609"), Some(1609.0));
+    assert_eq!(parse_altitude(" 1609 m"), Some(1609.0));
+    assert!((parse_altitude("5280ft").unwrap() - 1609.344).abs() < 1e-9);
+    assert_eq!(parse_altitude("high"), None);
+
+    assert_eq!(Config::parse("altitude = 34").unwrap().altitude, 34.0);
+    assert!((Config::parse(r#"altitude = "100ft""#).unwrap().altitude - 30.48).abs() < 1e-9);
+    assert!(Config::parse(r#"altitude = "100yd""#).is_err());
+    // Feet entered as meters, from the top of Mount Everest
+    assert!(Config::parse("altitude = 29032").is_err());
+    assert!(Config::parse(r#"altitude = "29032ft""#).is_ok());
+}
+
+#[test]
+fn test_coordinate_config() {
+    let config = Config::parse(
+        r#"latitude = "52°31'12\"N"
+longitude = 13.405"#,
+    )
+    .unwrap();
+    assert!((config.latitude - 52.52).abs() < 1e-9);
+    assert_eq!(config.longitude, 13.405);
+
+    assert!(Config::parse(r#"latitude = "52°31'E""#).is_err());
+    assert!(Config::parse("longitude = 200").is_err());
+}
+
+#[test]
+fn test_on_exit_config() {
+    assert_eq!(Config::default().on_exit, ExitState::Keep);
+    assert_eq!(
+        Config::parse(r#"on_exit = "identity""#).unwrap().on_exit,
+        ExitState::Identity
+    );
+    assert!(Config::parse(r#"on_exit = "off""#).is_err());
+}
+
+#[test]
+fn test_day_mode_config() {
+    let parse = |config| Config::parse(config).map(|config| config.day_temperature);
+
+    assert_eq!(parse("day_temperature = 5500").unwrap(), Some(5500));
+    assert_eq!(
+        parse("day_mode = { temperature = 6500 }").unwrap(),
+        Some(6500)
+    );
+    assert_eq!(parse(r#"day_mode = "identity""#).unwrap(), None);
+    assert!(parse(r#"day_mode = "dim""#).is_err());
+    assert!(parse("day_mode = { kelvin = 6500 }").is_err());
+    // Both at once would be ambiguous
+    assert!(parse("day_temperature = 5500\nday_mode = { temperature = 6500 }").is_err());
+}
+
+#[test]
+fn test_dir_overrides() {
+    let config = Config::parse(r#"state_dir = "/persist/hyprsunset-overdrive""#).unwrap();
+    assert_eq!(
+        config.state_dir,
+        Some(PathBuf::from("/persist/hyprsunset-overdrive"))
+    );
+    assert_eq!(config.runtime_dir, None);
+    assert_eq!(config.log_dir, None);
+}
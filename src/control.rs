@@ -0,0 +1,1038 @@
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::Shutdown;
+use std::os::fd::AsRawFd;
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc::{Sender, channel};
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, NaiveDateTime, NaiveTime, TimeZone, Utc};
+use hyprsunset_overdrive::error::IpcError;
+use tracing::{debug, error, info, info_span};
+
+use crate::IDENTITY_TEMPERATURE;
+use crate::events::Event;
+use crate::paths;
+use crate::schedule::format_temperature;
+use crate::supervisor::{self, Worker};
+
+#[derive(Debug, PartialEq)]
+pub enum TemperatureChange {
+    Set(i32),
+    // Relative to the currently applied temperature
+    Adjust(i32),
+}
+
+// Requests accepted on the control socket. Each connection carries a single
+// line with the request and receives the reply before the socket is closed,
+// except for `inhibit`, which lasts until the client closes it, and
+// `subscribe`, which is answered with events until then.
+#[derive(Debug, PartialEq)]
+pub enum Request {
+    // `None` queries the current value without changing it
+    Automatic(Option<bool>),
+    ToggleAutomatic,
+    Day,
+    Night,
+    // Flips between day and night until the next transition
+    Toggle,
+    Temperature(Option<TemperatureChange>),
+    // The next temperature in the configured `cycle`
+    Cycle,
+    Astro(Option<bool>),
+    ToggleAstro,
+    Reading(Option<bool>),
+    ToggleReading,
+    Schedule,
+    // The next transition only
+    Next,
+    // Location and today's solar data
+    Where,
+    // Applies a temperature for a while, then restores the current state
+    Preview(i32, Duration),
+    // Ends the current preview early
+    PreviewOff,
+    // `None` queries whether the winding down temperature is applied
+    WindDown(Option<bool>),
+    // From the locker's unlock hook, e.g. hypridle's `on_unlock_cmd`
+    Unlocked,
+    // Forwarded to hyprsunset as is, for features not wrapped yet
+    Raw(String),
+    // The current state, or what the schedule picks at `at`
+    Status {
+        at: Option<DateTime<Utc>>,
+        json: bool,
+    },
+    // The current state as a single `status_format` line
+    StatusLine,
+    // The JSON events in the given version of the protocol
+    Subscribe(u32),
+    // Keeps the filter off for as long as the connection stays open. `id`
+    // and `caller` are filled in by the control thread, which sees it
+    // closing and knows who is on the other end.
+    Inhibit {
+        id: u64,
+        caller: String,
+        reason: Option<String>,
+    },
+}
+
+impl Request {
+    // Whether the request changes the state, in a way a later one replaces,
+    // so it may be held back by `min_command_interval_ms`. Queries aren't,
+    // and neither are inhibitors, re-asserting after unlocking and raw
+    // commands, which must each go through on their own.
+    pub fn is_change(&self) -> bool {
+        match self {
+            Request::Automatic(enabled)
+            | Request::Astro(enabled)
+            | Request::Reading(enabled)
+            | Request::WindDown(enabled) => enabled.is_some(),
+            Request::Temperature(change) => change.is_some(),
+            Request::ToggleAutomatic
+            | Request::Day
+            | Request::Night
+            | Request::Toggle
+            | Request::Cycle
+            | Request::ToggleAstro
+            | Request::ToggleReading
+            | Request::Preview(..)
+            | Request::PreviewOff => true,
+            Request::Inhibit { .. }
+            | Request::Unlocked
+            | Request::Raw(_)
+            | Request::Schedule
+            | Request::Next
+            | Request::Where
+            | Request::Status { .. }
+            | Request::StatusLine
+            | Request::Subscribe(_) => false,
+        }
+    }
+}
+
+// A field in the reply to `status`
+#[derive(Debug, Clone, PartialEq)]
+pub enum StatusValue {
+    Text(String),
+    Number(u64),
+    Flag(bool),
+    // `None` is identity
+    Temperature(Option<i32>),
+    // In degrees
+    Angle(f64),
+    // Who holds an inhibitor, and why
+    Inhibitors(Vec<(String, Option<String>)>),
+    Missing,
+}
+
+// "darktable (color grading), gimp"
+pub fn format_inhibitors(inhibitors: &[(String, Option<String>)]) -> String {
+    inhibitors
+        .iter()
+        .map(|(caller, reason)| match reason {
+            Some(reason) => format!("{} ({})", caller, reason),
+            None => caller.clone(),
+        })
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+fn escape_json(text: &str) -> String {
+    let mut escaped = String::new();
+    for c in text.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+
+    escaped
+}
+
+// One "name: value" line per field, or a flat JSON object for scripts
+pub fn format_status(fields: &[(&str, StatusValue)], json: bool) -> String {
+    if json {
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|(name, value)| {
+                let value = match value {
+                    StatusValue::Text(text) => format!("\"{}\"", escape_json(text)),
+                    StatusValue::Number(number) => number.to_string(),
+                    StatusValue::Flag(flag) => flag.to_string(),
+                    StatusValue::Temperature(Some(temperature)) => temperature.to_string(),
+                    // NaN and infinity aren't JSON numbers
+                    StatusValue::Angle(angle) if !angle.is_finite() => "null".to_string(),
+                    StatusValue::Angle(angle) => format!("{:.1}", angle),
+                    StatusValue::Inhibitors(inhibitors) => {
+                        let inhibitors: Vec<String> = inhibitors
+                            .iter()
+                            .map(|(caller, reason)| {
+                                let reason = match reason {
+                                    Some(reason) => format!("\"{}\"", escape_json(reason)),
+                                    None => "null".to_string(),
+                                };
+                                format!(
+                                    "{{\"caller\":\"{}\",\"reason\":{}}}",
+                                    escape_json(caller),
+                                    reason
+                                )
+                            })
+                            .collect();
+                        format!("[{}]", inhibitors.join(","))
+                    }
+                    StatusValue::Temperature(None) | StatusValue::Missing => "null".to_string(),
+                };
+                format!("\"{}\":{}", name, value)
+            })
+            .collect();
+
+        return format!("{{{}}}", fields.join(","));
+    }
+
+    fields
+        .iter()
+        .map(|(name, value)| {
+            let value = match value {
+                StatusValue::Text(text) => text.clone(),
+                StatusValue::Number(number) => number.to_string(),
+                StatusValue::Flag(true) => "yes".to_string(),
+                StatusValue::Flag(false) => "no".to_string(),
+                StatusValue::Temperature(temperature) => format_temperature(*temperature),
+                StatusValue::Angle(angle) if !angle.is_finite() => "none".to_string(),
+                StatusValue::Angle(angle) => format!("{:.1}°", angle),
+                StatusValue::Inhibitors(inhibitors) if inhibitors.is_empty() => "none".to_string(),
+                StatusValue::Inhibitors(inhibitors) => format_inhibitors(inhibitors),
+                StatusValue::Missing => "none".to_string(),
+            };
+            format!("{}: {}", name.replace('_', " "), value)
+        })
+        .collect::<Vec<String>>()
+        .join("\n")
+}
+
+// Fills in the placeholders of `status_format`: `{icon}`, `{mode}` (night or
+// day), `{temp}` (in Kelvin, the identity temperature when off) and `{until}`
+// (the time of the next transition, empty without one)
+pub fn format_status_line(template: &str, temperature: Option<i32>, until: Option<&str>) -> String {
+    let (icon, mode) = match temperature {
+        Some(_) => ("☾", "night"),
+        None => ("☀", "day"),
+    };
+
+    template
+        .replace("{icon}", icon)
+        .replace("{mode}", mode)
+        .replace(
+            "{temp}",
+            &temperature.unwrap_or(IDENTITY_TEMPERATURE).to_string(),
+        )
+        .replace("{until}", until.unwrap_or_default())
+}
+
+// How long `preview` shows a temperature without `--seconds`
+const DEFAULT_PREVIEW_DURATION: Duration = Duration::from_secs(5);
+
+fn parse_temperature_change(arg: &str) -> Result<TemperatureChange> {
+    let value = match arg.parse::<i32>() {
+        Ok(value) => value,
+        Err(_) => return Err(anyhow!("Invalid temperature: {}", arg)),
+    };
+
+    if arg.starts_with('+') || arg.starts_with('-') {
+        Ok(TemperatureChange::Adjust(value))
+    } else {
+        Ok(TemperatureChange::Set(value))
+    }
+}
+
+fn parse_preview(temperature: &str, seconds: Option<&str>) -> Result<Request> {
+    let temperature = match temperature.parse::<i32>() {
+        Ok(temperature) => temperature,
+        Err(_) => return Err(anyhow!("Invalid temperature: {}", temperature)),
+    };
+
+    let duration = match seconds.map(str::parse::<u64>) {
+        Some(Ok(seconds)) if seconds > 0 => Duration::from_secs(seconds),
+        Some(_) => return Err(anyhow!("Invalid duration: {}", seconds.unwrap_or_default())),
+        None => DEFAULT_PREVIEW_DURATION,
+    };
+
+    Ok(Request::Preview(temperature, duration))
+}
+
+// A local "YYYY-MM-DD HH:MM", or "HH:MM" for today
+fn parse_local_time(args: &[&str]) -> Result<DateTime<Utc>> {
+    let joined = args.join(" ");
+    let local = match args {
+        [time] => NaiveTime::parse_from_str(time, "%H:%M")
+            .map(|time| Local::now().date_naive().and_time(time)),
+        _ => NaiveDateTime::parse_from_str(&joined, "%Y-%m-%d %H:%M"),
+    };
+
+    let local = match local {
+        Ok(local) => local,
+        Err(_) => {
+            return Err(anyhow!(
+                "Invalid time \"{}\", expected \"YYYY-MM-DD HH:MM\" or \"HH:MM\"",
+                joined
+            ));
+        }
+    };
+
+    match Local.from_local_datetime(&local).earliest() {
+        Some(local) => Ok(local.with_timezone(&Utc)),
+        None => Err(anyhow!("{} does not exist in the local timezone", joined)),
+    }
+}
+
+// `--json` can go anywhere, `--at` takes the rest of the arguments
+fn parse_status(options: &[&str]) -> Result<Request> {
+    if options == ["--format"] {
+        return Ok(Request::StatusLine);
+    }
+
+    let json = options.contains(&"--json");
+    let options: Vec<&str> = options
+        .iter()
+        .copied()
+        .filter(|option| *option != "--json")
+        .collect();
+
+    let at = match options.as_slice() {
+        [] => None,
+        ["--at", time @ ..] if !time.is_empty() => Some(parse_local_time(time)?),
+        _ => {
+            return Err(anyhow!(
+                "Usage: status [--json] [--at TIME] | status --format"
+            ));
+        }
+    };
+
+    Ok(Request::Status { at, json })
+}
+
+fn parse_request(line: &str) -> Result<Request> {
+    let args: Vec<&str> = line.split_whitespace().collect();
+
+    match args.as_slice() {
+        ["auto"] => Ok(Request::Automatic(None)),
+        ["auto", "on"] => Ok(Request::Automatic(Some(true))),
+        ["auto", "off"] => Ok(Request::Automatic(Some(false))),
+        ["auto", "toggle"] => Ok(Request::ToggleAutomatic),
+        ["day"] => Ok(Request::Day),
+        ["night"] => Ok(Request::Night),
+        ["toggle"] => Ok(Request::Toggle),
+        ["cycle"] => Ok(Request::Cycle),
+        ["astro"] => Ok(Request::Astro(None)),
+        ["astro", "on"] => Ok(Request::Astro(Some(true))),
+        ["astro", "off"] => Ok(Request::Astro(Some(false))),
+        ["astro", "toggle"] => Ok(Request::ToggleAstro),
+        ["reading"] => Ok(Request::Reading(None)),
+        ["reading", "on"] => Ok(Request::Reading(Some(true))),
+        ["reading", "off"] => Ok(Request::Reading(Some(false))),
+        ["reading", "toggle"] => Ok(Request::ToggleReading),
+        ["schedule"] => Ok(Request::Schedule),
+        ["next"] => Ok(Request::Next),
+        ["where"] => Ok(Request::Where),
+        // Spelled out for scripts
+        ["temp"] | ["get", "temperature"] => Ok(Request::Temperature(None)),
+        ["temp", value] => Ok(Request::Temperature(Some(parse_temperature_change(value)?))),
+        ["preview", "off"] => Ok(Request::PreviewOff),
+        ["raw", command @ ..] if !command.is_empty() => Ok(Request::Raw(command.join(" "))),
+        ["subscribe", version] => match version.parse() {
+            Ok(version) => Ok(Request::Subscribe(version)),
+            Err(_) => Err(anyhow!("Invalid protocol version: {}", version)),
+        },
+        ["subscribe"] => Err(anyhow!("Usage: subscribe VERSION")),
+        ["inhibit", reason @ ..] => Ok(Request::Inhibit {
+            id: 0,
+            caller: String::new(),
+            reason: (!reason.is_empty()).then(|| reason.join(" ")),
+        }),
+        ["wind-down"] => Ok(Request::WindDown(None)),
+        ["wind-down", "on"] => Ok(Request::WindDown(Some(true))),
+        ["wind-down", "off"] => Ok(Request::WindDown(Some(false))),
+        ["unlocked"] => Ok(Request::Unlocked),
+        ["status", options @ ..] => parse_status(options),
+        ["preview", temperature] => parse_preview(temperature, None),
+        ["preview", temperature, "--seconds", seconds] => parse_preview(temperature, Some(seconds)),
+        [] => Err(anyhow!("Empty command")),
+        _ => Err(anyhow!("Unknown command: {}", line.trim())),
+    }
+}
+
+// How long a client of the control socket gets to send its command and to
+// take the reply
+const CLIENT_TIMEOUT: Duration = Duration::from_secs(5);
+
+// The version of the JSON events that `subscribe` speaks. Fields are only
+// ever added within a version, anything else gets a new one.
+pub const PROTOCOL_VERSION: u32 = 1;
+
+// The first line for a subscriber, e.g.
+// {"event":"hello","protocol":1,"version":"0.4.3"}
+pub fn format_hello() -> String {
+    format_status(
+        &[
+            ("event", StatusValue::Text("hello".to_string())),
+            ("protocol", StatusValue::Number(PROTOCOL_VERSION as u64)),
+            (
+                "version",
+                StatusValue::Text(env!("CARGO_PKG_VERSION").to_string()),
+            ),
+        ],
+        true,
+    )
+}
+
+// A `status --json` object with `"event":"status"` in front
+pub fn format_status_event(mut fields: Vec<(&str, StatusValue)>) -> String {
+    fields.insert(0, ("event", StatusValue::Text("status".to_string())));
+    format_status(&fields, true)
+}
+
+// Tells the inhibitors apart, so the main loop knows which one was released
+static NEXT_INHIBITOR: AtomicU64 = AtomicU64::new(1);
+
+// `/proc/<pid>/comm` of `inhibit`, truncated to 15 characters
+const PROCESS_NAME: &str = "hyprsunset-over";
+
+pub fn get_control_socket_path() -> Result<PathBuf> {
+    Ok(paths::get_runtime_dir()?.join("hyprsunset-overdrive.sock"))
+}
+
+fn handle_connection(stream: UnixStream, main_tx: &Sender<Event>) -> Result<()> {
+    // A client that never sends its command or never reads the reply would
+    // otherwise block every other client
+    if let Err(e) = stream
+        .set_read_timeout(Some(CLIENT_TIMEOUT))
+        .and_then(|_| stream.set_write_timeout(Some(CLIENT_TIMEOUT)))
+    {
+        return Err(e).context("Failed to set timeouts");
+    };
+
+    let mut line = String::new();
+    let mut reader = BufReader::new(&stream);
+    if let Err(e) = reader.read_line(&mut line) {
+        return Err(e).context("Failed to read control command");
+    };
+
+    let _span = info_span!("command", command = line.trim()).entered();
+    debug!("Received control command");
+
+    if let Ok(Request::Subscribe(version)) = parse_request(&line) {
+        return subscribe(stream, version, main_tx);
+    }
+
+    let mut inhibitor = None;
+    let reply = match parse_request(&line) {
+        Ok(mut request) => {
+            if let Request::Inhibit { id, caller, .. } = &mut request {
+                *id = NEXT_INHIBITOR.fetch_add(1, Ordering::Relaxed);
+                *caller = get_peer_pid(&stream)
+                    .and_then(get_inhibit_caller)
+                    .unwrap_or_else(|| "unknown".to_string());
+                inhibitor = Some(*id);
+            }
+            let (reply_tx, reply_rx) = channel::<String>();
+            let event = Event::IpcRequest {
+                request,
+                reply: reply_tx,
+            };
+            if main_tx.send(event).is_err() {
+                return Err(anyhow!("Main loop is not running"));
+            };
+            match reply_rx.recv_timeout(Duration::from_secs(5)) {
+                Ok(reply) => reply,
+                Err(_) => "error: no reply from daemon".to_string(),
+            }
+        }
+        Err(e) => format!("error: {}", e),
+    };
+
+    let mut writer = &stream;
+    let result = match writer.write_all(format!("{}\n", reply).as_bytes()) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e).context("Failed to write control reply"),
+    };
+
+    // Even after an error, since the main loop may have taken it after all.
+    // Releasing an inhibitor it doesn't know does nothing.
+    if let Some(id) = inhibitor {
+        hold_inhibitor(stream, id, main_tx.clone());
+    }
+
+    result
+}
+
+// Hands the connection over to a thread that writes the events from the
+// main loop, so the control thread can go back to other clients
+fn subscribe(stream: UnixStream, version: u32, main_tx: &Sender<Event>) -> Result<()> {
+    let mut writer = &stream;
+    if version != PROTOCOL_VERSION {
+        let reply = format!(
+            "error: Unsupported protocol version {}, this daemon speaks {}\n",
+            version, PROTOCOL_VERSION
+        );
+        return match writer.write_all(reply.as_bytes()) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context("Failed to write control reply"),
+        };
+    }
+
+    let (events_tx, events_rx) = channel::<String>();
+    if main_tx.send(Event::Subscribe(events_tx)).is_err() {
+        return Err(anyhow!("Main loop is not running"));
+    };
+
+    thread::spawn(move || {
+        // A widget that stops reading runs into the write timeout and is
+        // dropped, instead of piling up events
+        let mut writer = &stream;
+        for event in events_rx {
+            if let Err(e) = writer.write_all(format!("{}\n", event).as_bytes()) {
+                debug!("Subscriber went away: {}", e);
+                break;
+            }
+        }
+    });
+
+    Ok(())
+}
+
+// `subscribe`: prints the JSON events as they come, e.g. for eww's
+// `deflisten`
+pub fn run_subscribe() -> Result<()> {
+    let socket_path = get_control_socket_path()?;
+    let mut sock = match UnixStream::connect(&socket_path) {
+        Ok(sock) => sock,
+        Err(e) => return Err(IpcError::NotRunning(e).into()),
+    };
+    if let Err(e) = sock.write_all(format!("subscribe {}\n", PROTOCOL_VERSION).as_bytes()) {
+        return Err(IpcError::Io {
+            action: "send command",
+            source: e,
+        }
+        .into());
+    };
+
+    for line in BufReader::new(&sock).lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                return Err(IpcError::Io {
+                    action: "read events",
+                    source: e,
+                }
+                .into());
+            }
+        };
+        if let Some(message) = line.strip_prefix("error: ") {
+            return Err(IpcError::Rejected(message.to_string()).into());
+        }
+        println!("{}", line);
+    }
+
+    Ok(())
+}
+
+// Holds the inhibitor until the client closes the connection or exits, like
+// the file descriptor of a logind inhibitor
+fn hold_inhibitor(stream: UnixStream, id: u64, main_tx: Sender<Event>) {
+    thread::spawn(move || {
+        // Anything the client sends after the request is ignored
+        if stream.set_read_timeout(None).is_ok() {
+            let _ = io::copy(&mut &stream, &mut io::sink());
+        }
+        // The main loop may be gone already
+        let _ = main_tx.send(Event::InhibitReleased(id));
+    });
+}
+
+// The process on the other end of the connection, as the kernel saw it
+// connecting
+fn get_peer_pid(stream: &UnixStream) -> Option<u32> {
+    let mut credentials = libc::ucred {
+        pid: 0,
+        uid: 0,
+        gid: 0,
+    };
+    let mut len = std::mem::size_of::<libc::ucred>() as libc::socklen_t;
+    // Both pointers are to locals that outlive the call
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            libc::SO_PEERCRED,
+            &mut credentials as *mut libc::ucred as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    (result == 0 && credentials.pid > 0).then_some(credentials.pid as u32)
+}
+
+fn read_comm(pid: u32) -> Option<String> {
+    let comm = fs::read_to_string(format!("/proc/{}/comm", pid)).ok()?;
+    let comm = comm.trim();
+    (!comm.is_empty()).then(|| comm.to_string())
+}
+
+// The fourth field of /proc/PID/stat, after the name in parentheses, which
+// may contain spaces itself
+fn get_parent_pid(pid: u32) -> Option<u32> {
+    let stat = fs::read_to_string(format!("/proc/{}/stat", pid)).ok()?;
+    let (_, fields) = stat.rsplit_once(')')?;
+    fields.split_whitespace().nth(1)?.parse().ok()
+}
+
+// The program after `--` in the arguments of `inhibit`
+fn get_inhibit_command(args: &[String]) -> Option<String> {
+    let i = args.iter().position(|arg| arg == "--")?;
+    let program = Path::new(args.get(i + 1)?).file_name()?;
+    Some(program.to_string_lossy().into_owned())
+}
+
+// Who holds an inhibitor, for `status`. When it is `inhibit` itself, that is
+// the command it runs, or the program that ran it otherwise, e.g. a script.
+fn get_inhibit_caller(pid: u32) -> Option<String> {
+    let comm = read_comm(pid)?;
+    if comm != PROCESS_NAME {
+        return Some(comm);
+    }
+
+    let cmdline = fs::read(format!("/proc/{}/cmdline", pid)).ok()?;
+    let args: Vec<String> = cmdline
+        .split(|&byte| byte == 0)
+        .map(|arg| String::from_utf8_lossy(arg).into_owned())
+        .collect();
+
+    match get_inhibit_command(&args) {
+        Some(command) => Some(command),
+        None => read_comm(get_parent_pid(pid)?),
+    }
+}
+
+// `inhibit [REASON] [-- COMMAND...]` keeps the filter off until COMMAND
+// exits, or until interrupted without one, the way `systemd-inhibit` does.
+// Returns the exit code to leave with.
+pub fn run_inhibit(args: &[String]) -> Result<i32> {
+    let (reason, command) = match args.iter().position(|arg| arg == "--") {
+        Some(i) => (&args[..i], Some(&args[i + 1..])),
+        None => (args, None),
+    };
+    if command.is_some_and(|command| command.is_empty()) {
+        return Err(anyhow!("Usage: inhibit [REASON] [-- COMMAND...]"));
+    }
+
+    // Who is asking is looked up by the daemon
+    let mut request = "inhibit".to_string();
+    if !reason.is_empty() {
+        request = format!("{} {}", request, reason.join(" "));
+    }
+
+    let socket_path = get_control_socket_path()?;
+    let mut sock = match UnixStream::connect(&socket_path) {
+        Ok(sock) => sock,
+        Err(e) => return Err(IpcError::NotRunning(e).into()),
+    };
+    if let Err(e) = sock.write_all(format!("{}\n", request).as_bytes()) {
+        return Err(IpcError::Io {
+            action: "send command",
+            source: e,
+        }
+        .into());
+    };
+
+    let mut reply = String::new();
+    if let Err(e) = BufReader::new(&sock).read_line(&mut reply) {
+        return Err(IpcError::Io {
+            action: "read reply",
+            source: e,
+        }
+        .into());
+    };
+    if let Some(message) = reply.trim_end().strip_prefix("error: ") {
+        return Err(IpcError::Rejected(message.to_string()).into());
+    }
+    eprintln!("{}", reply.trim_end());
+
+    let Some([program, args @ ..]) = command else {
+        // Closing the connection on exit is what releases it
+        loop {
+            thread::park();
+        }
+    };
+    match Command::new(program).args(args).status() {
+        Ok(status) => Ok(status.code().unwrap_or(1)),
+        Err(e) => Err(e).context(format!("Failed to run {}", program)),
+    }
+}
+
+pub fn setup_control_socket(main_tx: Sender<Event>) -> Result<PathBuf> {
+    let socket_path = get_control_socket_path()?;
+
+    // We hold the instance lock at this point, so any existing socket was
+    // left behind by a previous instance that did not exit cleanly.
+    if socket_path.exists() {
+        fs::remove_file(&socket_path).context("Failed to remove stale control socket")?;
+    }
+
+    let listener = match UnixListener::bind(&socket_path) {
+        Ok(listener) => listener,
+        Err(e) => {
+            return Err(e).context(format!(
+                "Failed to bind control socket at: {:?}",
+                socket_path
+            ));
+        }
+    };
+
+    info!("Control socket listening at: {:?}", socket_path);
+
+    supervisor::spawn(Worker::Control, main_tx.clone(), move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => {
+                    if let Err(e) = handle_connection(stream, &main_tx) {
+                        error!("Failed to handle control command: {}", e);
+                    }
+                }
+                Err(e) => error!("Failed to accept control connection: {}", e),
+            }
+        }
+    });
+
+    Ok(socket_path)
+}
+
+pub fn send_control_command(command: &str) -> Result<String> {
+    let socket_path = get_control_socket_path()?;
+
+    let mut sock = match UnixStream::connect(&socket_path) {
+        Ok(sock) => sock,
+        Err(e) => return Err(IpcError::NotRunning(e).into()),
+    };
+
+    if let Err(e) = sock.set_read_timeout(Some(Duration::from_secs(10))) {
+        return Err(IpcError::Io {
+            action: "set read timeout",
+            source: e,
+        }
+        .into());
+    };
+
+    if let Err(e) = sock.write_all(format!("{}\n", command).as_bytes()) {
+        return Err(IpcError::Io {
+            action: "send command",
+            source: e,
+        }
+        .into());
+    };
+
+    if let Err(e) = sock.shutdown(Shutdown::Write) {
+        return Err(IpcError::Io {
+            action: "send command",
+            source: e,
+        }
+        .into());
+    };
+
+    let mut reply = String::new();
+    if let Err(e) = sock.read_to_string(&mut reply) {
+        return Err(IpcError::Io {
+            action: "read reply",
+            source: e,
+        }
+        .into());
+    };
+
+    match reply.trim_end().strip_prefix("error: ") {
+        Some(message) => Err(IpcError::Rejected(message.to_string()).into()),
+        None => Ok(reply.trim_end().to_string()),
+    }
+}
+
+#[test]
+fn test_parse_inhibit() {
+    assert_eq!(
+        parse_request("inhibit recording a talk\n").unwrap(),
+        Request::Inhibit {
+            id: 0,
+            caller: String::new(),
+            reason: Some("recording a talk".to_string()),
+        }
+    );
+    assert_eq!(
+        parse_request("inhibit").unwrap(),
+        Request::Inhibit {
+            id: 0,
+            caller: String::new(),
+            reason: None,
+        }
+    );
+
+    let args = [
+        "hyprsunset-overdrive",
+        "inhibit",
+        "color grading",
+        "--",
+        "/usr/bin/darktable",
+        "photo.raw",
+    ];
+    let args: Vec<String> = args.iter().map(|arg| arg.to_string()).collect();
+    assert_eq!(get_inhibit_command(&args), Some("darktable".to_string()));
+    assert_eq!(get_inhibit_command(&args[..3]), None);
+
+    // Both ends of a pair belong to this process
+    let (stream, _) = UnixStream::pair().unwrap();
+    assert_eq!(get_peer_pid(&stream), Some(std::process::id()));
+    assert_eq!(
+        get_parent_pid(std::process::id()),
+        Some(std::os::unix::process::parent_id())
+    );
+}
+
+#[test]
+fn test_parse_request() {
+    assert_eq!(parse_request("auto\n").unwrap(), Request::Automatic(None));
+    assert_eq!(
+        parse_request("auto off").unwrap(),
+        Request::Automatic(Some(false))
+    );
+    assert_eq!(
+        parse_request("auto toggle").unwrap(),
+        Request::ToggleAutomatic
+    );
+    assert_eq!(parse_request(" night ").unwrap(), Request::Night);
+    assert_eq!(parse_request("toggle").unwrap(), Request::Toggle);
+    assert_eq!(parse_request("cycle").unwrap(), Request::Cycle);
+    assert_eq!(parse_request("next").unwrap(), Request::Next);
+    assert_eq!(
+        parse_request("astro on").unwrap(),
+        Request::Astro(Some(true))
+    );
+    assert_eq!(
+        parse_request("reading toggle").unwrap(),
+        Request::ToggleReading
+    );
+    assert_eq!(
+        parse_request("wind-down off").unwrap(),
+        Request::WindDown(Some(false))
+    );
+    assert_eq!(parse_request("unlocked").unwrap(), Request::Unlocked);
+    assert_eq!(
+        parse_request("raw gamma 80").unwrap(),
+        Request::Raw("gamma 80".to_string())
+    );
+    assert!(parse_request("raw").is_err());
+    assert!(parse_request("").is_err());
+    assert!(parse_request("auto maybe").is_err());
+}
+
+#[test]
+fn test_parse_temperature_request() {
+    assert_eq!(parse_request("temp").unwrap(), Request::Temperature(None));
+    assert_eq!(
+        parse_request("get temperature").unwrap(),
+        Request::Temperature(None)
+    );
+    assert_eq!(
+        parse_request("temp 3500").unwrap(),
+        Request::Temperature(Some(TemperatureChange::Set(3500)))
+    );
+    assert_eq!(
+        parse_request("temp +200").unwrap(),
+        Request::Temperature(Some(TemperatureChange::Adjust(200)))
+    );
+    assert_eq!(
+        parse_request("temp -200").unwrap(),
+        Request::Temperature(Some(TemperatureChange::Adjust(-200)))
+    );
+    assert!(parse_request("temp warm").is_err());
+}
+
+#[test]
+fn test_parse_preview_request() {
+    assert_eq!(
+        parse_request("preview 2700").unwrap(),
+        Request::Preview(2700, DEFAULT_PREVIEW_DURATION)
+    );
+    assert_eq!(
+        parse_request("preview 2700 --seconds 10").unwrap(),
+        Request::Preview(2700, Duration::from_secs(10))
+    );
+    assert_eq!(parse_request("preview off").unwrap(), Request::PreviewOff);
+    assert!(parse_request("preview 2700 --seconds 0").is_err());
+    assert!(parse_request("preview warm").is_err());
+}
+
+#[test]
+fn test_parse_status_request() {
+    assert_eq!(
+        parse_request("status").unwrap(),
+        Request::Status {
+            at: None,
+            json: false
+        }
+    );
+    assert_eq!(
+        parse_request("status --json").unwrap(),
+        Request::Status {
+            at: None,
+            json: true
+        }
+    );
+
+    let expected = Local
+        .from_local_datetime(
+            &NaiveDateTime::parse_from_str("2025-06-21 23:00", "%Y-%m-%d %H:%M").unwrap(),
+        )
+        .earliest()
+        .unwrap()
+        .with_timezone(&Utc);
+    assert_eq!(
+        parse_request("status --json --at 2025-06-21 23:00").unwrap(),
+        Request::Status {
+            at: Some(expected),
+            json: true
+        }
+    );
+    assert!(matches!(
+        parse_request("status --at 23:00").unwrap(),
+        Request::Status { at: Some(_), .. }
+    ));
+    assert!(parse_request("status --at tonight").is_err());
+    assert!(parse_request("status --at").is_err());
+}
+
+#[test]
+fn test_format_status() {
+    let fields = [
+        ("automatic", StatusValue::Flag(true)),
+        ("temperature", StatusValue::Temperature(Some(3000))),
+        ("failed_commands", StatusValue::Number(2)),
+        ("sun_elevation", StatusValue::Angle(-4.26)),
+        (
+            "last_error",
+            StatusValue::Text("Failed to \"connect\"".to_string()),
+        ),
+        ("last_success", StatusValue::Missing),
+    ];
+
+    assert_eq!(
+        format_status(&fields, false),
+        "automatic: yes\ntemperature: 3000K\nfailed commands: 2\nsun elevation: -4.3°\nlast error: Failed to \"connect\"\nlast success: none"
+    );
+    assert_eq!(
+        format_status(&fields, true),
+        r#"{"automatic":true,"temperature":3000,"failed_commands":2,"sun_elevation":-4.3,"last_error":"Failed to \"connect\"","last_success":null}"#
+    );
+
+    let fields = [
+        ("sun_elevation", StatusValue::Angle(f64::NAN)),
+        ("azimuth", StatusValue::Angle(f64::INFINITY)),
+    ];
+    assert_eq!(
+        format_status(&fields, false),
+        "sun elevation: none\nazimuth: none"
+    );
+    assert_eq!(
+        format_status(&fields, true),
+        r#"{"sun_elevation":null,"azimuth":null}"#
+    );
+
+    let inhibitors = StatusValue::Inhibitors(vec![
+        ("darktable".to_string(), Some("color grading".to_string())),
+        ("gimp".to_string(), None),
+    ]);
+    let fields = [("inhibitors", inhibitors)];
+    assert_eq!(
+        format_status(&fields, false),
+        "inhibitors: darktable (color grading), gimp"
+    );
+    assert_eq!(
+        format_status(&fields, true),
+        r#"{"inhibitors":[{"caller":"darktable","reason":"color grading"},{"caller":"gimp","reason":null}]}"#
+    );
+    assert_eq!(
+        format_status(&[("inhibitors", StatusValue::Inhibitors(vec![]))], true),
+        r#"{"inhibitors":[]}"#
+    );
+}
+
+#[test]
+fn test_format_status_line() {
+    assert_eq!(
+        format_status_line("{icon} {temp}K ({until})", Some(3000), Some("06:41")),
+        "☾ 3000K (06:41)"
+    );
+    assert_eq!(
+        format_status_line("{mode} {temp}K{until}", None, None),
+        format!("day {}K", IDENTITY_TEMPERATURE)
+    );
+    assert_eq!(
+        parse_request("status --format").unwrap(),
+        Request::StatusLine
+    );
+}
+
+#[test]
+fn test_request_is_change() {
+    let is_change = |command: &str| parse_request(command).unwrap().is_change();
+
+    assert!(is_change("toggle"));
+    assert!(is_change("temp +100"));
+    assert!(is_change("astro on"));
+    assert!(!is_change("astro"));
+    assert!(!is_change("temp"));
+    assert!(!is_change("status --json"));
+    assert!(!is_change("inhibit recording"));
+    assert!(!is_change("unlocked"));
+    assert!(!is_change("raw gamma 80"));
+}
+
+#[test]
+fn test_events() {
+    assert_eq!(parse_request("subscribe 1").unwrap(), Request::Subscribe(1));
+    assert!(parse_request("subscribe").is_err());
+    assert!(parse_request("subscribe latest").is_err());
+
+    assert_eq!(
+        format_hello(),
+        format!(
+            "{{\"event\":\"hello\",\"protocol\":1,\"version\":\"{}\"}}",
+            env!("CARGO_PKG_VERSION")
+        )
+    );
+    assert_eq!(
+        format_status_event(vec![
+            ("night", StatusValue::Flag(true)),
+            ("temperature", StatusValue::Temperature(Some(3000))),
+        ]),
+        r#"{"event":"status","night":true,"temperature":3000}"#
+    );
+}
+
+#[test]
+fn test_hold_inhibitor() {
+    let (client, server) = UnixStream::pair().unwrap();
+    let (main_tx, main_rx) = channel::<Event>();
+    hold_inhibitor(server, 7, main_tx);
+    assert!(main_rx.recv_timeout(Duration::from_millis(100)).is_err());
+
+    drop(client);
+    assert!(matches!(
+        main_rx.recv_timeout(Duration::from_secs(5)),
+        Ok(Event::InhibitReleased(7))
+    ));
+}
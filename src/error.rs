@@ -0,0 +1,73 @@
+//! Errors returned at the library boundary, grouped by what failed.
+//!
+//! The daemon reports errors through `anyhow`, with these at the bottom of
+//! the chain, so they can be matched with `downcast_ref`.
+
+use std::io;
+use std::path::PathBuf;
+
+/// Any of the failure kinds below.
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    #[error(transparent)]
+    Config(#[from] ConfigError),
+    #[error(transparent)]
+    Backend(#[from] BackendError),
+    #[error(transparent)]
+    Schedule(#[from] ScheduleError),
+    #[error(transparent)]
+    Ipc(#[from] IpcError),
+}
+
+/// The config file couldn't be used.
+#[derive(Debug, thiserror::Error)]
+pub enum ConfigError {
+    #[error("Failed to read {path:?}")]
+    Read {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    /// `message` is the parser's, with the line and field.
+    #[error("Failed to parse {path:?}: {message}")]
+    Parse { path: PathBuf, message: String },
+}
+
+/// hyprsunset, or whichever backend sets the temperature, couldn't be reached.
+#[derive(Debug, thiserror::Error)]
+pub enum BackendError {
+    #[error("Failed to connect to socket at: {path:?}")]
+    Connect {
+        path: PathBuf,
+        #[source]
+        source: io::Error,
+    },
+    #[error("Failed to send command to hyprsunset")]
+    Send(#[source] io::Error),
+    #[error("Failed to read reply from hyprsunset")]
+    Reply(#[source] io::Error),
+}
+
+/// The sun's times couldn't be computed.
+#[derive(Debug, Clone, PartialEq, thiserror::Error)]
+pub enum ScheduleError {
+    #[error("Invalid coordinates {latitude}, {longitude}")]
+    InvalidCoordinates { latitude: f64, longitude: f64 },
+}
+
+/// The running daemon couldn't be asked over the control socket.
+#[derive(Debug, thiserror::Error)]
+pub enum IpcError {
+    #[error("Failed to connect to hyprsunset-overdrive. Is it running?")]
+    NotRunning(#[source] io::Error),
+    /// `action` is e.g. "send command".
+    #[error("Failed to {action}")]
+    Io {
+        action: &'static str,
+        #[source]
+        source: io::Error,
+    },
+    /// The daemon replied with "error: MESSAGE".
+    #[error("{0}")]
+    Rejected(String),
+}
@@ -0,0 +1,169 @@
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+// Repeats of the same failure are logged at most this often at first, then
+// half as often each time, down to once per `MAX_LOG_INTERVAL`
+const LOG_INTERVAL: Duration = Duration::from_secs(60);
+const MAX_LOG_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+// Keeps track of failures to reach hyprsunset, so a dead socket does not
+// fill up the logs or go unnoticed.
+#[derive(Debug, Default)]
+pub struct ErrorReporter {
+    failing: bool,
+    // Repeats of `last_message` since it was last logged
+    suppressed: u32,
+    last_logged: Option<Instant>,
+    last_message: Option<String>,
+    // Zero until the first failure
+    interval: Duration,
+    stats: ErrorStats,
+}
+
+// Kept for the lifetime of the daemon and shown by `status`
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct ErrorStats {
+    pub failed: u64,
+    // Failures while hyprsunset was already unreachable
+    pub retried: u64,
+    pub last_error: Option<String>,
+    pub last_success: Option<DateTime<Utc>>,
+}
+
+impl ErrorReporter {
+    pub fn is_failing(&self) -> bool {
+        self.failing
+    }
+
+    pub fn get_stats(&self) -> &ErrorStats {
+        &self.stats
+    }
+
+    // Returns true for the first failure in a row
+    pub fn report(&mut self, action: &str, e: &Error) -> bool {
+        self.report_at(action, e, Instant::now())
+    }
+
+    fn report_at(&mut self, action: &str, e: &Error, now: Instant) -> bool {
+        self.stats.failed += 1;
+        if self.failing {
+            self.stats.retried += 1;
+        }
+        let message = format!("Failed to {}: {:#}", action, e);
+        self.stats.last_error = Some(message.clone());
+
+        // A different error is news, so it is logged straight away
+        let repeated = self.last_message.as_ref() == Some(&message);
+        let throttled = match self.last_logged {
+            Some(last_logged) => self.failing && repeated && now - last_logged < self.interval,
+            None => false,
+        };
+
+        if throttled {
+            self.suppressed += 1;
+        } else {
+            match (repeated, self.suppressed) {
+                (_, 0) => error!("{}", message),
+                (true, suppressed) => {
+                    error!("{} (last message repeated {} times)", message, suppressed)
+                }
+                (false, suppressed) => {
+                    info!("Last message repeated {} times", suppressed);
+                    error!("{}", message);
+                }
+            };
+            self.interval = if repeated {
+                (self.interval * 2).min(MAX_LOG_INTERVAL)
+            } else {
+                LOG_INTERVAL
+            };
+            self.suppressed = 0;
+            self.last_logged = Some(now);
+            self.last_message = Some(message);
+        }
+
+        if self.failing {
+            return false;
+        }
+
+        self.failing = true;
+        true
+    }
+
+    // Returns true if the previous attempt failed
+    pub fn succeed(&mut self) -> bool {
+        self.stats.last_success = Some(Utc::now());
+        if !self.failing {
+            return false;
+        }
+
+        match self.suppressed {
+            0 => info!("hyprsunset is reachable again"),
+            suppressed => info!(
+                "hyprsunset is reachable again (last message repeated {} times)",
+                suppressed
+            ),
+        };
+        self.failing = false;
+        self.suppressed = 0;
+        self.last_logged = None;
+        self.last_message = None;
+        true
+    }
+}
+
+#[test]
+fn test_error_reporter() {
+    let mut reporter = ErrorReporter::default();
+    let e = anyhow::anyhow!("connection refused");
+    let start = Instant::now();
+
+    assert!(reporter.report_at("set temperature", &e, start));
+    assert!(reporter.is_failing());
+
+    // Repeats are counted instead of logged
+    assert!(!reporter.report_at("set temperature", &e, start));
+    assert!(!reporter.report_at("set temperature", &e, start));
+    assert_eq!(reporter.suppressed, 2);
+
+    reporter.report_at("set temperature", &e, start + LOG_INTERVAL);
+    assert_eq!(reporter.suppressed, 0);
+
+    assert!(reporter.succeed());
+    assert!(!reporter.is_failing());
+    assert!(!reporter.succeed());
+
+    let stats = reporter.get_stats();
+    assert_eq!(stats.failed, 4);
+    assert_eq!(stats.retried, 3);
+    assert_eq!(
+        stats.last_error.as_deref(),
+        Some("Failed to set temperature: connection refused")
+    );
+    assert!(stats.last_success.is_some());
+}
+
+#[test]
+fn test_error_reporter_backoff() {
+    let mut reporter = ErrorReporter::default();
+    let refused = anyhow::anyhow!("connection refused");
+    let start = Instant::now();
+
+    reporter.report_at("set temperature", &refused, start);
+    assert_eq!(reporter.interval, LOG_INTERVAL);
+
+    // Each time the same error is logged, the next one waits twice as long
+    reporter.report_at("set temperature", &refused, start + LOG_INTERVAL);
+    assert_eq!(reporter.interval, LOG_INTERVAL * 2);
+    reporter.report_at("set temperature", &refused, start + LOG_INTERVAL * 2);
+    assert_eq!(reporter.suppressed, 1);
+
+    // Another error isn't held back
+    let missing = anyhow::anyhow!("no such file or directory");
+    reporter.report_at("set temperature", &missing, start + LOG_INTERVAL * 2);
+    assert_eq!(reporter.suppressed, 0);
+    assert_eq!(reporter.interval, LOG_INTERVAL);
+}
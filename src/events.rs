@@ -0,0 +1,152 @@
+use std::sync::mpsc::Sender;
+
+use chrono::{DateTime, Utc};
+
+use crate::control::Request;
+use crate::supervisor::Worker;
+
+// How far a gradual transition has come
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Progress {
+    pub temperature: i32,
+    // From 0 to 100
+    pub percent: u8,
+}
+
+// Picked from the tray menu
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayAction {
+    Night,
+    Day,
+    ToggleAutomatic,
+    ToggleAstro,
+    ToggleReading,
+    SetLocation,
+    OpenLog,
+    CopyStatus,
+    // Sends the schedule, or what was picked, to hyprsunset again
+    Reapply,
+    // Day until the next sunrise, then automatic mode
+    DisableUntilSunrise,
+    // `None` goes back to picking by network
+    SelectProfile(Option<String>),
+}
+
+impl TrayAction {
+    // Whether the action changes the state, like `Request::is_change`.
+    // "Re-apply now" only sends the same again.
+    pub fn is_change(&self) -> bool {
+        match self {
+            TrayAction::Night
+            | TrayAction::Day
+            | TrayAction::ToggleAutomatic
+            | TrayAction::ToggleAstro
+            | TrayAction::ToggleReading
+            | TrayAction::DisableUntilSunrise
+            | TrayAction::SelectProfile(_) => true,
+            TrayAction::Reapply
+            | TrayAction::SetLocation
+            | TrayAction::OpenLog
+            | TrayAction::CopyStatus => false,
+        }
+    }
+}
+
+// Why the config has to be loaded again
+#[derive(Debug, PartialEq)]
+pub enum ConfigChange {
+    // The new SSID, from the network watcher
+    Network(Option<String>),
+    // The new timezone, from the timezone watcher
+    Timezone(Option<String>),
+    // A location was picked in `setup`
+    Location,
+}
+
+// Everything the main loop reacts to. Each event is handled by
+// `Daemon::handle`, which owns all of the state.
+#[derive(Debug)]
+pub enum Event {
+    // Sent by the scheduler. `None` disables the filter.
+    Solar(Option<i32>),
+    // Sent by the scheduler `countdown_minutes` before the filter turns on
+    // at the given time
+    Countdown(DateTime<Utc>),
+    // "Delay 1h" was clicked on the countdown notification
+    Postpone,
+    // A widget subscribed to the JSON events, which are sent here one line
+    // each until it goes away
+    Subscribe(Sender<String>),
+    // A client of the control socket closed the connection holding this
+    // inhibitor
+    InhibitReleased(u64),
+    // A postponed transition is due, unless the override has changed since
+    OverrideEnded(DateTime<Utc>),
+    // Sent by the scheduler when a transition moves on. `None` once it is
+    // over.
+    Progress(Option<Progress>),
+    Tray(TrayAction),
+    IpcRequest {
+        request: Request,
+        reply: Sender<String>,
+    },
+    // The process was stopped with SIGTSTP and continued with SIGCONT
+    Pause,
+    Resume,
+    // A preview is over and the state it replaced should be restored
+    PreviewEnded(u64),
+    // `min_command_interval_ms` has passed since the last change, so the
+    // one held back can go ahead
+    DebounceEnded,
+    ConfigChanged(ConfigChange),
+    // Time to check that hyprsunset still applies what we asked for
+    Watchdog,
+    // A thread panicked, with the panic message
+    WorkerDied(Worker, String),
+    // The screen locker exited. Some lock and DPMS cycles reset the gamma
+    // tables, so the temperature has to be applied again.
+    Unlocked,
+    // Hyprland added the monitor with this name, which starts out without
+    // the temperature
+    MonitorAdded(String),
+    Shutdown,
+}
+
+// What the tray icon and menu show
+#[derive(Debug, Clone, PartialEq)]
+pub enum TrayUpdate {
+    Night,
+    Day,
+    Automatic(bool),
+    Astro(bool),
+    Reading(bool),
+    // hyprsunset became reachable again, or stopped responding
+    BackendUp,
+    BackendDown,
+    Progress(Option<Progress>),
+    // The `status_format` line, for the tooltip
+    Status(String),
+    // What is applied, for the badge. `None` while the filter is off.
+    Temperature(Option<i32>),
+    // The rows at the top of the menu, e.g. "Sunrise 06:41" and "Sunset
+    // 20:12"
+    Almanac {
+        sunrise: String,
+        sunset: String,
+    },
+    // Why the filter is held back, e.g. "Paused until 06:41", or `None`
+    // when the schedule or a command is in charge
+    Paused(Option<String>),
+    // Whether the config still has the default location
+    LocationUnset(bool),
+    // For the profile submenu: every profile, the one picked and the one
+    // applied
+    Profiles {
+        names: Vec<String>,
+        picked: Option<String>,
+        active: Option<String>,
+    },
+    // Sent by the tray itself when a bar starts after it
+    HostAppeared,
+    Shutdown,
+}
@@ -0,0 +1,275 @@
+use std::process::{Child, Command};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result, anyhow};
+use tracing::{debug, error, info};
+
+// How long a freshly started command gets to fail, e.g. because the
+// compositor lacks the gamma control protocol
+const STARTUP_GRACE: Duration = Duration::from_millis(300);
+
+// Every restart briefly flashes the screen at identity, so the small steps
+// of a gradual transition are applied at most this often, the latest one
+// winning. Bigger changes, such as picking Night, go ahead right away.
+const MIN_RESTART_INTERVAL: Duration = Duration::from_secs(30);
+const SMALL_STEP: i32 = 100;
+
+// What the command should be running with
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Settings {
+    // `None` with the filter off
+    temperature: Option<i32>,
+    // From 0.1 to 1.0
+    brightness: f64,
+}
+
+// Applies the temperature with an external tool outside of Hyprland, e.g.
+// gammastep on sway or river. wlroots resets the gamma tables once the
+// client holding them disconnects, so the tool keeps running for as long as
+// the filter is on. It is started from its own thread, which the main loop
+// only hands the settings to.
+pub struct GammaCommand {
+    template: String,
+    settings: Settings,
+    // `None` once dropped, which stops the thread
+    settings_tx: Option<Sender<Settings>>,
+    thread: Option<JoinHandle<()>>,
+    // Why the command last failed to start, until it starts again
+    failure: Arc<Mutex<Option<String>>>,
+}
+
+// The program and arguments for `template`
+fn get_command_args(template: &str, temperature: i32, brightness: f64) -> Vec<String> {
+    template
+        .split_whitespace()
+        .map(|arg| {
+            arg.replace("{temperature}", &temperature.to_string())
+                .replace("{brightness}", &format!("{:.2}", brightness))
+        })
+        .collect()
+}
+
+fn stop(child: &mut Option<Child>) {
+    let Some(mut child) = child.take() else {
+        return;
+    };
+
+    if let Err(e) = child.kill() {
+        error!("Failed to stop gamma command: {}", e);
+    }
+    let _ = child.wait();
+}
+
+fn start(template: &str, temperature: i32, brightness: f64) -> Result<Child> {
+    let args = get_command_args(template, temperature, brightness);
+    let Some((program, args)) = args.split_first() else {
+        return Err(anyhow!("gamma_command is empty"));
+    };
+
+    let mut child = Command::new(program)
+        .args(args)
+        .spawn()
+        .context(format!("Failed to run {}", program))?;
+
+    thread::sleep(STARTUP_GRACE);
+    if let Some(status) = child.try_wait().context("Failed to check gamma command")? {
+        return Err(anyhow!("{} exited with {}", program, status));
+    }
+
+    info!("Started {} for {}K", program, temperature);
+    Ok(child)
+}
+
+// A change that can wait for `MIN_RESTART_INTERVAL`
+fn is_small_step(running: Settings, settings: Settings) -> bool {
+    running.brightness == settings.brightness
+        && matches!(
+            (running.temperature, settings.temperature),
+            (Some(running), Some(temperature)) if (running - temperature).abs() < SMALL_STEP
+        )
+}
+
+// Runs the command for the latest settings until the channel closes. A
+// command that exited since is started again even with the same settings.
+fn run(template: String, settings_rx: Receiver<Settings>, failure: Arc<Mutex<Option<String>>>) {
+    let mut child: Option<Child> = None;
+    let mut running: Option<Settings> = None;
+    let mut last_start: Option<Instant> = None;
+
+    while let Ok(mut settings) = settings_rx.recv() {
+        while let Ok(newer) = settings_rx.try_recv() {
+            settings = newer;
+        }
+
+        // A small step waits out the rest of the interval, taking whatever
+        // comes in meanwhile
+        loop {
+            let wait = match (last_start, running) {
+                (Some(last_start), Some(running)) if is_small_step(running, settings) => {
+                    (last_start + MIN_RESTART_INTERVAL).checked_duration_since(Instant::now())
+                }
+                _ => None,
+            };
+            let Some(wait) = wait else {
+                break;
+            };
+
+            match settings_rx.recv_timeout(wait) {
+                Ok(newer) => settings = newer,
+                Err(RecvTimeoutError::Timeout) => break,
+                Err(RecvTimeoutError::Disconnected) => {
+                    stop(&mut child);
+                    return;
+                }
+            }
+        }
+
+        let alive = child
+            .as_mut()
+            .is_some_and(|child| matches!(child.try_wait(), Ok(None)));
+        if running == Some(settings) && (alive || settings.temperature.is_none()) {
+            debug!("The gamma command already runs with these settings");
+            continue;
+        }
+
+        stop(&mut child);
+        running = Some(settings);
+        let Some(temperature) = settings.temperature else {
+            last_start = None;
+            continue;
+        };
+
+        last_start = Some(Instant::now());
+        let result = start(&template, temperature, settings.brightness);
+        let mut failure = failure.lock().unwrap_or_else(|e| e.into_inner());
+        match result {
+            Ok(started) => {
+                child = Some(started);
+                *failure = None;
+            }
+            Err(e) => {
+                error!("Failed to start the gamma command: {:#}", e);
+                *failure = Some(format!("{:#}", e));
+                // Tried again with the next settings, even the same
+                running = None;
+            }
+        };
+    }
+
+    stop(&mut child);
+}
+
+impl GammaCommand {
+    pub fn new(template: String) -> Self {
+        Self {
+            template,
+            settings: Settings {
+                temperature: None,
+                brightness: 1.0,
+            },
+            settings_tx: None,
+            thread: None,
+            failure: Arc::new(Mutex::new(None)),
+        }
+    }
+
+    // Hands the settings to the thread, started with the first ones. The
+    // result of starting the command only shows up with the next call.
+    fn apply(&mut self) -> Result<()> {
+        if self.template.split_whitespace().next().is_none() {
+            return Err(anyhow!("gamma_command is empty"));
+        }
+
+        let settings_tx = self.settings_tx.get_or_insert_with(|| {
+            let (settings_tx, settings_rx) = channel();
+            let template = self.template.clone();
+            let failure = self.failure.clone();
+            self.thread = Some(thread::spawn(move || run(template, settings_rx, failure)));
+            settings_tx
+        });
+        if settings_tx.send(self.settings).is_err() {
+            return Err(anyhow!("The gamma command thread is gone"));
+        }
+
+        match self
+            .failure
+            .lock()
+            .unwrap_or_else(|e| e.into_inner())
+            .as_ref()
+        {
+            Some(failure) => Err(anyhow!("{}", failure)),
+            None => Ok(()),
+        }
+    }
+
+    pub fn enable(&mut self, temperature: i32) -> Result<()> {
+        self.settings.temperature = Some(temperature);
+        self.apply()
+    }
+
+    pub fn disable(&mut self) -> Result<()> {
+        self.settings.temperature = None;
+        self.apply()
+    }
+
+    // `gamma` is in percent, like hyprsunset's
+    pub fn set_gamma(&mut self, gamma: i32) -> Result<()> {
+        self.settings.brightness = (gamma as f64 / 100.0).clamp(0.1, 1.0);
+        self.apply()
+    }
+}
+
+// Closing the channel stops the command, which is waited for so it doesn't
+// outlive the daemon
+impl Drop for GammaCommand {
+    fn drop(&mut self) {
+        self.settings_tx = None;
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[test]
+fn test_get_command_args() {
+    assert_eq!(
+        get_command_args(
+            "gammastep -m wayland -O {temperature} -b {brightness}",
+            3000,
+            0.5
+        ),
+        ["gammastep", "-m", "wayland", "-O", "3000", "-b", "0.50"]
+    );
+}
+
+#[test]
+fn test_is_small_step() {
+    let settings = |temperature, brightness| Settings {
+        temperature,
+        brightness,
+    };
+
+    assert!(is_small_step(
+        settings(Some(4000), 1.0),
+        settings(Some(3975), 1.0)
+    ));
+    assert!(!is_small_step(
+        settings(Some(4000), 1.0),
+        settings(Some(3800), 1.0)
+    ));
+    assert!(!is_small_step(
+        settings(Some(4000), 1.0),
+        settings(Some(3975), 0.5)
+    ));
+    assert!(!is_small_step(
+        settings(Some(4000), 1.0),
+        settings(None, 1.0)
+    ));
+    assert!(!is_small_step(
+        settings(None, 1.0),
+        settings(Some(4000), 1.0)
+    ));
+}
@@ -0,0 +1,252 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result, anyhow};
+use chrono::{DateTime, Local, NaiveDate, TimeDelta, TimeZone, Utc};
+
+use crate::config::Config;
+use crate::paths;
+use crate::schedule::format_temperature;
+
+// Older entries are dropped on startup
+const MAX_ENTRIES: usize = 5000;
+
+// Why the temperature changed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Cause {
+    // The scheduler, including schedule points and scripts
+    Schedule,
+    // A command, the tray menu or a toggle
+    Manual,
+    Astro,
+    Reading,
+    WindDown,
+}
+
+impl Cause {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Cause::Schedule => "schedule",
+            Cause::Manual => "manual",
+            Cause::Astro => "astro",
+            Cause::Reading => "reading",
+            Cause::WindDown => "wind-down",
+        }
+    }
+
+    fn parse(cause: &str) -> Option<Self> {
+        match cause {
+            "schedule" => Some(Cause::Schedule),
+            "manual" => Some(Cause::Manual),
+            "astro" => Some(Cause::Astro),
+            "reading" => Some(Cause::Reading),
+            "wind-down" => Some(Cause::WindDown),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+struct Entry {
+    time: DateTime<Utc>,
+    cause: Cause,
+    // `None` is identity
+    temperature: Option<i32>,
+}
+
+fn get_path() -> Result<PathBuf> {
+    Ok(paths::get_state_dir()?.join("history.log"))
+}
+
+// One tab separated line per entry, e.g.
+// "2025-06-21T15:42:00+00:00	schedule	3000"
+fn format_entry(entry: &Entry) -> String {
+    let temperature = match entry.temperature {
+        Some(temperature) => temperature.to_string(),
+        None => "identity".to_string(),
+    };
+
+    format!(
+        "{}\t{}\t{}",
+        entry.time.to_rfc3339(),
+        entry.cause.as_str(),
+        temperature
+    )
+}
+
+fn parse_entry(line: &str) -> Option<Entry> {
+    let mut fields = line.split('\t');
+    let time = DateTime::parse_from_rfc3339(fields.next()?).ok()?;
+    let cause = Cause::parse(fields.next()?)?;
+    let temperature = match fields.next()? {
+        "identity" => None,
+        temperature => Some(temperature.parse().ok()?),
+    };
+
+    Some(Entry {
+        time: time.with_timezone(&Utc),
+        cause,
+        temperature,
+    })
+}
+
+pub fn record(cause: Cause, temperature: Option<i32>) -> Result<()> {
+    let path = get_path()?;
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent).context("Failed to create state directory")?;
+    };
+
+    let entry = Entry {
+        time: Utc::now(),
+        cause,
+        temperature,
+    };
+
+    let mut file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("Failed to open {:?}", path))?;
+    match writeln!(file, "{}", format_entry(&entry)) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e).context(format!("Failed to write to {:?}", path)),
+    }
+}
+
+// Keeps the log from growing forever
+pub fn trim() -> Result<()> {
+    let path = get_path()?;
+    let Ok(contents) = fs::read_to_string(&path) else {
+        return Ok(());
+    };
+
+    let lines: Vec<&str> = contents.lines().collect();
+    if lines.len() <= MAX_ENTRIES {
+        return Ok(());
+    }
+
+    let kept = lines[lines.len() - MAX_ENTRIES..].join("\n") + "\n";
+    match fs::write(&path, kept) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e).context(format!("Failed to write {:?}", path)),
+    }
+}
+
+// "today", "yesterday", a date such as "2025-06-21", or a number of hours or
+// days back such as "6h" or "3d"
+fn parse_since(since: &str, now: DateTime<Local>) -> Result<DateTime<Utc>> {
+    let midnight = |date: NaiveDate| {
+        Local
+            .from_local_datetime(&date.and_time(Default::default()))
+            .earliest()
+            .map(|time| time.with_timezone(&Utc))
+            .ok_or_else(|| anyhow!("Midnight of {} does not exist locally", date))
+    };
+
+    let today = now.date_naive();
+    match since {
+        "today" => return midnight(today),
+        "yesterday" => return midnight(today - TimeDelta::days(1)),
+        _ => {}
+    };
+
+    if let Ok(date) = NaiveDate::parse_from_str(since, "%Y-%m-%d") {
+        return midnight(date);
+    }
+
+    let ago = match since.split_at_checked(since.len().saturating_sub(1)) {
+        Some((hours, "h")) => hours.parse().ok().map(TimeDelta::hours),
+        Some((days, "d")) => days.parse().ok().map(TimeDelta::days),
+        _ => None,
+    };
+    match ago {
+        Some(ago) => Ok(now.with_timezone(&Utc) - ago),
+        None => Err(anyhow!(
+            "Invalid time \"{}\", expected \"today\", \"yesterday\", \"YYYY-MM-DD\", \"6h\" or \"3d\"",
+            since
+        )),
+    }
+}
+
+// `history [--since WHEN]`, today's changes by default
+pub fn print_history(args: &[String]) -> Result<()> {
+    let since = match args {
+        [] => parse_since("today", Local::now())?,
+        [option, since] if option == "--since" => parse_since(since, Local::now())?,
+        _ => return Err(anyhow!("Usage: history [--since WHEN]")),
+    };
+
+    let path = get_path()?;
+    let contents = match fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(_) => return Err(anyhow!("No history yet at {:?}", path)),
+    };
+
+    // Without a config, the defaults are as good a guess as any
+    let time_format = Config::get_path()
+        .and_then(|path| Config::read(&path, None))
+        .unwrap_or_default()
+        .time_format;
+
+    for entry in contents.lines().filter_map(parse_entry) {
+        if entry.time < since {
+            continue;
+        }
+
+        let time = entry.time.with_timezone(&Local);
+        println!(
+            "{} {}  {:<9}  {}",
+            time.format("%Y-%m-%d"),
+            time_format.format(time.time()),
+            entry.cause.as_str(),
+            format_temperature(entry.temperature)
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_entry_round_trip() {
+    let entry = Entry {
+        time: Utc.with_ymd_and_hms(2025, 6, 21, 15, 42, 0).unwrap(),
+        cause: Cause::WindDown,
+        temperature: Some(2000),
+    };
+    let line = format_entry(&entry);
+    assert_eq!(line, "2025-06-21T15:42:00+00:00\twind-down\t2000");
+    assert_eq!(parse_entry(&line), Some(entry));
+
+    assert_eq!(
+        parse_entry("2025-06-21T04:10:00+00:00\tschedule\tidentity").map(|entry| entry.temperature),
+        Some(None)
+    );
+    assert_eq!(parse_entry("garbage"), None);
+}
+
+#[test]
+fn test_parse_since() {
+    let now = Local.with_ymd_and_hms(2025, 6, 21, 15, 0, 0).unwrap();
+
+    assert_eq!(
+        parse_since("yesterday", now).unwrap(),
+        Local
+            .with_ymd_and_hms(2025, 6, 20, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    );
+    assert_eq!(
+        parse_since("6h", now).unwrap(),
+        now.with_timezone(&Utc) - TimeDelta::hours(6)
+    );
+    assert_eq!(
+        parse_since("2025-06-01", now).unwrap(),
+        Local
+            .with_ymd_and_hms(2025, 6, 1, 0, 0, 0)
+            .unwrap()
+            .with_timezone(&Utc)
+    );
+    assert!(parse_since("last week", now).is_err());
+    assert!(parse_since("h", now).is_err());
+}
@@ -0,0 +1,282 @@
+use std::fs::{self, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{Context, Result};
+
+// Icons from `icon_theme_dir`, laid out like a freedesktop icon theme, e.g.
+// DIR/22x22/enabled.png and DIR/48x48/enabled.png. DIR/enabled.png is used
+// when there are no sized ones. With the `svg` feature, DIR/scalable/enabled.svg
+// or DIR/enabled.svg is drawn at the panel's size unless a PNG has exactly
+// that size.
+pub struct IconTheme {
+    dir: PathBuf,
+    // In pixels, the panel's icon size times its scale
+    size: u32,
+}
+
+// The smallest of `sizes` that is at least `target`, so the host only ever
+// scales down, or else the largest
+fn pick_size(sizes: &[u32], target: u32) -> Option<u32> {
+    let larger = sizes.iter().filter(|size| **size >= target).min();
+    larger.or(sizes.iter().max()).copied()
+}
+
+// "48x48" is 48, anything else isn't a size directory
+fn parse_size_dir(name: &str) -> Option<u32> {
+    let (width, height) = name.split_once('x')?;
+    let size = width.parse().ok()?;
+    (height.parse() == Ok(size)).then_some(size)
+}
+
+// The largest monitor scale, from `hyprctl monitors -j`. Panels follow the
+// scale of their monitor, and a sharper icon on the others doesn't hurt.
+fn parse_monitor_scale(json: &str) -> Option<f64> {
+    json.split("\"scale\":")
+        .skip(1)
+        .filter_map(|rest| {
+            let end = rest.find([',', '}']).unwrap_or(rest.len());
+            rest[..end].trim().parse::<f64>().ok()
+        })
+        .reduce(f64::max)
+}
+
+fn get_monitor_scale() -> f64 {
+    Command::new("hyprctl")
+        .args(["monitors", "-j"])
+        .output()
+        .ok()
+        .and_then(|output| parse_monitor_scale(&String::from_utf8_lossy(&output.stdout)))
+        .unwrap_or(1.0)
+}
+
+// RGBA pixels and the width and height
+pub type Pixels = (Vec<u8>, (u32, u32));
+
+#[derive(Debug, PartialEq)]
+enum Source {
+    Png(PathBuf),
+    // Drawn at the size the panel needs
+    Svg(PathBuf),
+}
+
+// With the `png` crate alone, the embedded icons are decoded by build.rs
+// so the binary doesn't need `image`
+fn load_png(path: &Path) -> Result<Pixels> {
+    let file = File::open(path).context(format!("Failed to open icon {:?}", path))?;
+    let mut decoder = png::Decoder::new(BufReader::new(file));
+    // Palettes and 16 bit channels come out as 8 bit gray, gray and alpha,
+    // RGB or RGBA
+    decoder.set_transformations(png::Transformations::normalize_to_color8());
+    let mut reader = decoder
+        .read_info()
+        .context(format!("Failed to load icon {:?}", path))?;
+
+    let mut buf = vec![0; reader.output_buffer_size()];
+    let info = reader
+        .next_frame(&mut buf)
+        .context(format!("Failed to load icon {:?}", path))?;
+    buf.truncate(info.buffer_size());
+
+    Ok((to_rgba(&buf, info.color_type), (info.width, info.height)))
+}
+
+fn to_rgba(pixels: &[u8], color_type: png::ColorType) -> Vec<u8> {
+    match color_type {
+        png::ColorType::Rgba => pixels.to_vec(),
+        png::ColorType::Rgb => pixels
+            .chunks_exact(3)
+            .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], 255])
+            .collect(),
+        png::ColorType::GrayscaleAlpha => pixels
+            .chunks_exact(2)
+            .flat_map(|gray| [gray[0], gray[0], gray[0], gray[1]])
+            .collect(),
+        // Indexed is expanded to RGB by the transformations above
+        png::ColorType::Grayscale | png::ColorType::Indexed => pixels
+            .iter()
+            .flat_map(|gray| [*gray, *gray, *gray, 255])
+            .collect(),
+    }
+}
+
+// Fits the SVG into a `size` square, keeping its aspect ratio
+#[cfg(feature = "svg")]
+fn load_svg(path: &Path, size: u32) -> Result<Pixels> {
+    use anyhow::anyhow;
+    use resvg::{tiny_skia, usvg};
+
+    let data = fs::read(path).context(format!("Failed to read icon {:?}", path))?;
+    let tree = usvg::Tree::from_data(&data, &usvg::Options::default())
+        .context(format!("Failed to parse icon {:?}", path))?;
+
+    let svg_size = tree.size();
+    let scale = size as f32 / svg_size.width().max(svg_size.height());
+    let (width, height) = (
+        ((svg_size.width() * scale).round() as u32).max(1),
+        ((svg_size.height() * scale).round() as u32).max(1),
+    );
+    let Some(mut pixmap) = tiny_skia::Pixmap::new(width, height) else {
+        return Err(anyhow!("Invalid icon size {}x{}", width, height));
+    };
+    resvg::render(
+        &tree,
+        tiny_skia::Transform::from_scale(scale, scale),
+        &mut pixmap.as_mut(),
+    );
+
+    // tiny-skia works on premultiplied alpha, the tray doesn't
+    let rgba = pixmap
+        .pixels()
+        .iter()
+        .flat_map(|pixel| {
+            let color = pixel.demultiply();
+            [color.red(), color.green(), color.blue(), color.alpha()]
+        })
+        .collect();
+
+    Ok((rgba, (width, height)))
+}
+
+#[cfg(not(feature = "svg"))]
+fn load_svg(path: &Path, _size: u32) -> Result<Pixels> {
+    Err(anyhow::anyhow!(
+        "Can't load {:?}, SVG icons need the svg feature",
+        path
+    ))
+}
+
+impl IconTheme {
+    // `icon_size` is the size of tray icons on the panel, before scaling
+    pub fn new(dir: PathBuf, icon_size: u32) -> Self {
+        let size = (icon_size as f64 * get_monitor_scale()).round() as u32;
+        Self { dir, size }
+    }
+
+    // Where the icon `name` is in the size that fits the panel best
+    fn find(&self, name: &str) -> Option<Source> {
+        let file = format!("{}.png", name);
+        let sizes: Vec<u32> = fs::read_dir(&self.dir)
+            .ok()?
+            .filter_map(|entry| entry.ok())
+            .filter(|entry| entry.path().join(&file).is_file())
+            .filter_map(|entry| parse_size_dir(&entry.file_name().to_string_lossy()))
+            .collect();
+        let png = match pick_size(&sizes, self.size) {
+            Some(size) => Some((size, self.dir.join(format!("{0}x{0}", size)).join(&file))),
+            None => Some(self.dir.join(&file))
+                .filter(|path| path.is_file())
+                .map(|path| (0, path)),
+        };
+
+        let svg_file = format!("{}.svg", name);
+        let svg = [
+            self.dir.join("scalable").join(&svg_file),
+            self.dir.join(&svg_file),
+        ]
+        .into_iter()
+        .find(|path| cfg!(feature = "svg") && path.is_file());
+
+        match (png, svg) {
+            (Some((size, png)), _) if size == self.size => Some(Source::Png(png)),
+            (_, Some(svg)) => Some(Source::Svg(svg)),
+            (png, None) => png.map(|(_, png)| Source::Png(png)),
+        }
+    }
+
+    // `None` when the theme doesn't have it, so the embedded one is used
+    pub fn load(&self, name: &str) -> Option<Result<Pixels>> {
+        self.find(name).map(|source| match source {
+            Source::Png(path) => load_png(&path),
+            Source::Svg(path) => load_svg(&path, self.size),
+        })
+    }
+}
+
+#[test]
+fn test_pick_size() {
+    assert_eq!(pick_size(&[16, 22, 48], 24), Some(48));
+    assert_eq!(pick_size(&[16, 22, 48], 22), Some(22));
+    assert_eq!(pick_size(&[16, 22], 36), Some(22));
+    assert_eq!(pick_size(&[], 24), None);
+
+    assert_eq!(parse_size_dir("48x48"), Some(48));
+    assert_eq!(parse_size_dir("48x32"), None);
+    assert_eq!(parse_size_dir("scalable"), None);
+}
+
+#[test]
+fn test_to_rgba() {
+    assert_eq!(
+        to_rgba(&[1, 2, 3, 4, 5, 6], png::ColorType::Rgb),
+        [1, 2, 3, 255, 4, 5, 6, 255]
+    );
+    assert_eq!(
+        to_rgba(&[7, 128], png::ColorType::GrayscaleAlpha),
+        [7, 7, 7, 128]
+    );
+    assert_eq!(to_rgba(&[9], png::ColorType::Grayscale), [9, 9, 9, 255]);
+
+    let (rgba, (width, height)) = load_png(Path::new("assets/enabled.png")).unwrap();
+    assert_eq!(rgba.len(), (width * height * 4) as usize);
+}
+
+#[test]
+fn test_parse_monitor_scale() {
+    let json =
+        r#"[{"id": 0, "name": "eDP-1", "scale": 1.50, "transform": 0}, {"id": 1, "scale": 1.00}]"#;
+    assert_eq!(parse_monitor_scale(json), Some(1.5));
+    assert_eq!(parse_monitor_scale("[]"), None);
+}
+
+#[test]
+fn test_find_icon() {
+    let dir = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-icons-{}",
+        std::process::id()
+    ));
+    for size in ["22x22", "48x48"] {
+        fs::create_dir_all(dir.join(size)).unwrap();
+        fs::write(dir.join(size).join("enabled.png"), "").unwrap();
+    }
+    fs::write(dir.join("disabled.png"), "").unwrap();
+
+    let theme = IconTheme {
+        dir: dir.clone(),
+        size: 24,
+    };
+    assert_eq!(
+        theme.find("enabled"),
+        Some(Source::Png(dir.join("48x48/enabled.png")))
+    );
+    assert_eq!(
+        theme.find("disabled"),
+        Some(Source::Png(dir.join("disabled.png")))
+    );
+    assert_eq!(theme.find("warning"), None);
+    assert!(theme.load("enabled").unwrap().is_err());
+
+    // Only an exact size beats drawing the SVG
+    fs::create_dir_all(dir.join("scalable")).unwrap();
+    fs::write(dir.join("scalable/enabled.svg"), "").unwrap();
+    if cfg!(feature = "svg") {
+        assert_eq!(
+            theme.find("enabled"),
+            Some(Source::Svg(dir.join("scalable/enabled.svg")))
+        );
+        fs::create_dir_all(dir.join("24x24")).unwrap();
+        fs::write(dir.join("24x24/enabled.png"), "").unwrap();
+        assert_eq!(
+            theme.find("enabled"),
+            Some(Source::Png(dir.join("24x24/enabled.png")))
+        );
+    } else {
+        assert_eq!(
+            theme.find("enabled"),
+            Some(Source::Png(dir.join("48x48/enabled.png")))
+        );
+    }
+
+    fs::remove_dir_all(&dir).unwrap();
+}
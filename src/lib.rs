@@ -0,0 +1,20 @@
+//! Solar scheduling primitives used by hyprsunset-overdrive.
+//!
+//! Other tools can use these to follow the exact same sunrise and sunset
+//! schedule as the daemon:
+//!
+//! ```
+//! use chrono::{NaiveDate, Utc};
+//! use hyprsunset_overdrive::solar::{self, PartOfDay};
+//!
+//! let date = NaiveDate::from_ymd_opt(2025, 6, 21).unwrap();
+//! let (sunrise, sunset) = solar::get_sunrise_and_sunset(-1.2921, 36.8219, 1795.0, date).unwrap();
+//!
+//! let now = Utc::now();
+//! if solar::get_part_of_day(now, sunrise, sunset) == PartOfDay::Daytime {
+//!     println!("The filter turns on in {:?}", solar::get_duration_to_next_event(now, sunrise, sunset));
+//! }
+//! ```
+
+pub mod error;
+pub mod solar;
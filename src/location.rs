@@ -0,0 +1,392 @@
+use std::fs;
+use std::path::PathBuf;
+use std::process::Command;
+
+use anyhow::{Context, Result, anyhow};
+use toml::Table;
+use tracing::info;
+
+// name,region,country,latitude,longitude,elevation
+const CITIES: &str = include_str!("../assets/cities.csv");
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct City {
+    pub name: String,
+    // State or province, only filled in where a name is ambiguous within
+    // a country or it is commonly given
+    pub region: Option<String>,
+    pub country: String,
+    pub latitude: f64,
+    pub longitude: f64,
+    // Meters above sea level
+    pub elevation: f64,
+}
+
+impl City {
+    // The most specific name, e.g. "Portland, Oregon, US"
+    pub fn get_full_name(&self) -> String {
+        match &self.region {
+            Some(region) => format!("{}, {}, {}", self.name, region, self.country),
+            None => format!("{}, {}", self.name, self.country),
+        }
+    }
+
+    // Matches "Portland", "Portland, Oregon", "Portland, US" and
+    // "Portland, Oregon, US", ignoring case
+    fn matches(&self, query: &[String]) -> bool {
+        let equals = |a: &str, b: &str| a.eq_ignore_ascii_case(b);
+        let region = self.region.as_deref().unwrap_or_default();
+
+        match query {
+            [name] => equals(name, &self.name),
+            [name, qualifier] => {
+                equals(name, &self.name)
+                    && (equals(qualifier, region) || equals(qualifier, &self.country))
+            }
+            [name, qualifier, country] => {
+                equals(name, &self.name)
+                    && equals(qualifier, region)
+                    && equals(country, &self.country)
+            }
+            _ => false,
+        }
+    }
+}
+
+fn parse_city(line: &str) -> Option<City> {
+    let fields: Vec<&str> = line.split(',').map(str::trim).collect();
+    let [name, region, country, latitude, longitude, elevation] = fields.as_slice() else {
+        return None;
+    };
+
+    Some(City {
+        name: name.to_string(),
+        region: (!region.is_empty()).then(|| region.to_string()),
+        country: country.to_string(),
+        latitude: latitude.parse().ok()?,
+        longitude: longitude.parse().ok()?,
+        elevation: elevation.parse().ok()?,
+    })
+}
+
+pub fn get_cities() -> impl Iterator<Item = City> {
+    CITIES
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .filter_map(parse_city)
+}
+
+pub fn find_city(query: &str) -> Result<City> {
+    let query: Vec<String> = query
+        .split(',')
+        .map(|part| part.trim().to_string())
+        .collect();
+    let mut matches: Vec<City> = get_cities().filter(|city| city.matches(&query)).collect();
+
+    match matches.len() {
+        0 => Err(anyhow!(
+            "Unknown city \"{}\". Set latitude and longitude instead",
+            query.join(", ")
+        )),
+        1 => Ok(matches.remove(0)),
+        _ => Err(anyhow!(
+            "City \"{}\" is ambiguous, use one of: {}",
+            query.join(", "),
+            matches
+                .iter()
+                .map(|city| format!("\"{}\"", city.get_full_name()))
+                .collect::<Vec<String>>()
+                .join(", ")
+        )),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Axis {
+    Latitude,
+    Longitude,
+}
+
+impl Axis {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Axis::Latitude => "latitude",
+            Axis::Longitude => "longitude",
+        }
+    }
+
+    fn get_limit(&self) -> f64 {
+        match self {
+            Axis::Latitude => 90.0,
+            Axis::Longitude => 180.0,
+        }
+    }
+
+    // The positive and negative hemisphere
+    fn get_hemispheres(&self) -> (char, char) {
+        match self {
+            Axis::Latitude => ('N', 'S'),
+            Axis::Longitude => ('E', 'W'),
+        }
+    }
+}
+
+// Degrees, minutes and seconds with an optional hemisphere, as copied from a
+// map, e.g. "52°31'12\"N", "52° 31.2′ N", "N 52 31" or "-1.2921"
+fn parse_degrees(text: &str, axis: Axis) -> Option<f64> {
+    let text = text.trim();
+    let (positive, negative) = axis.get_hemispheres();
+    let is_hemisphere =
+        |c: char| c.eq_ignore_ascii_case(&positive) || c.eq_ignore_ascii_case(&negative);
+
+    let (text, hemisphere) = match (text.chars().next(), text.chars().last()) {
+        (Some(first), _) if is_hemisphere(first) => (&text[first.len_utf8()..], Some(first)),
+        (_, Some(last)) if is_hemisphere(last) => {
+            (&text[..text.len() - last.len_utf8()], Some(last))
+        }
+        _ => (text, None),
+    };
+    let (text, sign) = match text.trim().strip_prefix('-') {
+        Some(rest) => (rest, -1.0),
+        None => (text.trim(), 1.0),
+    };
+
+    let parts: Vec<f64> = text
+        .split(|c: char| !c.is_ascii_digit() && c != '.')
+        .filter(|part| !part.is_empty())
+        .map(|part| part.parse().ok())
+        .collect::<Option<_>>()?;
+    // Anything but the separators, e.g. a stray minus sign or letter
+    let separators = ['°', 'º', '\'', '′', '"', '″', ' '];
+    if text
+        .chars()
+        .any(|c| !c.is_ascii_digit() && c != '.' && !separators.contains(&c))
+    {
+        return None;
+    }
+
+    let degrees = match parts.as_slice() {
+        [degrees] => *degrees,
+        [degrees, minutes] if *minutes < 60.0 => degrees + minutes / 60.0,
+        [degrees, minutes, seconds] if *minutes < 60.0 && *seconds < 60.0 => {
+            degrees + minutes / 60.0 + seconds / 3600.0
+        }
+        _ => return None,
+    };
+
+    match hemisphere {
+        Some(hemisphere) if hemisphere.eq_ignore_ascii_case(&negative) => Some(-sign * degrees),
+        _ => Some(sign * degrees),
+    }
+}
+
+// A latitude or longitude in decimal degrees or as accepted by
+// `parse_degrees`, checked to be on the globe
+pub fn parse_coordinate(text: &str, axis: Axis) -> Result<f64> {
+    let Some(degrees) = parse_degrees(text, axis) else {
+        return Err(anyhow!(
+            "Invalid {} \"{}\", expected e.g. \"{}\" or \"{}\"",
+            axis.as_str(),
+            text.trim(),
+            if axis == Axis::Latitude {
+                "52.52"
+            } else {
+                "13.405"
+            },
+            if axis == Axis::Latitude {
+                "52°31'N"
+            } else {
+                "13°24'E"
+            }
+        ));
+    };
+
+    check_coordinate(degrees, axis)
+}
+
+pub fn check_coordinate(degrees: f64, axis: Axis) -> Result<f64> {
+    let limit = axis.get_limit();
+    // TOML has `nan` and `inf`, and NaN fails every comparison
+    if !degrees.is_finite() || degrees.abs() > limit {
+        return Err(anyhow!(
+            "The {} must be between -{} and {}",
+            axis.as_str(),
+            limit,
+            limit
+        ));
+    }
+
+    Ok(degrees)
+}
+
+// Coordinates are rounded to about a kilometer for the cache
+fn get_cache_key(latitude: f64, longitude: f64) -> String {
+    format!("{:.2},{:.2}", latitude, longitude)
+}
+
+fn get_altitude_cache_path() -> Result<PathBuf> {
+    match dirs::cache_dir() {
+        Some(dir) => Ok(dir.join("hyprsunset-overdrive").join("altitude.toml")),
+        None => Err(anyhow!("Failed to find cache directory")),
+    }
+}
+
+fn read_altitude_cache() -> Table {
+    let cache_path = match get_altitude_cache_path() {
+        Ok(path) => path,
+        Err(_) => return Table::new(),
+    };
+
+    match fs::read_to_string(cache_path) {
+        Ok(contents) => toml::from_str(&contents).unwrap_or_default(),
+        Err(_) => Table::new(),
+    }
+}
+
+fn write_altitude_cache(cache: &Table) -> Result<()> {
+    let cache_path = get_altitude_cache_path()?;
+
+    if let Some(parent) = cache_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create cache directory")?;
+    };
+
+    let contents = match toml::to_string(cache) {
+        Ok(contents) => contents,
+        Err(e) => return Err(e).context("Failed to serialize altitude cache"),
+    };
+
+    match fs::write(&cache_path, contents) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e).context("Failed to write altitude cache"),
+    }
+}
+
+// Picks the elevation out of an Open-Elevation reply such as
+// {"results": [{"latitude": -1.29, "longitude": 36.82, "elevation": 1661.0}]}
+fn parse_elevation_response(response: &str) -> Option<f64> {
+    let (_, rest) = response.split_once("\"elevation\"")?;
+    let value = rest.trim_start().strip_prefix(':')?.trim_start();
+    let end = value
+        .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '-'))
+        .unwrap_or(value.len());
+
+    value[..end].parse().ok()
+}
+
+fn fetch_altitude(latitude: f64, longitude: f64) -> Result<f64> {
+    let url = format!(
+        "https://api.open-elevation.com/api/v1/lookup?locations={},{}",
+        latitude, longitude
+    );
+
+    let output = match Command::new("curl")
+        .args(["--silent", "--fail", "--max-time", "10", &url])
+        .output()
+    {
+        Ok(output) => output,
+        Err(e) => return Err(e).context("Failed to run curl"),
+    };
+
+    if !output.status.success() {
+        return Err(anyhow!(
+            "Elevation lookup failed: curl exited with {}",
+            output.status
+        ));
+    }
+
+    match parse_elevation_response(&String::from_utf8_lossy(&output.stdout)) {
+        Some(altitude) => Ok(altitude),
+        None => Err(anyhow!("Unexpected reply from the elevation service")),
+    }
+}
+
+// Looks up the altitude of the coordinates, once per location. The result
+// is cached so later starts work offline.
+pub fn lookup_altitude(latitude: f64, longitude: f64) -> Result<f64> {
+    let key = get_cache_key(latitude, longitude);
+    let mut cache = read_altitude_cache();
+
+    if let Some(altitude) = cache.get(&key).and_then(|value| value.as_float()) {
+        return Ok(altitude);
+    }
+
+    let altitude = fetch_altitude(latitude, longitude)?;
+    info!("Looked up altitude for {}: {}m", key, altitude);
+
+    cache.insert(key, toml::Value::Float(altitude));
+    write_altitude_cache(&cache)?;
+
+    Ok(altitude)
+}
+
+#[test]
+fn test_parse_elevation_response() {
+    assert_eq!(
+        parse_elevation_response(
+            r#"{"results": [{"latitude": -1.2921, "longitude": 36.8219, "elevation": 1661.0}]}"#
+        ),
+        Some(1661.0)
+    );
+    assert_eq!(
+        parse_elevation_response(r#"{"results":[{"elevation":-2,"latitude":52.37}]}"#),
+        Some(-2.0)
+    );
+    assert_eq!(parse_elevation_response(r#"{"error": "Invalid"}"#), None);
+    assert_eq!(get_cache_key(-1.29211, 36.8219), "-1.29,36.82");
+}
+
+#[test]
+fn test_cities_parse() {
+    let lines = CITIES
+        .lines()
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .count();
+    assert_eq!(get_cities().count(), lines);
+}
+
+#[test]
+fn test_find_city() {
+    let berlin = find_city("berlin").unwrap();
+    assert_eq!(berlin.country, "DE");
+    assert_eq!(berlin.latitude, 52.52);
+
+    assert!(find_city("Portland").is_err());
+    assert_eq!(
+        find_city("Portland, Maine").unwrap().get_full_name(),
+        "Portland, Maine, US"
+    );
+    assert_eq!(find_city("London, GB").unwrap().region, None);
+    assert_eq!(find_city("London, Ontario, CA").unwrap().country, "CA");
+
+    let error = find_city("Atlantis").unwrap_err().to_string();
+    assert!(error.contains("Unknown city"), "{}", error);
+}
+
+#[test]
+fn test_parse_coordinate() {
+    let parse = |text| parse_coordinate(text, Axis::Latitude).unwrap();
+    let close = |a: f64, b: f64| (a - b).abs() < 1e-9;
+
+    assert_eq!(parse("-1.2921"), -1.2921);
+    assert!(close(parse("52°31'N"), 52.0 + 31.0 / 60.0));
+    assert!(close(parse("52° 31′ 12″ N"), 52.52));
+    assert!(close(
+        parse("1°17'31.6\"S"),
+        -(1.0 + 17.0 / 60.0 + 31.6 / 3600.0)
+    ));
+    assert!(close(parse("s 1.2921"), -1.2921));
+    assert!(close(
+        parse_coordinate("13°24'18\"W", Axis::Longitude).unwrap(),
+        -13.405
+    ));
+
+    // Wrong hemisphere, minutes out of range, off the globe
+    assert!(parse_coordinate("13°24'E", Axis::Latitude).is_err());
+    assert!(parse_coordinate("52°61'N", Axis::Latitude).is_err());
+    assert!(parse_coordinate("91", Axis::Latitude).is_err());
+    assert!(parse_coordinate("179°W", Axis::Longitude).is_ok());
+    assert!(parse_coordinate("1-2", Axis::Latitude).is_err());
+    assert!(parse_coordinate("NaN", Axis::Latitude).is_err());
+    assert!(check_coordinate(f64::NAN, Axis::Longitude).is_err());
+    assert!(check_coordinate(f64::INFINITY, Axis::Latitude).is_err());
+}
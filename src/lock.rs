@@ -0,0 +1,100 @@
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use fs2::FileExt;
+use tracing::info;
+
+fn parse_lock_pid(contents: &str) -> Option<u32> {
+    contents.trim().parse().ok()
+}
+
+fn read_lock_pid(file: &mut File) -> Option<u32> {
+    let mut contents = String::new();
+    file.read_to_string(&mut contents).ok()?;
+    parse_lock_pid(&contents)
+}
+
+fn try_lock(lock_path: &Path) -> Result<Option<File>> {
+    // Not truncated on open, as the PID of a running owner must survive
+    let mut lock_file = match OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(false)
+        .open(lock_path)
+    {
+        Ok(file) => file,
+        Err(e) => return Err(e).context("Failed to create lock file"),
+    };
+
+    let previous_pid = read_lock_pid(&mut lock_file);
+
+    if lock_file.try_lock_exclusive().is_err() {
+        return Ok(None);
+    }
+
+    if let Some(pid) = previous_pid
+        && pid != std::process::id()
+    {
+        info!("Recovered lock left behind by PID {}", pid);
+    }
+
+    let write_pid = |file: &mut File| -> std::io::Result<()> {
+        file.set_len(0)?;
+        file.rewind()?;
+        write!(file, "{}", std::process::id())
+    };
+    if let Err(e) = write_pid(&mut lock_file) {
+        return Err(e).context("Failed to write PID to lock file");
+    };
+
+    Ok(Some(lock_file))
+}
+
+// Takes the instance lock and records our PID in it. The kernel releases
+// the lock when its owner exits, so a lock left behind is simply taken over.
+pub fn acquire_lock(lock_path: &Path) -> Result<File> {
+    if let Some(lock_file) = try_lock(lock_path)? {
+        return Ok(lock_file);
+    }
+
+    // The PID is only for the message, whoever holds the lock is running
+    let pid = match File::open(lock_path) {
+        Ok(mut file) => read_lock_pid(&mut file),
+        Err(_) => None,
+    };
+
+    match pid {
+        Some(pid) => Err(anyhow!("Another instance is running (PID {})", pid)),
+        None => Err(anyhow!("Another instance is running")),
+    }
+}
+
+#[test]
+fn test_parse_lock_pid() {
+    assert_eq!(parse_lock_pid("1234\n"), Some(1234));
+    assert_eq!(parse_lock_pid(""), None);
+    assert_eq!(parse_lock_pid("not a pid"), None);
+}
+
+#[test]
+fn test_acquire_lock() {
+    let lock_path = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-{}.lock",
+        std::process::id()
+    ));
+
+    // Left behind by an instance that is no longer running
+    std::fs::write(&lock_path, "4194305").unwrap();
+
+    let lock_file = acquire_lock(&lock_path).unwrap();
+    assert_eq!(
+        std::fs::read_to_string(&lock_path).unwrap(),
+        std::process::id().to_string()
+    );
+
+    drop(lock_file);
+    std::fs::remove_file(&lock_path).unwrap();
+}
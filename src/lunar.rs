@@ -0,0 +1,35 @@
+use std::f64::consts::PI;
+
+use chrono::{DateTime, TimeZone, Utc};
+
+// Average length of a lunar cycle in days
+const SYNODIC_MONTH: f64 = 29.530588853;
+
+// How far the night temperature moves at full and new moon
+pub const MAX_OFFSET: i32 = 200;
+
+// Fraction of the moon that is lit, from 0.0 at new moon to 1.0 at full moon.
+// Counts whole cycles from a known new moon, which is off by less than a day.
+pub fn get_illumination(now: DateTime<Utc>) -> f64 {
+    let new_moon = Utc.with_ymd_and_hms(2000, 1, 6, 18, 14, 0).unwrap();
+    let days = (now - new_moon).num_seconds() as f64 / 86400.0;
+    let phase = days.rem_euclid(SYNODIC_MONTH) / SYNODIC_MONTH;
+
+    (1.0 - (2.0 * PI * phase).cos()) / 2.0
+}
+
+// Added to the night temperature: a touch cooler at full moon, warmer at new
+// moon
+pub fn get_offset(now: DateTime<Utc>) -> i32 {
+    ((get_illumination(now) - 0.5) * 2.0 * MAX_OFFSET as f64).round() as i32
+}
+
+#[test]
+fn test_get_offset() {
+    // Full moon on 2025-06-11 07:44 UTC, new moon on 2025-06-25 10:31 UTC
+    let full = Utc.with_ymd_and_hms(2025, 6, 11, 7, 44, 0).unwrap();
+    assert!(get_offset(full) > MAX_OFFSET - 5);
+
+    let new = Utc.with_ymd_and_hms(2025, 6, 25, 10, 31, 0).unwrap();
+    assert!(get_offset(new) < -MAX_OFFSET + 5);
+}
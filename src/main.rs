@@ -1,137 +1,434 @@
+use std::collections::HashMap;
 use std::fs::{self, File};
-use std::io::Write;
+use std::io::{Read, Write};
 use std::os::unix::net::UnixStream;
 use std::path::{Path, PathBuf};
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::result::Result::{Err, Ok};
-use std::sync::mpsc::{Sender, channel};
-use std::{str::FromStr, thread, time::Duration};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{RecvTimeoutError, Sender, channel};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use std::{str::FromStr, thread};
 
 use anyhow::{Context, Result, anyhow};
-use chrono::{Datelike, NaiveDate, NaiveTime, Utc};
-use fs2::FileExt;
-use log::{error, info};
-use serde::Deserialize;
-use signal_hook::consts::{SIGINT, SIGTERM};
+use chrono::{
+    DateTime, Datelike, FixedOffset, Local, NaiveDate, NaiveTime, Offset, TimeDelta, TimeZone, Utc,
+    Weekday,
+};
+use signal_hook::consts::{SIGCONT, SIGINT, SIGTERM, SIGTSTP};
 use signal_hook::iterator::Signals;
-use simplelog::{
-    ColorChoice, CombinedLogger, Config as LogConfig, LevelFilter, TermLogger, TerminalMode,
-    WriteLogger,
+use signal_hook::low_level::emulate_default_handler;
+use tracing::{debug, error, info, info_span, warn};
+use tracing_subscriber::EnvFilter;
+use tracing_subscriber::fmt::format::Writer;
+use tracing_subscriber::fmt::time::FormatTime;
+use tracing_subscriber::prelude::*;
+
+use config::{BackendKind, Config, ExitState};
+use control::{
+    Request, StatusValue, TemperatureChange, format_hello, format_inhibitors, format_status,
+    format_status_event, format_status_line,
 };
-use sunrise::{Coordinates, SolarDay, SolarEvent};
-use tray_icon::Icon;
-
-const ENABLED_ICON_BYTES: &[u8] = include_bytes!("../assets/enabled.png");
-const DISABLED_ICON_BYTES: &[u8] = include_bytes!("../assets/disabled.png");
+use error_reporter::ErrorReporter;
+use events::{ConfigChange, Event, TrayAction, TrayUpdate};
+use gamma::GammaCommand;
+use history::Cause;
+use hyprsunset_overdrive::error::BackendError;
+use hyprsunset_overdrive::solar::{self, PartOfDay, get_civil_twilight, get_solar_elevation};
+use icon_theme::IconTheme;
+use lock::acquire_lock;
+use network::spawn_network_watcher;
+use notification::{Notification, get_transition_message, notify, notify_with_action};
+use null_backend::NullBackend;
+use schedule::{
+    ResolvedPoint, TimeFormat, format_duration, format_temperature, get_local_time_as_utc,
+    get_next_point, get_seconds_between,
+};
+use scheduler::spawn_scheduler;
+use session::{spawn_monitor_watcher, spawn_unlock_watcher};
+use state::{Mode, State};
+use supervisor::Worker;
+use timezone::{describe_clock_change, spawn_timezone_watcher};
+use tray::{TrayHandle, is_tray_host_running, setup_tray_icon};
+
+mod calibrate;
+mod config;
+mod control;
+mod error_reporter;
+mod events;
+mod gamma;
+mod history;
+mod icon_theme;
+mod location;
+mod lock;
+mod lunar;
+mod network;
+mod notification;
+mod null_backend;
+mod paths;
+mod schedule;
+mod scheduler;
+#[cfg(feature = "scripting")]
+mod script;
+mod session;
+mod setup;
+mod state;
+mod supervisor;
+mod timezone;
+mod tray;
+
+// What hyprsunset's `identity` corresponds to. Used as the starting point
+// when adjusting the temperature relative to a disabled filter.
+const IDENTITY_TEMPERATURE: i32 = 6500;
+
+// How often the current state is sent again while hyprsunset is unreachable
+const RETRY_INTERVAL: Duration = Duration::from_secs(10);
+
+// Threads that panicked are restarted until this many died, then the
+// daemon shuts down instead of limping along
+const MAX_WORKER_FAILURES: u64 = 5;
+
+// How many seconds `wait_for_tray_host` waits for a bar at most
+const TRAY_HOST_TRIES: u32 = 30;
+
+// For every read from and write to hyprsunset's socket
+const HYPRSUNSET_TIMEOUT: Duration = Duration::from_millis(500);
+
+// How often the temperature is read back from hyprsunset, in case another
+// tool reset it
+const WATCHDOG_INTERVAL: Duration = Duration::from_secs(10 * 60);
+
+// In `log_dir`
+const LOG_FILE_NAME: &str = "hyprsunset-overdrive.log";
+
+// How often the log file is looked for, to start it again after it was
+// removed or couldn't be created
+const LOG_FILE_CHECK_INTERVAL: Duration = Duration::from_secs(5);
+
+// How far "Delay 1h" on the countdown notification puts off the evening
+const POSTPONE_DURATION: TimeDelta = TimeDelta::hours(1);
 
-#[derive(Debug, Deserialize)]
-struct Config {
-    temperature: i32,
+fn get_sunrise_and_sunset(
     latitude: f64,
     longitude: f64,
     altitude: f64,
+    year: i32,
+    month: u32,
+    day: u32,
+) -> (NaiveTime, NaiveTime) {
+    let times = NaiveDate::from_ymd_opt(year, month, day)
+        .ok_or_else(|| anyhow!("Invalid date {}-{}-{}", year, month, day))
+        .and_then(|date| {
+            Ok(solar::get_sunrise_and_sunset(
+                latitude, longitude, altitude, date,
+            )?)
+        });
+
+    match times {
+        Ok((sunrise, sunset)) => (sunrise.time(), sunset.time()),
+        // The config checks the coordinates, so this shouldn't happen. The
+        // times at the equator keep the schedule going.
+        Err(e) => {
+            error!("Failed to get sunrise and sunset: {:#}", e);
+            (
+                NaiveTime::from_hms_opt(6, 0, 0).unwrap(),
+                NaiveTime::from_hms_opt(18, 0, 0).unwrap(),
+            )
+        }
+    }
 }
 
-impl Config {
-    fn load() -> Result<Self> {
-        let config_path = match dirs::config_dir() {
-            Some(dir) => dir.join("hypr").join("hyprsunset-overdrive.toml"),
-            None => {
-                return Err(anyhow!("Failed to find config directory"));
-            }
-        };
+// The schedule works on UTC times of day, so pin them to a single day to
+// compare them with the library functions
+fn pin_to_day(time: NaiveTime) -> DateTime<Utc> {
+    NaiveDate::default().and_time(time).and_utc()
+}
 
-        if !config_path.exists() {
-            if let Some(parent) = config_path.parent() {
-                std::fs::create_dir_all(parent).context("Failed to create config directory")?;
-            };
+fn get_sunset_delay(weekday: Weekday, weekend_delay_minutes: u32) -> TimeDelta {
+    match weekday {
+        Weekday::Fri | Weekday::Sat => TimeDelta::minutes(weekend_delay_minutes as i64),
+        _ => TimeDelta::zero(),
+    }
+}
 
-            let default_config = r#"temperature = 3000
-# Coordinates for Nairobi, Kenya
-latitude = -1.2921
-longitude = 36.8219
-# Altitude of Nairobi, Kenya in meters. You can set it as 0.
-altitude = 1795
-            "#;
-
-            match fs::write(&config_path, default_config) {
-                Ok(_) => info!("Created default config file"),
-                Err(e) => return Err(e).context("Failed to create default config file"),
-            };
-        }
+// Sunrise and sunset for the day of `now`, with the configured adjustments
+// applied.
+fn get_solar_times(config: &Config, now: DateTime<Utc>) -> (NaiveTime, NaiveTime) {
+    let (sunrise, sunset) = get_sunrise_and_sunset(
+        config.latitude,
+        config.longitude,
+        config.altitude,
+        now.year(),
+        now.month(),
+        now.day(),
+    );
 
-        let config_contents = match fs::read_to_string(&config_path) {
-            Ok(config_str) => config_str,
-            Err(_) => return Err(anyhow!("Failed to read config file")),
-        };
+    let delay = get_sunset_delay(
+        now.with_timezone(&Local).weekday(),
+        config.weekend_sunset_delay_minutes,
+    );
+
+    // The schedule works on a single day, so don't let sunset wrap around
+    // past midnight.
+    let sunset = match sunset.overflowing_add_signed(delay) {
+        (sunset, 0) => sunset,
+        _ => NaiveTime::from_str("23:59:59").unwrap(),
+    };
+
+    (sunrise, sunset)
+}
+
+fn get_part_of_day(time: NaiveTime, sunrise: NaiveTime, sunset: NaiveTime) -> PartOfDay {
+    solar::get_part_of_day(pin_to_day(time), pin_to_day(sunrise), pin_to_day(sunset))
+}
 
-        let config: Config = match toml::from_str(&config_contents) {
-            Ok(config) => config,
-            Err(_) => return Err(anyhow!("Failed to parse config file")),
+// Start of the wake-up fade as a UTC time of day, so it can be compared
+// with the sunrise and sunset times.
+fn get_wake_fade_start(config: &Config, now: DateTime<Utc>) -> Option<NaiveTime> {
+    let wake = get_local_time_as_utc(config.wake_time?, now)?;
+
+    Some(wake - TimeDelta::minutes(config.wake_fade_minutes as i64))
+}
+
+// Temperature during the wake-up window, which runs from the start of the
+// fade until the filter would normally be disabled. `None` if the fade has
+// not started since the filter was enabled at `warm_start`.
+fn get_wake_temperature(
+    time: NaiveTime,
+    warm_start: NaiveTime,
+    fade_start: NaiveTime,
+    fade_duration: Duration,
+    night_temperature: i32,
+) -> Option<i32> {
+    let since_fade_start = get_seconds_between(fade_start, time);
+    if since_fade_start >= get_seconds_between(warm_start, time) {
+        return None;
+    }
+
+    let fade_secs = fade_duration.as_secs() as i64;
+    if since_fade_start >= fade_secs {
+        return Some(IDENTITY_TEMPERATURE);
+    }
+
+    let progress = since_fade_start as f64 / fade_secs as f64;
+    let delta = (IDENTITY_TEMPERATURE - night_temperature) as f64 * progress;
+    Some(night_temperature + delta.round() as i32)
+}
+
+// The configured night temperature, moved with the moon phase in lunar mode
+fn get_night_temperature(config: &Config, now: DateTime<Utc>) -> i32 {
+    if !config.lunar {
+        return config.temperature;
+    }
+
+    config.clamp_temperature(config.temperature + lunar::get_offset(now))
+}
+
+// Temperature the scheduler wants at `now`. `None` means the filter should
+// be disabled.
+fn get_scheduled_temperature(config: &Config, now: DateTime<Utc>) -> Option<i32> {
+    if let Some(block) = config.schedule.get_active_block(now) {
+        return block.temperature;
+    }
+
+    let (sunrise, sunset) = get_solar_times(config, now);
+
+    if !config.schedule.points.is_empty() {
+        let points = config.schedule.resolve(now, sunrise, sunset);
+        return schedule::get_temperature_at(&points, now.time());
+    }
+
+    let night_temperature = get_night_temperature(config, now);
+
+    let is_daytime = get_part_of_day(now.time(), sunrise, sunset) == PartOfDay::Daytime;
+
+    // The filter is normally enabled from sunset to sunrise. An inverted
+    // schedule enables it from sunrise to sunset instead.
+    let warm_start = if config.invert_schedule {
+        sunrise
+    } else {
+        sunset
+    };
+    if is_daytime != config.invert_schedule {
+        return None;
+    }
+
+    if let Some(fade_start) = get_wake_fade_start(config, now) {
+        let fade_duration = Duration::from_secs(config.wake_fade_minutes as u64 * 60);
+        match get_wake_temperature(
+            now.time(),
+            warm_start,
+            fade_start,
+            fade_duration,
+            night_temperature,
+        ) {
+            Some(temperature) if temperature >= IDENTITY_TEMPERATURE => return None,
+            Some(temperature) => return Some(temperature),
+            None => {}
         };
+    }
+
+    Some(night_temperature)
+}
 
-        info!("Config loaded");
+// Whether the scheduled temperature is changing gradually at `now`, in
+// which case the scheduler has to keep stepping it.
+fn is_in_transition(config: &Config, now: DateTime<Utc>) -> bool {
+    if config.schedule.get_active_block(now).is_some() {
+        return false;
+    }
 
-        Ok(config)
+    if !config.schedule.points.is_empty() {
+        let (sunrise, sunset) = get_solar_times(config, now);
+        let points = config.schedule.resolve(now, sunrise, sunset);
+        return schedule::is_interpolating(&points, now.time());
     }
+
+    // Only the wake-up fade produces other temperatures
+    let night_temperature = get_night_temperature(config, now);
+    get_scheduled_temperature(config, now)
+        .is_some_and(|temperature| temperature != night_temperature)
 }
 
-#[derive(Debug, PartialEq)]
-enum Message {
-    Day,
-    Night,
-    Shutdown,
+// How far the gradual transition at `now` has come, from 0 to 100
+fn get_transition_progress(config: &Config, now: DateTime<Utc>) -> Option<u8> {
+    if config.schedule.get_active_block(now).is_some() {
+        return None;
+    }
+
+    if !config.schedule.points.is_empty() {
+        let (sunrise, sunset) = get_solar_times(config, now);
+        let points = config.schedule.resolve(now, sunrise, sunset);
+        return schedule::get_progress_at(&points, now.time());
+    }
+
+    if !is_in_transition(config, now) {
+        return None;
+    }
+
+    // The wake-up fade is linear, so the temperature tells how far it is
+    let temperature = get_scheduled_temperature(config, now)?;
+    let night_temperature = get_night_temperature(config, now);
+    let span = (IDENTITY_TEMPERATURE - night_temperature) as f64;
+    let progress = (temperature - night_temperature) as f64 / span;
+
+    Some((progress.clamp(0.0, 1.0) * 100.0).round() as u8)
 }
 
-#[derive(PartialEq, Debug)]
-enum ParOfDay {
-    BeforeDaytime,
-    Daytime,
-    AfterDaytime,
+fn get_duration_to_next_change(config: &Config, now: DateTime<Utc>) -> Duration {
+    let duration = get_duration_to_next_solar_change(config, now);
+
+    match config.schedule.get_duration_to_next_block_change(now) {
+        Some(block_change) => duration.min(block_change),
+        None => duration,
+    }
 }
 
-fn get_sunrise_and_sunset(
-    latitude: f64,
-    longitude: f64,
-    altitude: f64,
-    year: i32,
-    month: u32,
-    day: u32,
-) -> (NaiveTime, NaiveTime) {
-    let date = NaiveDate::from_ymd_opt(year, month, day).unwrap();
-    let coord = Coordinates::new(latitude, longitude).unwrap();
+fn get_duration_to_next_solar_change(config: &Config, now: DateTime<Utc>) -> Duration {
+    let (sunrise, sunset) = get_solar_times(config, now);
 
-    let solarday = SolarDay::new(coord, date).with_altitude(altitude);
+    if !config.schedule.points.is_empty() {
+        let points = config.schedule.resolve(now, sunrise, sunset);
+        return schedule::get_duration_to_next_point(&points, now.time());
+    }
 
-    let sunrise = solarday.event_time(SolarEvent::Sunrise).time();
-    let sunset = solarday.event_time(SolarEvent::Sunset).time();
+    let mut duration = get_duration_to_next_event(now.time(), sunrise, sunset);
 
-    (sunrise, sunset)
+    if let Some(fade_start) = get_wake_fade_start(config, now) {
+        let secs_to_fade = get_seconds_between(now.time(), fade_start) as u64;
+        duration = duration.min(Duration::from_secs(secs_to_fade));
+    }
+
+    duration
 }
 
-fn get_part_of_day(time: NaiveTime, sunrise: NaiveTime, sunset: NaiveTime) -> ParOfDay {
-    if time < sunrise {
-        ParOfDay::BeforeDaytime
-    } else if time < sunset {
-        ParOfDay::Daytime
-    } else {
-        ParOfDay::AfterDaytime
+// The day's transitions, for showing to the user. Focus blocks starting
+// today add a point for their start, and one for the temperature the
+// schedule goes back to at their end.
+fn get_schedule_points(config: &Config, now: DateTime<Utc>) -> Vec<ResolvedPoint> {
+    let mut points = get_solar_schedule_points(config, now);
+
+    let today = now.with_timezone(&Local).date_naive();
+    for block in &config.schedule.blocks {
+        if !block.days.is_empty() && !block.days.contains(&today.weekday()) {
+            continue;
+        }
+
+        let (Some(start), Some(end)) = (
+            Local
+                .from_local_datetime(&today.and_time(block.start))
+                .earliest(),
+            Local
+                .from_local_datetime(&today.and_time(block.end))
+                .earliest(),
+        ) else {
+            continue;
+        };
+        let end = if block.end < block.start {
+            end + TimeDelta::days(1)
+        } else {
+            end
+        };
+
+        points.push(ResolvedPoint {
+            time: start.with_timezone(&Utc).time(),
+            label: Some(block.get_label().to_string()),
+            temperature: block.temperature,
+        });
+        points.push(ResolvedPoint {
+            time: end.with_timezone(&Utc).time(),
+            label: Some(format!("end of {}", block.get_label())),
+            temperature: get_scheduled_temperature(config, end.with_timezone(&Utc)),
+        });
     }
+
+    points.sort_by_key(|point| point.time);
+    points
 }
 
-fn get_duration_to_next_event(time: NaiveTime, sunrise: NaiveTime, sunset: NaiveTime) -> Duration {
-    let num_sec = match get_part_of_day(time, sunrise, sunset) {
-        ParOfDay::BeforeDaytime => sunrise - time,
-        ParOfDay::Daytime => sunset - time,
-        ParOfDay::AfterDaytime => NaiveTime::from_str("23:59:59").unwrap() - time,
+fn get_solar_schedule_points(config: &Config, now: DateTime<Utc>) -> Vec<ResolvedPoint> {
+    let (sunrise, sunset) = get_solar_times(config, now);
+
+    if !config.schedule.points.is_empty() {
+        return config.schedule.resolve(now, sunrise, sunset);
+    }
+
+    let night_temperature = get_night_temperature(config, now);
+    let point = |time: NaiveTime, label: &str, temperature: Option<i32>| ResolvedPoint {
+        time,
+        label: Some(label.to_string()),
+        temperature,
     };
 
-    let result = u64::try_from(num_sec.num_seconds());
+    let mut points = if config.invert_schedule {
+        vec![
+            point(sunrise, "sunrise", Some(night_temperature)),
+            point(sunset, "sunset", None),
+        ]
+    } else {
+        vec![
+            point(sunset, "sunset", Some(night_temperature)),
+            point(sunrise, "sunrise", None),
+        ]
+    };
 
-    match result {
-        Ok(secs) => Duration::from_secs(secs),
-        Err(_) => Duration::from_secs(0),
+    if let (Some(fade_start), Some(wake)) = (
+        get_wake_fade_start(config, now),
+        config
+            .wake_time
+            .and_then(|wake_time| get_local_time_as_utc(wake_time, now)),
+    ) {
+        points.push(point(fade_start, "wake-up fade", Some(night_temperature)));
+        points.push(point(wake, "wake", None));
     }
+
+    points.sort_by_key(|point| point.time);
+    points
+}
+
+fn get_duration_to_next_event(time: NaiveTime, sunrise: NaiveTime, sunset: NaiveTime) -> Duration {
+    solar::get_duration_to_next_event(pin_to_day(time), pin_to_day(sunrise), pin_to_day(sunset))
 }
 
 #[test]
@@ -141,6 +438,14 @@ fn test_get_sunrise_and_sunset() {
     assert_eq!(sunset, NaiveTime::from_str("18:07:08").unwrap());
 }
 
+#[test]
+fn test_get_sunset_delay() {
+    assert_eq!(get_sunset_delay(Weekday::Thu, 90), TimeDelta::zero());
+    assert_eq!(get_sunset_delay(Weekday::Fri, 90), TimeDelta::minutes(90));
+    assert_eq!(get_sunset_delay(Weekday::Sat, 90), TimeDelta::minutes(90));
+    assert_eq!(get_sunset_delay(Weekday::Sun, 90), TimeDelta::zero());
+}
+
 #[test]
 fn test_get_part_of_day() {
     let (sunrise, sunset) = get_sunrise_and_sunset(0., 0., 0., 1970, 1, 1);
@@ -151,14 +456,17 @@ fn test_get_part_of_day() {
 
     assert_eq!(
         get_part_of_day(before_daytime, sunrise, sunset),
-        ParOfDay::BeforeDaytime
+        PartOfDay::BeforeDaytime
     );
 
-    assert_eq!(get_part_of_day(daytime, sunrise, sunset), ParOfDay::Daytime);
+    assert_eq!(
+        get_part_of_day(daytime, sunrise, sunset),
+        PartOfDay::Daytime
+    );
 
     assert_eq!(
         get_part_of_day(after_daytime, sunrise, sunset),
-        ParOfDay::AfterDaytime
+        PartOfDay::AfterDaytime
     );
 }
 
@@ -186,6 +494,139 @@ fn test_duration_to_next_event() {
     );
 }
 
+#[test]
+fn test_get_scheduled_temperature() {
+    use chrono::TimeZone;
+
+    let mut config: Config = toml::from_str(
+        r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0"#,
+    )
+    .unwrap();
+
+    let night = Utc.with_ymd_and_hms(1970, 1, 1, 23, 30, 0).unwrap();
+    let day = Utc.with_ymd_and_hms(1970, 1, 1, 10, 30, 0).unwrap();
+
+    assert_eq!(get_scheduled_temperature(&config, night), Some(3000));
+    assert_eq!(get_scheduled_temperature(&config, day), None);
+
+    config.invert_schedule = true;
+    assert_eq!(get_scheduled_temperature(&config, night), None);
+    assert_eq!(get_scheduled_temperature(&config, day), Some(3000));
+    assert_eq!(get_transition_progress(&config, night), None);
+}
+
+#[test]
+fn test_get_startup_temperature() {
+    use chrono::TimeZone;
+
+    let mut config: Config = toml::from_str(
+        r#"temperature = 3000
+latitude = 0
+longitude = 0
+altitude = 0"#,
+    )
+    .unwrap();
+    let night = Utc.with_ymd_and_hms(1970, 1, 1, 23, 30, 0).unwrap();
+    let day = Utc.with_ymd_and_hms(1970, 1, 1, 10, 30, 0).unwrap();
+
+    let mut state = State::default();
+    assert_eq!(
+        get_startup_temperature(&config, &state, night),
+        Some(Some(3000))
+    );
+    assert_eq!(get_startup_temperature(&config, &state, day), Some(None));
+    config.day_temperature = Some(5500);
+    assert_eq!(
+        get_startup_temperature(&config, &state, day),
+        Some(Some(5500))
+    );
+
+    state.automatic = false;
+    assert_eq!(get_startup_temperature(&config, &state, day), None);
+    state.mode = Some(Mode::Night);
+    assert_eq!(
+        get_startup_temperature(&config, &state, day),
+        Some(Some(3000))
+    );
+}
+
+#[test]
+fn test_get_wake_temperature() {
+    let sunset = NaiveTime::from_str("18:00:00").unwrap();
+    let fade_start = NaiveTime::from_str("05:30:00").unwrap();
+    let fade_duration = Duration::from_secs(30 * 60);
+
+    let get_temperature = |time: &str| {
+        get_wake_temperature(
+            NaiveTime::from_str(time).unwrap(),
+            sunset,
+            fade_start,
+            fade_duration,
+            3000,
+        )
+    };
+
+    // Still night, the fade has not started yet
+    assert_eq!(get_temperature("22:00:00"), None);
+    assert_eq!(get_temperature("05:00:00"), None);
+
+    assert_eq!(get_temperature("05:30:00"), Some(3000));
+    assert_eq!(get_temperature("05:45:00"), Some(4750));
+
+    // Past the wake time, stays off until sunrise
+    assert_eq!(get_temperature("06:00:00"), Some(IDENTITY_TEMPERATURE));
+    assert_eq!(get_temperature("06:20:00"), Some(IDENTITY_TEMPERATURE));
+}
+
+// Sunrise and sunset by day, so notifications don't recompute them.
+// Cleared whenever the config changes.
+#[derive(Debug, Default)]
+struct SolarTable {
+    days: HashMap<NaiveDate, (NaiveTime, NaiveTime)>,
+}
+
+impl SolarTable {
+    fn get(&mut self, config: &Config, date: NaiveDate) -> (NaiveTime, NaiveTime) {
+        // Only a couple of days are ever asked for
+        if self.days.len() > 7 {
+            self.days.clear();
+        }
+
+        *self
+            .days
+            .entry(date)
+            .or_insert_with(|| get_solar_times(config, date.and_time(NaiveTime::MIN).and_utc()))
+    }
+
+    fn clear(&mut self) {
+        self.days.clear();
+    }
+
+    // The first sunrise, or sunset, after `now`
+    fn get_next_event(
+        &mut self,
+        config: &Config,
+        now: DateTime<Utc>,
+        sunrise: bool,
+    ) -> DateTime<Utc> {
+        let date = now.date_naive();
+        let pick = |(rise, set): (NaiveTime, NaiveTime)| if sunrise { rise } else { set };
+
+        let today = date.and_time(pick(self.get(config, date))).and_utc();
+        if today > now {
+            return today;
+        }
+
+        let tomorrow = date + TimeDelta::days(1);
+        tomorrow
+            .and_time(pick(self.get(config, tomorrow)))
+            .and_utc()
+    }
+}
+
 struct HyprsunsetClient {
     sock_path: PathBuf,
 }
@@ -195,28 +636,54 @@ impl HyprsunsetClient {
         Self { sock_path }
     }
 
+    // With short timeouts both ways, so a wedged hyprsunset can't hang the
+    // main loop
     fn create_socket(&self, socket_path: &PathBuf) -> Result<UnixStream> {
         let sock = match UnixStream::connect(socket_path) {
             Ok(sock) => sock,
-            Err(e) => {
-                return Err(e)
-                    .context(format!("Failed to connect to socket at: {:?}", socket_path));
+            Err(source) => {
+                return Err(BackendError::Connect {
+                    path: socket_path.clone(),
+                    source,
+                }
+                .into());
             }
         };
+
+        if let Err(e) = sock.set_read_timeout(Some(HYPRSUNSET_TIMEOUT)) {
+            return Err(e).context("Failed to set read timeout");
+        };
+        if let Err(e) = sock.set_write_timeout(Some(HYPRSUNSET_TIMEOUT)) {
+            return Err(e).context("Failed to set write timeout");
+        };
+
         Ok(sock)
     }
 
+    // hyprsunset closes the connection after each reply, so every command
+    // gets its own
     fn send_command(&mut self, command: &str) -> Result<()> {
         let mut sock = self.create_socket(&self.sock_path)?;
 
-        // Set short timeout to prevent hanging
-        if let Err(e) = sock.set_read_timeout(Some(Duration::from_millis(500))) {
-            return Err(e).context("Failed to set read timeout");
-        };
-
         match sock.write_all(command.as_bytes()) {
             Ok(_) => Ok(()),
-            Err(e) => Err(e).context("Failed to send command to hyprsunset"),
+            Err(e) => Err(BackendError::Send(e).into()),
+        }
+    }
+
+    // Sends `command` and returns hyprsunset's reply
+    fn query(&mut self, command: &str) -> Result<String> {
+        let mut sock = self.create_socket(&self.sock_path)?;
+
+        if let Err(e) = sock.write_all(command.as_bytes()) {
+            return Err(BackendError::Send(e).into());
+        };
+
+        // The reply is short and arrives in one piece
+        let mut reply = [0; 64];
+        match sock.read(&mut reply) {
+            Ok(length) => Ok(String::from_utf8_lossy(&reply[..length]).into_owned()),
+            Err(e) => Err(BackendError::Reply(e).into()),
         }
     }
 
@@ -224,260 +691,2590 @@ impl HyprsunsetClient {
         self.send_command(format!("temperature {}", temperature).as_str())
     }
 
+    // The temperature hyprsunset currently applies. Older versions don't
+    // answer queries, which is an error as well.
+    fn get_temperature(&mut self) -> Result<i32> {
+        parse_temperature_reply(&self.query("temperature")?)
+    }
+
     fn disable(&mut self) -> Result<()> {
         self.send_command("identity")
     }
-}
 
-fn get_hyprsunset_socket_path() -> Result<PathBuf> {
-    let his = match std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok() {
-        Some(env) => env,
-        None => return Err(anyhow!("HYPRSUNSET_INSTANCE_SIGNATURE not set")),
-    };
-
-    let runtime_dir = match std::env::var("XDG_RUNTIME_DIR") {
-        Ok(dir) => dir,
-        Err(_) => return Err(anyhow!("XDG_RUNTIME_DIR not set")),
-    };
-
-    let socket_path = PathBuf::from(format!("{}/hypr/{}/.hyprsunset.sock", runtime_dir, his));
-
-    match wait_for_hyprsunset_socket(&socket_path) {
-        Ok(_) => Ok(socket_path),
-        Err(e) => Err(e).context("Failed to wait for hyprsunset socket"),
+    fn set_gamma(&mut self, gamma: i32) -> Result<()> {
+        self.send_command(format!("gamma {}", gamma).as_str())
     }
 }
 
-fn wait_for_hyprsunset_socket(socket_path: &Path) -> Result<()> {
-    let mut tries = 0;
-    while tries < 10 {
-        if socket_path.exists() {
-            info!("Socket path exists");
-            return Ok(());
+#[test]
+fn test_hyprsunset_client() {
+    use std::os::unix::net::UnixListener;
+
+    let sock_path = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-client-{}.sock",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&sock_path);
+    let listener = UnixListener::bind(&sock_path).unwrap();
+
+    // Like hyprsunset, replies and closes the connection
+    let server = thread::spawn(move || {
+        let mut commands = Vec::new();
+        for _ in 0..2 {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut command = [0; 64];
+            let length = stream.read(&mut command).unwrap();
+            commands.push(String::from_utf8_lossy(&command[..length]).into_owned());
+            // The client doesn't wait for the reply
+            let _ = stream.write_all(b"ok");
         }
-        tries += 1;
-        info!("Socket path does not exist. Waiting 1 second");
-        thread::sleep(Duration::from_secs(1));
-    }
+        commands
+    });
 
-    anyhow::bail!("hyprsunset did not create socket");
+    let mut client = HyprsunsetClient::new(sock_path.clone());
+    client.enable(3000).unwrap();
+    client.disable().unwrap();
+
+    assert_eq!(server.join().unwrap(), ["temperature 3000", "identity"]);
+    fs::remove_file(&sock_path).unwrap();
+
+    let e = client.enable(3000).unwrap_err();
+    assert!(matches!(
+        e.downcast_ref::<BackendError>(),
+        Some(BackendError::Connect { .. })
+    ));
 }
 
-fn verify_hyprsunset_is_installed() -> Result<()> {
-    match Command::new("which").arg("hyprsunset").output() {
-        Ok(output) => {
-            if !output.status.success() {
-                anyhow::bail!("hyprsunset is not installed");
-            };
-            info!("hyprsunset is installed");
-            Ok(())
-        }
-        Err(e) => anyhow::bail!("Failed to check if hyprsunset is installed: {}", e),
-    }
+// hyprsunset of every running Hyprland instance, or only of the one we were
+// started in
+struct HyprsunsetClients {
+    clients: Vec<HyprsunsetClient>,
+    // $XDG_RUNTIME_DIR/hypr, scanned for instances when managing all of them
+    hypr_dir: Option<PathBuf>,
 }
 
-fn wait_for_hyprsunset_to_start() -> Result<()> {
-    let mut tries = 0;
-    while tries < 10 {
-        match Command::new("hyprsunset").arg("--help").output() {
-            Ok(output) => {
-                if output.status.success() {
-                    info!("hyprsunset is running");
-                    return Ok(());
-                }
-            }
-            Err(e) => anyhow::bail!("Failed to check if hyprsunset is running: {}", e),
+impl HyprsunsetClients {
+    fn new(sock_path: PathBuf, hypr_dir: Option<PathBuf>) -> Self {
+        Self {
+            clients: vec![HyprsunsetClient::new(sock_path)],
+            hypr_dir,
         }
-        tries += 1;
-        info!("hyprsunset is not running. Waiting 1 second");
-        thread::sleep(Duration::from_secs(1));
     }
-    anyhow::bail!("hyprsunset failed to start");
-}
 
-fn setup_logging() {
-    let runtime_dir = match std::env::var("XDG_RUNTIME_DIR") {
-        Ok(dir) => dir,
-        Err(_) => {
-            println!("Failed to get XDG_RUNTIME_DIR when setting up logging");
+    // Picks up instances started or stopped since the last command, e.g. a
+    // nested session for development
+    fn refresh(&mut self) {
+        let Some(hypr_dir) = &self.hypr_dir else {
             return;
-        }
-    };
+        };
 
-    let log_path = format!("{}/hyprsunset-overdrive.log", runtime_dir);
-    let log_file = match File::create(log_path) {
-        Ok(file) => file,
-        Err(_) => {
-            println!("Failed to create log file when setting up logging");
+        let sock_paths = find_instance_sockets(hypr_dir);
+        let current: Vec<&PathBuf> = self
+            .clients
+            .iter()
+            .map(|client| &client.sock_path)
+            .collect();
+        if sock_paths.is_empty() || sock_paths.iter().eq(current) {
             return;
         }
-    };
-
-    if let Err(e) = CombinedLogger::init(vec![
-        TermLogger::new(
-            LevelFilter::Info,
-            LogConfig::default(),
-            TerminalMode::Mixed,
-            ColorChoice::Auto,
-        ),
-        WriteLogger::new(LevelFilter::Info, LogConfig::default(), log_file),
-    ]) {
-        println!("Failed to initialize logging: {}", e);
-    };
-}
 
-fn convert_bytes_to_icon(bytes: &[u8]) -> Result<Icon> {
-    let image_buff = match image::load_from_memory(bytes) {
-        Ok(image_dyn) => image_dyn.into_rgba8(),
-        Err(e) => return Err(e).context("Failed to load icon"),
-    };
+        info!("Managing hyprsunset at {:?}", sock_paths);
+        self.clients = sock_paths.into_iter().map(HyprsunsetClient::new).collect();
+    }
 
-    let (width, height) = image_buff.dimensions();
-    let icon_rgba = image_buff.into_raw();
+    // Runs `command` on every instance, even if some of them fail
+    fn for_each(&mut self, command: impl Fn(&mut HyprsunsetClient) -> Result<()>) -> Result<()> {
+        self.refresh();
 
-    let icon = match Icon::from_rgba(icon_rgba, width, height) {
-        Ok(icon) => icon,
-        Err(e) => return Err(e).context("Failed to create icon"),
-    };
+        let mut result = Ok(());
+        for client in &mut self.clients {
+            if let Err(e) = command(client) {
+                let e = e.context(format!("hyprsunset at {:?}", client.sock_path));
+                if result.is_ok() {
+                    result = Err(e);
+                }
+            }
+        }
 
-    Ok(icon)
-}
+        result
+    }
 
-fn setup_tray_icon(main_tx: Sender<Message>) -> Sender<Message> {
-    let (tx, rx) = channel::<Message>();
+    fn enable(&mut self, temperature: i32) -> Result<()> {
+        self.for_each(|client| client.enable(temperature))
+    }
 
-    // We need gtk in order to build the tray icon in linux.
-    // Without gtk, the tray icon build will fail. You'll see an error
-    // message in the terminal.
-    // Also, this will be spawned in a separate thread as calling gtk::main()
-    // will block the main thread.
-    std::thread::spawn(move || {
-        use glib;
-        use tray_icon::{
-            TrayIconBuilder,
-            menu::{Menu, MenuEvent, MenuItem, PredefinedMenuItem},
-        };
+    fn disable(&mut self) -> Result<()> {
+        self.for_each(|client| client.disable())
+    }
 
-        gtk::init().unwrap();
+    fn set_gamma(&mut self, gamma: i32) -> Result<()> {
+        self.for_each(|client| client.set_gamma(gamma))
+    }
 
-        let icon = match convert_bytes_to_icon(ENABLED_ICON_BYTES) {
-            Ok(icon) => icon,
-            Err(e) => {
-                error!("Failed to convert bytes to icon: {}", e);
-                return;
-            }
-        };
+    // One reply per instance
+    fn query(&mut self, command: &str) -> Result<Vec<String>> {
+        self.refresh();
+        self.clients
+            .iter_mut()
+            .map(|client| client.query(command))
+            .collect()
+    }
 
-        // Tray icons withoutmenus are not displayed on linux.
-        // Therefore, we need to addan empty menu to the tray icon.
-        // See: https://github.com/tauri-apps/tray-icon/blob/97723fd207add9c3bb0511cb0e4d04d8652a0027/src/lib.rs#L255
-        // See: https://github.com/libsdl-org/SDL/issues/12092
+    fn get_temperatures(&mut self) -> Result<Vec<i32>> {
+        self.refresh();
+        self.clients
+            .iter_mut()
+            .map(|client| client.get_temperature())
+            .collect()
+    }
+}
 
-        let enable_item = MenuItem::with_id("enabled", "Night", true, None);
-        let disable_item = MenuItem::with_id("disabled", "Day", true, None);
+// What applies the temperature
+enum Backend {
+    Hyprsunset(HyprsunsetClients),
+    // Outside of Hyprland
+    Command(GammaCommand),
+    // `backend = "null"`
+    Null(NullBackend),
+    // `--dry-run` only logs the hyprsunset commands
+    DryRun,
+}
 
-        let menu = Menu::new();
+impl Backend {
+    fn enable(&mut self, temperature: i32) -> Result<()> {
+        match self {
+            Backend::Hyprsunset(clients) => clients.enable(temperature),
+            Backend::Command(command) => command.enable(temperature),
+            Backend::Null(null) => null.enable(temperature),
+            Backend::DryRun => log_dry_run(&format!("temperature {}", temperature)),
+        }
+    }
 
-        if let Err(e) = menu.append_items(&[
-            &enable_item,
-            &PredefinedMenuItem::separator(),
-            &disable_item,
-        ]) {
-            error!("Failed to append menu item: {}", e);
-            return;
+    fn disable(&mut self) -> Result<()> {
+        match self {
+            Backend::Hyprsunset(clients) => clients.disable(),
+            Backend::Command(command) => command.disable(),
+            Backend::Null(null) => null.disable(),
+            Backend::DryRun => log_dry_run("identity"),
+        }
+    }
+
+    fn set_gamma(&mut self, gamma: i32) -> Result<()> {
+        match self {
+            Backend::Hyprsunset(clients) => clients.set_gamma(gamma),
+            Backend::Command(command) => command.set_gamma(gamma),
+            Backend::Null(null) => null.set_gamma(gamma),
+            Backend::DryRun => log_dry_run(&format!("gamma {}", gamma)),
+        }
+    }
+
+    fn get_temperatures(&mut self) -> Result<Vec<i32>> {
+        match self {
+            Backend::Hyprsunset(clients) => clients.get_temperatures(),
+            Backend::Command(_) => Err(anyhow!("The gamma command can't be queried")),
+            Backend::Null(null) => Ok(vec![null.get_temperature()]),
+            Backend::DryRun => Err(anyhow!("Nothing to query in a dry run")),
+        }
+    }
+
+    fn query(&mut self, command: &str) -> Result<Vec<String>> {
+        match self {
+            Backend::Hyprsunset(clients) => clients.query(command),
+            Backend::Command(_) | Backend::Null(_) => Err(anyhow!("Raw commands need hyprsunset")),
+            Backend::DryRun => Err(anyhow!("Nothing to query in a dry run")),
+        }
+    }
+}
+
+// E.g. "Sunrise tomorrow 06:42", with the day left out for today
+fn format_almanac_row(
+    name: &str,
+    time: DateTime<Local>,
+    now: DateTime<Local>,
+    time_format: TimeFormat,
+) -> String {
+    let day = match (time.date_naive() - now.date_naive()).num_days() {
+        0 => String::new(),
+        1 => " tomorrow".to_string(),
+        -1 => " yesterday".to_string(),
+        _ => time.format(" %b %-d").to_string(),
+    };
+
+    format!("{}{} {}", name, day, time_format.format(time.time()))
+}
+
+#[test]
+fn test_format_almanac_row() {
+    let now = Local.with_ymd_and_hms(2025, 6, 21, 22, 0, 0).unwrap();
+    let sunrise = Local.with_ymd_and_hms(2025, 6, 22, 6, 42, 0).unwrap();
+    let sunset = Local.with_ymd_and_hms(2025, 6, 21, 20, 12, 0).unwrap();
+
+    assert_eq!(
+        format_almanac_row("Sunrise", sunrise, now, TimeFormat::TwentyFourHour),
+        "Sunrise tomorrow 06:42"
+    );
+    assert_eq!(
+        format_almanac_row("Sunset", sunset, now, TimeFormat::TwelveHour),
+        "Sunset 8:12 PM"
+    );
+    assert_eq!(
+        format_almanac_row("Sunset", sunset, sunrise, TimeFormat::TwentyFourHour),
+        "Sunset yesterday 20:12"
+    );
+}
+
+fn log_dry_run(command: &str) -> Result<()> {
+    info!("Would send to hyprsunset: {}", command);
+    Ok(())
+}
+
+// The hyprsunset sockets in each instance directory under `hypr_dir`
+fn find_instance_sockets(hypr_dir: &Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(hypr_dir) else {
+        return Vec::new();
+    };
+
+    let mut sock_paths: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(".hyprsunset.sock"))
+        .filter(|sock_path| sock_path.exists())
+        .collect();
+    sock_paths.sort();
+
+    sock_paths
+}
+
+#[test]
+fn test_find_instance_sockets() {
+    let hypr_dir = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-hypr-{}",
+        std::process::id()
+    ));
+    assert!(find_instance_sockets(&hypr_dir).is_empty());
+
+    for (instance, has_socket) in [("b_nested", true), ("a_primary", true), ("c_idle", false)] {
+        let dir = hypr_dir.join(instance);
+        fs::create_dir_all(&dir).unwrap();
+        if has_socket {
+            fs::write(dir.join(".hyprsunset.sock"), "").unwrap();
+        }
+    }
+    assert_eq!(
+        find_instance_sockets(&hypr_dir),
+        [
+            hypr_dir.join("a_primary/.hyprsunset.sock"),
+            hypr_dir.join("b_nested/.hyprsunset.sock")
+        ]
+    );
+
+    fs::remove_dir_all(&hypr_dir).unwrap();
+}
+
+fn parse_temperature_reply(reply: &str) -> Result<i32> {
+    match reply.trim().parse::<i32>() {
+        Ok(temperature) => Ok(temperature),
+        Err(_) => Err(anyhow!(
+            "Unexpected reply from hyprsunset: {:?}",
+            reply.trim()
+        )),
+    }
+}
+
+#[test]
+fn test_parse_temperature_reply() {
+    assert_eq!(parse_temperature_reply("3000\n").unwrap(), 3000);
+    assert!(parse_temperature_reply("").is_err());
+    assert!(parse_temperature_reply("invalid command").is_err());
+}
+
+fn get_hyprsunset_socket_path() -> Result<PathBuf> {
+    let his = match std::env::var("HYPRLAND_INSTANCE_SIGNATURE").ok() {
+        Some(env) => env,
+        None => return Err(anyhow!("HYPRSUNSET_INSTANCE_SIGNATURE not set")),
+    };
+
+    let runtime_dir = match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => dir,
+        Err(_) => return Err(anyhow!("XDG_RUNTIME_DIR not set")),
+    };
+
+    let socket_path = PathBuf::from(format!("{}/hypr/{}/.hyprsunset.sock", runtime_dir, his));
+
+    match wait_for_hyprsunset_socket(&socket_path) {
+        Ok(_) => Ok(socket_path),
+        Err(e) => Err(e).context("Failed to wait for hyprsunset socket"),
+    }
+}
+
+fn wait_for_hyprsunset_socket(socket_path: &Path) -> Result<()> {
+    let mut tries = 0;
+    while tries < 10 {
+        if socket_path.exists() {
+            info!("Socket path exists");
+            return Ok(());
+        }
+        tries += 1;
+        info!("Socket path does not exist. Waiting 1 second");
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    anyhow::bail!("hyprsunset did not create socket");
+}
+
+// `enable [temperature]` and `disable` talk to hyprsunset directly, so
+// scripts can use them whether or not the daemon is running
+fn run_one_shot(args: &[String]) -> Result<String> {
+    let mut client = HyprsunsetClient::new(get_hyprsunset_socket_path()?);
+
+    match args {
+        [command] if command == "disable" => {
+            client.disable()?;
+            Ok("identity".to_string())
+        }
+        [command, temperature @ ..] if command == "enable" && temperature.len() <= 1 => {
+            // Without a config, the defaults are as good a guess as any
+            let config = Config::get_path()
+                .and_then(|path| Config::read(&path, None))
+                .unwrap_or_default();
+            let temperature = match temperature.first().map(|arg| arg.parse::<i32>()) {
+                Some(Ok(temperature)) => config.clamp_temperature(temperature),
+                Some(Err(_)) => return Err(anyhow!("Invalid temperature: {}", temperature[0])),
+                None => config.temperature,
+            };
+
+            client.enable(temperature)?;
+            Ok(format!("{}K", temperature))
+        }
+        _ => Err(anyhow!("Usage: enable [TEMPERATURE] | disable")),
+    }
+}
+
+fn verify_hyprsunset_is_installed() -> Result<()> {
+    match Command::new("which").arg("hyprsunset").output() {
+        Ok(output) => {
+            if !output.status.success() {
+                anyhow::bail!("hyprsunset is not installed");
+            };
+            info!("hyprsunset is installed");
+            Ok(())
+        }
+        Err(e) => anyhow::bail!("Failed to check if hyprsunset is installed: {}", e),
+    }
+}
+
+fn wait_for_hyprsunset_to_start() -> Result<()> {
+    let mut tries = 0;
+    while tries < 10 {
+        match Command::new("hyprsunset").arg("--help").output() {
+            Ok(output) => {
+                if output.status.success() {
+                    info!("hyprsunset is running");
+                    return Ok(());
+                }
+            }
+            Err(e) => anyhow::bail!("Failed to check if hyprsunset is running: {}", e),
+        }
+        tries += 1;
+        info!("hyprsunset is not running. Waiting 1 second");
+        thread::sleep(Duration::from_secs(1));
+    }
+    anyhow::bail!("hyprsunset failed to start");
+}
+
+// Only created once there is something to log, so an unwritable runtime
+// directory doesn't keep the daemon from starting. Logs are dropped while it
+// can't be created, and it is tried again with later lines.
+struct LazyLogFile {
+    path: PathBuf,
+    file: Option<File>,
+    // Looking for the file on every line would be a syscall per line
+    last_check: Option<Instant>,
+    // So a failure is only printed once until it works again
+    failing: bool,
+}
+
+impl LazyLogFile {
+    fn new(path: PathBuf) -> Self {
+        Self {
+            path,
+            file: None,
+            last_check: None,
+            failing: false,
+        }
+    }
+
+    fn create(&self) -> std::io::Result<File> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        File::create(&self.path)
+    }
+}
+
+impl Write for LazyLogFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if self
+            .last_check
+            .is_none_or(|checked| checked.elapsed() >= LOG_FILE_CHECK_INTERVAL)
+        {
+            self.last_check = Some(Instant::now());
+
+            // Removed along with $XDG_RUNTIME_DIR, writing on would only
+            // fill the unlinked file
+            if self.file.is_some() && !self.path.exists() {
+                self.file = None;
+            }
+
+            if self.file.is_none() {
+                match self.create() {
+                    Ok(file) => {
+                        self.file = Some(file);
+                        self.failing = false;
+                    }
+                    Err(e) if !self.failing => {
+                        eprintln!("Failed to create log file {:?}: {}", self.path, e);
+                        self.failing = true;
+                    }
+                    Err(_) => {}
+                };
+            }
+        }
+
+        match &mut self.file {
+            Some(file) => file.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.file {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+#[test]
+fn test_lazy_log_file() {
+    let dir = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-log-{}",
+        std::process::id()
+    ));
+    fs::create_dir_all(&dir).unwrap();
+
+    let path = dir.join("hyprsunset-overdrive.log");
+    let mut log_file = LazyLogFile::new(path.clone());
+    assert!(!path.exists());
+    log_file.write_all(b"started\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "started\n");
+
+    // Cleaned up under us, noticed with the next check
+    fs::remove_dir_all(&dir).unwrap();
+    log_file.last_check = None;
+    log_file.write_all(b"recreated\n").unwrap();
+    assert_eq!(fs::read_to_string(&path).unwrap(), "recreated\n");
+
+    // A file in place of the directory, until it is cleaned up
+    let blocker = dir.join("blocker");
+    fs::write(&blocker, "").unwrap();
+    let mut log_file = LazyLogFile::new(blocker.join("hyprsunset-overdrive.log"));
+    log_file.write_all(b"dropped\n").unwrap();
+    assert!(log_file.failing);
+
+    fs::remove_file(&blocker).unwrap();
+    log_file.write_all(b"dropped too\n").unwrap();
+    assert!(log_file.failing);
+    log_file.last_check = None;
+    log_file.write_all(b"retried\n").unwrap();
+    assert_eq!(
+        fs::read_to_string(blocker.join("hyprsunset-overdrive.log")).unwrap(),
+        "retried\n"
+    );
+
+    fs::remove_dir_all(&dir).unwrap();
+}
+
+// Gives up after a while, the rest works without a tray icon
+fn wait_for_tray_host() {
+    let mut tries = 0;
+    while tries < TRAY_HOST_TRIES {
+        if is_tray_host_running() {
+            info!("Tray host is running");
+            return;
+        }
+        tries += 1;
+        info!("Tray host is not running. Waiting 1 second");
+        thread::sleep(Duration::from_secs(1));
+    }
+
+    warn!("No tray host appeared, starting anyway");
+}
+
+// Timestamps log lines in local time
+struct LocalTimer(String);
+
+impl FormatTime for LocalTimer {
+    fn format_time(&self, w: &mut Writer<'_>) -> std::fmt::Result {
+        write!(w, "{}", Local::now().format(&self.0))
+    }
+}
+
+// A UTC time of day on the day of `now`, such as a sunset, for the log
+fn format_log_time(config: &Config, time: NaiveTime, now: DateTime<Utc>) -> String {
+    let time = now.date_naive().and_time(time).and_utc();
+    time.with_timezone(&Local)
+        .format(&config.log_time_format)
+        .to_string()
+}
+
+// Opens `log_file` with the default application, or else shows this
+// process's part of the journal in $TERMINAL, which is where the terminal
+// output goes under systemd
+fn get_open_log_command(log_file: Option<&Path>, pid: u32, terminal: Option<&str>) -> Vec<String> {
+    if let Some(log_file) = log_file {
+        return vec!["xdg-open".to_string(), log_file.display().to_string()];
+    }
+
+    let mut command = match terminal {
+        Some(terminal) => vec![terminal.to_string(), "-e".to_string()],
+        None => vec!["xdg-terminal-exec".to_string()],
+    };
+    command.extend([
+        "journalctl".to_string(),
+        "--user".to_string(),
+        "--follow".to_string(),
+        format!("_PID={}", pid),
+    ]);
+    command
+}
+
+#[test]
+fn test_get_open_log_command() {
+    assert_eq!(
+        get_open_log_command(
+            Some(Path::new("/run/user/1000/hyprsunset-overdrive.log")),
+            42,
+            None
+        ),
+        ["xdg-open", "/run/user/1000/hyprsunset-overdrive.log"]
+    );
+    assert_eq!(
+        get_open_log_command(None, 42, Some("foot")),
+        ["foot", "-e", "journalctl", "--user", "--follow", "_PID=42"]
+    );
+    assert_eq!(get_open_log_command(None, 42, None)[0], "xdg-terminal-exec");
+}
+
+// Logs to the terminal, and to `log_dir` unless `log_to_file` is off
+fn setup_logging(log_to_file: bool, time_format: &str) {
+    let log_file = match (log_to_file, paths::get_log_dir()) {
+        (false, _) => None,
+        (true, Ok(dir)) => {
+            // An overridden `log_dir` may not exist yet, and if it can't be
+            // created the file fails later with the proper error
+            let _ = fs::create_dir_all(&dir);
+            Some(LazyLogFile::new(dir.join(LOG_FILE_NAME)))
+        }
+        (true, Err(e)) => {
+            eprintln!("{:#}, logging to the terminal only", e);
+            None
+        }
+    };
+
+    // Defaults to info. Use RUST_LOG to change it per module, for example
+    // RUST_LOG=hyprsunset_overdrive::scheduler=debug
+    let filter = match EnvFilter::try_from_default_env() {
+        Ok(filter) => filter,
+        Err(_) => EnvFilter::new("info"),
+    };
+
+    if let Err(e) = tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer().with_timer(LocalTimer(time_format.to_string())))
+        .with(log_file.map(|log_file| {
+            tracing_subscriber::fmt::layer()
+                .with_timer(LocalTimer(time_format.to_string()))
+                .with_ansi(false)
+                .with_writer(Mutex::new(log_file))
+        }))
+        .try_init()
+    {
+        println!("Failed to initialize logging: {}", e);
+    };
+}
+
+// Someone who asked for the filter to stay off, over the control socket
+struct Inhibitor {
+    id: u64,
+    caller: String,
+    reason: Option<String>,
+}
+
+struct Daemon {
+    config: Config,
+    state: State,
+    // Shared with the scheduler thread so it stops issuing state changes
+    // while in manual mode.
+    automatic: Arc<AtomicBool>,
+    client: Backend,
+    tray: TrayHandle,
+    // Currently applied temperature. `None` means the filter is disabled.
+    temperature: Option<i32>,
+    // While in astronomy or reading mode the schedule is ignored
+    astro: bool,
+    reading: bool,
+    errors: ErrorReporter,
+    // Set between SIGTSTP and SIGCONT
+    paused: bool,
+    // Used to end previews and to restart the scheduler
+    main_tx: Sender<Event>,
+    scheduler_tx: Sender<()>,
+    // The network the config was loaded for
+    ssid: Option<String>,
+    // Counts previews, so only the end of the latest one restores the state
+    preview: u64,
+    previewing: bool,
+    // Set by `toggle`. The schedule is ignored until then.
+    override_until: Option<DateTime<Utc>>,
+    // While there are any, the filter is off and the schedule is ignored
+    inhibitors: Vec<Inhibitor>,
+    // Widgets following the JSON events, and the last status they got
+    // without the fields that move with the clock
+    subscribers: Vec<Sender<String>>,
+    published: Option<Vec<(&'static str, StatusValue)>>,
+    // Between `wind-down on` and `wind-down off`
+    wind_down: bool,
+    // The last temperature written to the history
+    recorded: Option<Option<i32>>,
+    // Threads that panicked, for `status`
+    worker_failures: u64,
+    last_worker_failure: Option<String>,
+    solar_table: SolarTable,
+    // The last temperature the scheduler sent, with a script the only way
+    // to know what it decided
+    scheduled: Option<Option<i32>>,
+    // Whether the last scheduled temperature enabled the filter, to notice
+    // when the schedule switches
+    scheduled_night: Option<bool>,
+    // Set by `--dry-run`, which leaves the state and history alone
+    dry_run: bool,
+    // The local UTC offset, to notice daylight saving time changes
+    utc_offset: FixedOffset,
+    // When the last command or tray action changed something
+    last_change: Option<Instant>,
+    // The latest change that came too soon after it, applied once
+    // `min_command_interval_ms` is over
+    debounced: Option<Event>,
+    // In $XDG_RUNTIME_DIR, recreated if it is cleaned up under us
+    lock_path: PathBuf,
+    lock_file: File,
+    // `None` in a dry run
+    control_sock_path: Option<PathBuf>,
+}
+
+impl Daemon {
+    fn save_state(&self) {
+        if self.dry_run {
+            return;
+        }
+
+        if let Err(e) = self.state.save() {
+            error!("Failed to save state: {}", e);
+        };
+    }
+
+    // Every hyprsunset command goes through here, so failures are throttled
+    // and surfaced in one place.
+    fn check(&mut self, action: &str, result: Result<()>) -> bool {
+        match result {
+            Ok(_) => {
+                if self.errors.succeed() {
+                    self.tray.send(TrayUpdate::BackendUp);
+                    self.publish_status();
+                }
+                true
+            }
+            Err(e) => {
+                if self.errors.report(action, &e) {
+                    notify(
+                        &self.config.notifications,
+                        Notification {
+                            summary: "Can't reach hyprsunset — retrying".to_string(),
+                            body: format!("{:#}", e),
+                            icon: "dialog-warning",
+                            sound: false,
+                        },
+                    );
+                    self.tray.send(TrayUpdate::BackendDown);
+                    self.publish_status();
+                }
+                false
+            }
+        }
+    }
+
+    // Sends the current state to hyprsunset again
+    fn retry(&mut self) {
+        if !self.inhibitors.is_empty() {
+            self.apply_inhibit();
+            return;
+        }
+
+        match self.temperature {
+            Some(temperature) => self.set_temperature(temperature),
+            None => self.set_mode(Mode::Day),
+        };
+
+        if let Some(gamma) = self.get_mode_gamma() {
+            let result = self.client.set_gamma(gamma);
+            self.check("set gamma", result);
+        }
+    }
+
+    // The gamma of astronomy or reading mode, `None` outside of them
+    fn get_mode_gamma(&self) -> Option<i32> {
+        if self.astro {
+            Some(self.config.astro_gamma)
+        } else if self.reading {
+            Some(self.config.reading_gamma)
+        } else {
+            None
+        }
+    }
+
+    // What the scheduler would send right now. A script is only run by the
+    // scheduler, so its last decision stands in for it until the next poll.
+    fn get_scheduled_temperature(&self) -> Option<i32> {
+        match self.scheduled {
+            Some(temperature) if cfg!(feature = "scripting") && self.config.script.is_some() => {
+                temperature
+            }
+            _ => get_scheduled_temperature(&self.config, Utc::now()),
+        }
+    }
+
+    // While the process was stopped, the schedule may have moved on or
+    // hyprsunset may have been restarted
+    fn reconcile(&mut self) {
+        if self.state.automatic && self.get_mode_gamma().is_none() {
+            self.set_scheduled_temperature(self.get_scheduled_temperature());
+        } else {
+            self.retry();
+        }
+    }
+
+    // Turns the filter and any dimming off, while leaving the temperature and
+    // mode to go back to once the inhibitors are released
+    fn apply_inhibit(&mut self) {
+        let result = self.client.disable();
+        if self.check("disable blue light filter", result) {
+            info!("Successfully disabled blue light filter");
+        }
+        if self.get_mode_gamma().is_some() {
+            let result = self.client.set_gamma(100);
+            self.check("reset gamma", result);
+        }
+    }
+
+    fn inhibit(&mut self, id: u64, caller: String, reason: Option<String>) -> String {
+        match &reason {
+            Some(reason) => info!("Inhibited by {}: {}", caller, reason),
+            None => info!("Inhibited by {}", caller),
+        };
+
+        let first = self.inhibitors.is_empty();
+        self.inhibitors.push(Inhibitor { id, caller, reason });
+        if first {
+            self.apply_inhibit();
+        }
+        self.update_tray_status();
+
+        "inhibited".to_string()
+    }
+
+    fn release_inhibitor(&mut self, id: u64) {
+        let Some(i) = self
+            .inhibitors
+            .iter()
+            .position(|inhibitor| inhibitor.id == id)
+        else {
+            return;
+        };
+
+        let inhibitor = self.inhibitors.remove(i);
+        info!("Released the inhibitor of {}", inhibitor.caller);
+        if self.inhibitors.is_empty() {
+            self.reconcile();
+        }
+        self.update_tray_status();
+    }
+
+    // E.g. "obs (recording a talk), gimp"
+    fn get_inhibitors(&self) -> Vec<(String, Option<String>)> {
+        self.inhibitors
+            .iter()
+            .map(|inhibitor| (inhibitor.caller.clone(), inhibitor.reason.clone()))
+            .collect()
+    }
+
+    fn describe_inhibitors(&self) -> String {
+        format_inhibitors(&self.get_inhibitors())
+    }
+
+    // "Re-apply now", for when something outside reset the gamma or a
+    // monitor was plugged in. Unlike `reconcile`, a toggle that is still
+    // holding off the schedule is sent again too.
+    fn reapply(&mut self) {
+        if self.paused || self.previewing {
+            info!("Not applying the temperature again while paused or previewing");
+            return;
+        }
+
+        info!("Applying the temperature again");
+        match self.override_until {
+            Some(until) if Utc::now() < until => self.retry(),
+            _ => self.reconcile(),
+        };
+    }
+
+    fn set_mode(&mut self, mode: Mode) {
+        match mode {
+            Mode::Day => {
+                match self.config.day_temperature {
+                    Some(temperature) => {
+                        let result = self.client.enable(temperature);
+                        if self.check("set day temperature", result) {
+                            info!("Successfully set day temperature to {}K", temperature);
+                        }
+                    }
+                    None => {
+                        let result = self.client.disable();
+                        if self.check("disable blue light filter", result) {
+                            info!("Successfully disabled blue light filter");
+                        }
+                    }
+                };
+                self.temperature = None;
+                self.tray.send(TrayUpdate::Day);
+                self.update_tray_status();
+            }
+            Mode::Night => self.set_temperature(self.config.temperature),
+        };
+
+        if self.state.mode != Some(mode) {
+            self.state.mode = Some(mode);
+            self.save_state();
+        }
+    }
+
+    // What is sent to hyprsunset for `temperature`. Never cooler than the
+    // config asks for while winding down.
+    fn get_applied_temperature(&self, temperature: i32) -> i32 {
+        match (self.wind_down, self.config.wind_down_temperature) {
+            (true, Some(wind_down)) if self.get_mode_gamma().is_none() => {
+                temperature.min(wind_down)
+            }
+            _ => temperature,
+        }
+    }
+
+    fn set_temperature(&mut self, temperature: i32) {
+        let applied = self.get_applied_temperature(temperature);
+        let result = self.client.enable(applied);
+        if self.check("set blue light filter", result) {
+            info!("Successfully set blue light filter to {}K", applied);
+        }
+        self.temperature = Some(temperature);
+        self.tray.send(TrayUpdate::Night);
+        self.update_tray_status();
+    }
+
+    fn update_tray_status(&mut self) {
+        let status = self.describe_status_line();
+        self.tray.send(TrayUpdate::Status(status));
+        let temperature = self
+            .temperature
+            .map(|temperature| self.get_applied_temperature(temperature));
+        self.tray.send(TrayUpdate::Temperature(temperature));
+        self.tray.send(TrayUpdate::Paused(self.describe_pause()));
+        self.update_tray_almanac();
+        self.publish_status();
+    }
+
+    // Everything that changes the tray changes the status, so subscribers
+    // are told from here too
+    fn publish_status(&mut self) {
+        if self.subscribers.is_empty() {
+            return;
+        }
+
+        // These move with the clock, so they would make every call an event
+        let fields = self.describe_status();
+        let state: Vec<(&str, StatusValue)> = fields
+            .iter()
+            .filter(|(name, _)| !["sun_elevation", "last_success"].contains(name))
+            .cloned()
+            .collect();
+        if self.published.as_ref() == Some(&state) {
+            return;
+        }
+
+        // A subscriber that went away has dropped its receiver
+        let event = format_status_event(fields);
+        self.subscribers
+            .retain(|subscriber| subscriber.send(event.clone()).is_ok());
+        self.published = Some(state);
+    }
+
+    fn subscribe(&mut self, events: Sender<String>) {
+        debug!("Widget subscribed to the events");
+        let status = format_status_event(self.describe_status());
+        if events.send(format_hello()).is_ok() && events.send(status).is_ok() {
+            self.subscribers.push(events);
+        }
+    }
+
+    // During the day, today's sunrise and sunset. At night, the sunset that
+    // started it and the sunrise that ends it.
+    fn update_tray_almanac(&mut self) {
+        let now = Utc::now();
+        let next_sunrise = self.solar_table.get_next_event(&self.config, now, true);
+        let next_sunset = self.solar_table.get_next_event(&self.config, now, false);
+        let yesterday = now - TimeDelta::days(1);
+        let (sunrise, sunset) = if next_sunset < next_sunrise {
+            let sunrise = self
+                .solar_table
+                .get_next_event(&self.config, yesterday, true);
+            (sunrise, next_sunset)
+        } else {
+            let sunset = self
+                .solar_table
+                .get_next_event(&self.config, yesterday, false);
+            (next_sunrise, sunset)
+        };
+
+        let now = now.with_timezone(&Local);
+        let time_format = self.config.time_format;
+        self.tray.send(TrayUpdate::Almanac {
+            sunrise: format_almanac_row("Sunrise", sunrise.with_timezone(&Local), now, time_format),
+            sunset: format_almanac_row("Sunset", sunset.with_timezone(&Local), now, time_format),
+        });
+    }
+
+    // A pause, or a toggle or "Disable until tomorrow" keeping the filter
+    // off, as opposed to the schedule turning it off for the day
+    fn describe_pause(&self) -> Option<String> {
+        if self.paused {
+            return Some("Paused".to_string());
+        }
+        if !self.inhibitors.is_empty() {
+            return Some(format!("Inhibited by {}", self.describe_inhibitors()));
+        }
+
+        let now = Local::now();
+        let until = self
+            .override_until
+            .filter(|until| *until > now && self.temperature.is_none())?
+            .with_timezone(&Local);
+        let time = self.config.time_format.format(until.time());
+        Some(if until.date_naive() == now.date_naive() {
+            format!("Paused until {}", time)
+        } else if until.date_naive() == now.date_naive() + TimeDelta::days(1) {
+            format!("Paused until tomorrow at {}", time)
+        } else {
+            format!("Paused until {} at {}", until.format("%b %-d"), time)
+        })
+    }
+
+    fn set_scheduled_temperature(&mut self, temperature: Option<i32>) {
+        if self.get_mode_gamma().is_some() || !self.inhibitors.is_empty() {
+            return;
+        }
+
+        match self.override_until {
+            Some(until) if Utc::now() < until => return,
+            Some(_) => {
+                info!("Resuming the schedule");
+                self.override_until = None;
+            }
+            None => {}
+        };
+
+        let night = temperature.is_some();
+        if self
+            .scheduled_night
+            .is_some_and(|scheduled| scheduled != night)
+        {
+            self.notify_transition(temperature);
+        }
+        self.scheduled_night = Some(night);
+
+        match temperature {
+            Some(temperature) => self.set_temperature(temperature),
+            None => self.set_mode(Mode::Day),
+        };
+        self.record(Cause::Schedule);
+    }
+
+    fn change_temperature(&mut self, change: TemperatureChange) {
+        let temperature = match change {
+            TemperatureChange::Set(temperature) => temperature,
+            TemperatureChange::Adjust(delta) => {
+                // `temp +2147483647` mustn't overflow before the clamp
+                self.temperature
+                    .unwrap_or(IDENTITY_TEMPERATURE)
+                    .saturating_add(delta)
+            }
+        };
+
+        self.set_temperature(self.config.clamp_temperature(temperature));
+    }
+
+    fn set_automatic(&mut self, automatic: bool) {
+        self.automatic.store(automatic, Ordering::Release);
+        self.tray.send(TrayUpdate::Automatic(automatic));
+
+        if self.state.automatic == automatic {
+            self.update_tray_status();
+            return;
+        }
+
+        self.override_until = None;
+        self.update_tray_status();
+
+        info!(
+            "Switched to {} mode",
+            if automatic { "automatic" } else { "manual" }
+        );
+
+        self.state.automatic = automatic;
+
+        // Catch up with the schedule straight away instead of waiting for
+        // the next sunrise or sunset. When switching to manual mode, whatever
+        // is applied right now becomes the selection that sticks.
+        if automatic {
+            self.set_scheduled_temperature(self.get_scheduled_temperature());
+        } else if self.temperature.is_some() {
+            self.state.mode = Some(Mode::Night);
+        } else {
+            self.state.mode = Some(Mode::Day);
+        }
+
+        self.save_state();
+    }
+
+    // Leaves astronomy mode without restoring the previous state, for when
+    // a manual selection replaces it anyway.
+    fn reset_astro(&mut self) {
+        if !self.astro {
+            return;
+        }
+
+        self.astro = false;
+        let result = self.client.set_gamma(100);
+        if self.check("reset gamma", result) {
+            info!("Astronomy mode off");
+        }
+        self.tray.send(TrayUpdate::Astro(false));
+    }
+
+    fn reset_reading(&mut self) {
+        if !self.reading {
+            return;
+        }
+
+        self.reading = false;
+        let result = self.client.set_gamma(100);
+        if self.check("reset gamma", result) {
+            info!("Reading mode off");
+        }
+        self.tray.send(TrayUpdate::Reading(false));
+    }
+
+    // For manual selections, which end either mode
+    fn reset_modes(&mut self) {
+        self.reset_astro();
+        self.reset_reading();
+    }
+
+    // Back to whatever we would be doing without astronomy or reading mode
+    fn restore_mode(&mut self) {
+        if self.state.automatic {
+            self.set_scheduled_temperature(self.get_scheduled_temperature());
+        } else if let Some(mode) = self.state.mode {
+            self.set_mode(mode);
+            self.record(Cause::Manual);
+        }
+    }
+
+    fn set_astro(&mut self, astro: bool) {
+        if self.astro == astro {
+            self.tray.send(TrayUpdate::Astro(astro));
+            return;
+        }
+
+        if astro {
+            self.reset_reading();
+            self.set_temperature(self.config.astro_temperature);
+            let result = self.client.set_gamma(self.config.astro_gamma);
+            if self.check("set gamma", result) {
+                info!("Astronomy mode on");
+            }
+            self.astro = true;
+            self.tray.send(TrayUpdate::Astro(true));
+            self.record(Cause::Astro);
+            return;
+        }
+
+        self.reset_astro();
+        self.restore_mode();
+    }
+
+    // Like astronomy mode, but for reading at any time of day
+    fn set_reading(&mut self, reading: bool) {
+        if self.reading == reading {
+            self.tray.send(TrayUpdate::Reading(reading));
+            return;
+        }
+
+        if !reading {
+            self.reset_reading();
+            self.restore_mode();
+            return;
+        }
+
+        self.reset_astro();
+        self.set_temperature(self.config.reading_temperature);
+        let result = self.client.set_gamma(self.config.reading_gamma);
+        if self.check("set gamma", result) {
+            info!("Reading mode on");
+        }
+        self.reading = true;
+        self.tray.send(TrayUpdate::Reading(true));
+        self.record(Cause::Reading);
+    }
+
+    // Applies `temperature` without making it the current state, which is
+    // restored after `duration`
+    fn preview(&mut self, temperature: i32, duration: Duration) -> String {
+        let temperature = self.config.clamp_temperature(temperature);
+        let result = self.client.enable(temperature);
+        if !self.check("preview temperature", result) {
+            return "error: failed to reach hyprsunset".to_string();
+        }
+
+        self.preview += 1;
+        self.previewing = true;
+        let preview = self.preview;
+        let main_tx = self.main_tx.clone();
+        thread::spawn(move || {
+            thread::sleep(duration);
+            // The main loop may be gone already
+            let _ = main_tx.send(Event::PreviewEnded(preview));
+        });
+
+        info!("Previewing {}K for {:?}", temperature, duration);
+        format!("previewing {}K for {}s", temperature, duration.as_secs())
+    }
+
+    fn end_preview(&mut self, preview: u64) {
+        if preview == self.preview && self.previewing {
+            info!("Preview ended");
+            self.previewing = false;
+            self.retry();
+        }
+    }
+
+    // Warms up the screen before it locks at night. The current state is kept
+    // underneath and comes back with `wind-down off`. Does nothing during
+    // the day.
+    fn set_wind_down(&mut self, wind_down: bool) -> Result<()> {
+        if self.config.wind_down_temperature.is_none() {
+            return Err(anyhow!("wind_down_temperature is not set"));
+        }
+
+        if self.wind_down == wind_down {
+            return Ok(());
+        }
+
+        self.wind_down = wind_down;
+        info!(
+            "Winding down {}",
+            if wind_down { "started" } else { "ended" }
+        );
+        // Only the flag while paused or inhibited, it takes effect once
+        // the screen is handed back
+        if self.paused || !self.inhibitors.is_empty() {
+            return Ok(());
+        }
+
+        if let Some(temperature) = self.temperature {
+            self.set_temperature(temperature);
+        }
+        self.record(Cause::WindDown);
+
+        Ok(())
+    }
+
+    // Some lockers reset the gamma tables, so whatever was on screen before
+    // is sent again
+    fn unlocked(&mut self) {
+        if self.paused {
+            return;
+        }
+
+        info!("Screen unlocked, applying the temperature again");
+        // Whatever asked to wind down is done with the evening
+        let wound_down = std::mem::take(&mut self.wind_down);
+        self.retry();
+        if wound_down {
+            self.record(Cause::WindDown);
+        }
+    }
+
+    // Applies the state again if hyprsunset drifted away from it, e.g. because
+    // another tool reset the filter. Between transitions nothing else talks
+    // to hyprsunset, so this is also what notices it went away.
+    fn watchdog(&mut self) {
+        let inhibited = !self.inhibitors.is_empty();
+        if self.paused || self.previewing || inhibited || self.errors.is_failing() {
+            return;
+        }
+
+        let actual = match self.client.get_temperatures() {
+            Ok(actual) => actual,
+            // The socket itself failing turns the tray icon into the warning
+            // and starts the retries, while a backend that can't be queried
+            // is left alone
+            Err(e) if e.downcast_ref::<BackendError>().is_some() => {
+                self.check("query hyprsunset", Err(e));
+                return;
+            }
+            Err(e) => {
+                debug!("Skipping the watchdog: {:#}", e);
+                return;
+            }
+        };
+
+        // There is no telling identity apart from a temperature, so only an
+        // enabled filter is checked
+        let expected = match (self.temperature, self.config.day_temperature) {
+            (Some(temperature), _) => self.get_applied_temperature(temperature),
+            (None, Some(temperature)) => temperature,
+            (None, None) => return,
+        };
+
+        if let Some(actual) = actual.iter().find(|actual| **actual != expected) {
+            warn!(
+                "hyprsunset is at {}K instead of {}K, applying it again",
+                actual, expected
+            );
+            self.retry();
+        }
+    }
+
+    // Brings back a thread that panicked. Returns false if the daemon should
+    // shut down instead.
+    fn restart_worker(&mut self, worker: Worker, message: String) -> bool {
+        self.worker_failures += 1;
+        self.last_worker_failure = Some(format!("{}: {}", worker, message));
+        if self.worker_failures >= MAX_WORKER_FAILURES {
+            error!("Too many threads died, shutting down");
+            return false;
+        }
+
+        let main_tx = self.main_tx.clone();
+        match worker {
+            Worker::Scheduler => {
+                self.scheduler_tx =
+                    spawn_scheduler(self.config.clone(), self.automatic.clone(), main_tx)
+            }
+            // GTK can only be initialized once, so carry on without it
+            Worker::Tray => {
+                warn!("Continuing without the tray icon");
+                return true;
+            }
+            Worker::Control => {
+                if let Err(e) = control::setup_control_socket(main_tx) {
+                    error!("Failed to set up control socket again: {:#}", e);
+                    return false;
+                }
+            }
+            Worker::NetworkWatcher => spawn_network_watcher(main_tx),
+            Worker::TimezoneWatcher => spawn_timezone_watcher(main_tx),
+            Worker::UnlockWatcher => {
+                spawn_unlock_watcher(self.config.screen_lockers.clone(), main_tx)
+            }
+            Worker::MonitorWatcher => spawn_monitor_watcher(main_tx),
+            Worker::Watchdog => spawn_watchdog(main_tx),
+        };
+
+        warn!("Restarted the {} thread", worker);
+        true
+    }
+
+    // Restarts the scheduler with a freshly loaded config, e.g. after moving
+    // to another network. Keeps the old config if the new one fails to load.
+    fn reload(&mut self) {
+        match load_config(self.ssid.as_deref(), self.state.profile.as_deref()) {
+            Ok(config) => {
+                self.config = config;
+                self.solar_table.clear();
+                self.tray.send(TrayUpdate::LocationUnset(
+                    self.config.has_default_location(),
+                ));
+                self.update_tray_profiles();
+            }
+            Err(e) => {
+                error!("Failed to reload config: {:#}", e);
+                return;
+            }
+        };
+
+        if self.scheduler_tx.send(()).is_err() {
+            error!("Failed to stop scheduler");
+        };
+        self.scheduler_tx = spawn_scheduler(
+            self.config.clone(),
+            self.automatic.clone(),
+            self.main_tx.clone(),
+        );
+    }
+
+    fn update_tray_profiles(&mut self) {
+        self.tray.send(TrayUpdate::Profiles {
+            names: self.config.profiles.keys().cloned().collect(),
+            picked: self.state.profile.clone(),
+            active: self.config.active_profile.clone(),
+        });
+    }
+
+    // Applies the profile picked from the tray straight away, instead of the
+    // one for the network. `None` goes back to picking by network.
+    fn select_profile(&mut self, profile: Option<String>) {
+        if self.state.profile == profile {
+            self.update_tray_profiles();
+            return;
+        }
+
+        match &profile {
+            Some(profile) => info!("Switched to profile {}", profile),
+            None => info!("Picking the profile by network"),
+        };
+        self.state.profile = profile;
+        self.save_state();
+        self.reload();
+    }
+
+    // Someone else's sunset is worse than a warning, so the default location
+    // is pointed out until it is changed
+    fn check_location(&mut self) {
+        let unset = self.config.has_default_location();
+        self.tray.send(TrayUpdate::LocationUnset(unset));
+        if !unset || self.dry_run {
+            return;
+        }
+
+        warn!("Using the default location, Nairobi. Run `hyprsunset-overdrive setup` to change it");
+        notify(
+            &self.config.notifications,
+            Notification {
+                summary: "Location not configured — schedule may be wrong".to_string(),
+                body: "Sunset and sunrise are those of Nairobi. Pick your location from the tray menu."
+                    .to_string(),
+                icon: "dialog-warning",
+                sound: false,
+            },
+        );
+    }
+
+    // The solar schedule is kept in UTC, but schedule points, focus blocks
+    // and the wake time are on the clock. When daylight saving time starts
+    // or ends, the scheduler is restarted to pick up the new offset, and
+    // the user is told that the switch moved.
+    fn check_clock_change(&mut self) {
+        let offset = Local::now().offset().fix();
+        let Some(change) = describe_clock_change(self.utc_offset, offset) else {
+            return;
+        };
+        self.utc_offset = offset;
+
+        info!("Clocks went {}, restarting the scheduler", change);
+        self.reload();
+
+        if self.dry_run || !self.config.notifications.clock_change {
+            return;
+        }
+
+        // The filter normally goes on at sunset
+        let sunrise = self.config.invert_schedule;
+        let next = self
+            .solar_table
+            .get_next_event(&self.config, Utc::now(), sunrise)
+            .with_timezone(&Local);
+        notify(
+            &self.config.notifications,
+            Notification {
+                summary: format!("Clocks went {}", change),
+                body: format!(
+                    "{} is at {} on the new clock.",
+                    if sunrise { "Sunrise" } else { "Sunset" },
+                    self.config.time_format.format(next.time())
+                ),
+                icon: "appointment-soon",
+                sound: false,
+            },
+        );
+    }
+
+    // Some session managers clean or remount $XDG_RUNTIME_DIR, taking the
+    // lock and the control socket with it. Without them, a second instance
+    // could start and the CLI couldn't reach us, so both are created again.
+    fn check_runtime_files(&mut self) {
+        if !self.lock_path.exists() {
+            warn!("The lock file is gone, creating it again");
+            match acquire_lock(&self.lock_path) {
+                Ok(lock_file) => self.lock_file = lock_file,
+                Err(e) => error!("Failed to acquire lock again: {:#}", e),
+            };
+        }
+
+        if self
+            .control_sock_path
+            .as_ref()
+            .is_some_and(|path| !path.exists())
+        {
+            warn!("The control socket is gone, creating it again");
+            match control::setup_control_socket(self.main_tx.clone()) {
+                Ok(path) => self.control_sock_path = Some(path),
+                Err(e) => error!("Failed to set up control socket again: {:#}", e),
+            };
+        }
+    }
+
+    // Leaves the screen in the `on_exit` state. With "keep", a transition is
+    // finished at once instead of leaving the screen at an arbitrary point of
+    // the fade.
+    fn apply_exit_state(&mut self) {
+        let now = Utc::now();
+        let temperature = match self.config.on_exit {
+            ExitState::Identity => None,
+            ExitState::Night => Some(self.config.temperature),
+            ExitState::Keep => {
+                let scheduled = self.state.automatic
+                    && self.get_mode_gamma().is_none()
+                    && !self.previewing
+                    && self.override_until.is_none_or(|until| until <= now)
+                    && is_in_transition(&self.config, now);
+                if !scheduled {
+                    return;
+                }
+
+                let end = now + get_duration_to_next_change(&self.config, now);
+                get_scheduled_temperature(&self.config, end).or(self.config.day_temperature)
+            }
+        };
+
+        if self.get_mode_gamma().is_some() {
+            let result = self.client.set_gamma(100);
+            self.check("reset gamma", result);
+        }
+        let result = match temperature {
+            Some(temperature) => self
+                .client
+                .enable(self.get_applied_temperature(temperature)),
+            None => self.client.disable(),
+        };
+        if self.check("apply the exit state", result) {
+            info!("Left the filter at {}", format_temperature(temperature));
+        }
+    }
+
+    // Brings up the location picker, and loads the config it writes
+    fn set_location(&mut self) {
+        let main_tx = self.main_tx.clone();
+        thread::spawn(move || match run_setup() {
+            Ok(_) => {
+                // Only fails while shutting down
+                let _ = main_tx.send(Event::ConfigChanged(ConfigChange::Location));
+            }
+            Err(e) => error!("{:#}", e),
+        });
+    }
+
+    fn open_log(&mut self) {
+        // Only exists if this run, or the last one, logged to it
+        let log_file = paths::get_log_dir()
+            .map(|dir| dir.join(LOG_FILE_NAME))
+            .ok()
+            .filter(|path| self.config.log_to_file && path.exists());
+        let terminal = std::env::var("TERMINAL").ok();
+        let command =
+            get_open_log_command(log_file.as_deref(), std::process::id(), terminal.as_deref());
+
+        // Waited for in the background, so it doesn't linger as a zombie
+        thread::spawn(
+            move || match Command::new(&command[0]).args(&command[1..]).status() {
+                Ok(status) if status.success() => {}
+                Ok(status) => error!("{} exited with {}", command[0], status),
+                Err(e) => error!("Failed to run {}: {}", command[0], e),
+            },
+        );
+    }
+
+    fn notify_transition(&mut self, temperature: Option<i32>) {
+        if self.dry_run {
+            return;
+        }
+
+        let settings = &self.config.notifications;
+        let wanted = match temperature {
+            Some(_) => settings.evening,
+            None => settings.morning,
+        };
+        if !wanted {
+            return;
+        }
+
+        let now = Utc::now();
+        // The filter normally goes on at sunset and lasts until sunrise
+        let sunrise = temperature.is_some() != self.config.invert_schedule;
+        let next = self.solar_table.get_next_event(&self.config, now, sunrise);
+        let name = if sunrise { "Sunrise" } else { "Sunset" };
+
+        let notification = get_transition_message(
+            temperature,
+            (name, next.with_timezone(&Local)),
+            now.with_timezone(&Local),
+            self.config.time_format,
+        );
+        notify(&self.config.notifications, notification);
+    }
+
+    // "Night mode in 15 minutes", with a button to put it off
+    fn notify_countdown(&mut self, at: DateTime<Utc>) {
+        let overridden = self.override_until.is_some_and(|until| until > at);
+        if self.dry_run || overridden || self.get_mode_gamma().is_some() {
+            return;
+        }
+
+        let minutes = ((at - Utc::now()).num_seconds() + 59) / 60;
+        let notification = Notification {
+            summary: format!("Night mode in {} minutes", minutes.max(1)),
+            body: format!(
+                "The filter turns on at {}.",
+                self.config
+                    .time_format
+                    .format(at.with_timezone(&Local).time())
+            ),
+            icon: "weather-clear-night",
+            sound: false,
+        };
+        let main_tx = self.main_tx.clone();
+        notify_with_action(
+            &self.config.notifications,
+            notification,
+            "Delay 1h",
+            move || {
+                // The main loop may be gone already
+                let _ = main_tx.send(Event::Postpone);
+            },
+        );
+    }
+
+    // Keeps the schedule from turning the filter on until an hour after it
+    // would have, or an hour from now if it already has
+    fn postpone(&mut self) {
+        if !self.state.automatic {
+            return;
+        }
+
+        let now = Utc::now();
+        let from = match self.temperature {
+            Some(_) => now,
+            None => now + get_duration_to_next_change(&self.config, now),
+        };
+        let until = from + POSTPONE_DURATION;
+        info!(
+            "Postponed until {}",
+            until
+                .with_timezone(&Local)
+                .format(&self.config.log_time_format)
+        );
+
+        self.override_until = Some(until);
+        if self.temperature.is_some() {
+            self.select_mode(Mode::Day);
+        }
+        self.update_tray_status();
+
+        // The scheduler only wakes up for the next transition. Sleeps don't
+        // count time in suspend, so the wall clock is checked every minute.
+        let main_tx = self.main_tx.clone();
+        thread::spawn(move || {
+            while let Ok(remaining) = (until - Utc::now()).to_std() {
+                thread::sleep(remaining.min(Duration::from_secs(60)));
+            }
+            // The main loop may be gone already
+            let _ = main_tx.send(Event::OverrideEnded(until));
+        });
+    }
+
+    fn end_override(&mut self, until: DateTime<Utc>) {
+        if self.override_until == Some(until) && !self.paused {
+            self.reconcile();
+        }
+    }
+
+    // Flips the filter without leaving automatic mode. The scheduler takes
+    // over again at the next transition.
+    fn toggle(&mut self) -> Mode {
+        let mode = match self.temperature {
+            Some(_) => Mode::Day,
+            None => Mode::Night,
+        };
+
+        if self.state.automatic {
+            let now = Utc::now();
+            let until = now + get_duration_to_next_change(&self.config, now);
+            info!(
+                "Toggled until {}",
+                until
+                    .with_timezone(&Local)
+                    .format(&self.config.log_time_format)
+            );
+            self.override_until = Some(until);
+        }
+
+        self.select_mode(mode);
+        mode
+    }
+
+    // Turns the filter off and keeps it off until the next sunrise, in
+    // automatic mode, so the schedule takes over from there
+    fn disable_until_sunrise(&mut self) {
+        let now = Utc::now();
+        let until = self.solar_table.get_next_event(&self.config, now, true);
+        info!(
+            "Disabled until {}",
+            until
+                .with_timezone(&Local)
+                .format(&self.config.log_time_format)
+        );
+
+        self.select_mode(Mode::Day);
+        // Not through `set_automatic`, which would apply the schedule first
+        if !self.state.automatic {
+            self.state.automatic = true;
+            self.automatic.store(true, Ordering::Release);
+            self.tray.send(TrayUpdate::Automatic(true));
+            self.save_state();
+        }
+        self.override_until = Some(until);
+        self.update_tray_status();
+    }
+
+    // Steps to the entry after the current temperature in `cycle`, or to the
+    // first one if the current temperature isn't in there
+    fn cycle(&mut self) -> Result<Option<i32>> {
+        let cycle = &self.config.cycle;
+        if cycle.is_empty() {
+            return Err(anyhow!("cycle is empty"));
+        }
+
+        let next = match cycle.iter().position(|entry| *entry == self.temperature) {
+            Some(i) => cycle[(i + 1) % cycle.len()],
+            None => cycle[0],
+        };
+
+        match next {
+            Some(temperature) => {
+                self.reset_modes();
+                self.change_temperature(TemperatureChange::Set(temperature));
+                self.record(Cause::Manual);
+            }
+            None => self.select_mode(Mode::Day),
+        };
+
+        Ok(self.temperature)
+    }
+
+    fn select_mode(&mut self, mode: Mode) {
+        self.reset_modes();
+        self.set_mode(mode);
+        self.record(Cause::Manual);
+    }
+
+    // Appends to the history when what is on screen changed
+    fn record(&mut self, cause: Cause) {
+        if self.dry_run {
+            return;
+        }
+
+        let applied = match self.temperature {
+            Some(temperature) => Some(self.get_applied_temperature(temperature)),
+            None => self.config.day_temperature,
+        };
+        if self.recorded == Some(applied) {
+            return;
+        }
+
+        self.recorded = Some(applied);
+        if let Err(e) = history::record(cause, applied) {
+            error!("Failed to record history: {:#}", e);
+        };
+    }
+
+    // Schedule points are pinned to UTC, but shown in local time
+    fn format_point_time(&self, point: &ResolvedPoint) -> String {
+        let time = Utc::now().date_naive().and_time(point.time).and_utc();
+        self.config
+            .time_format
+            .format(time.with_timezone(&Local).time())
+    }
+
+    fn describe_location(&self) -> String {
+        let config = &self.config;
+        let now = Utc::now();
+        let local_time = |time: NaiveTime| {
+            let time = now.date_naive().and_time(time).and_utc();
+            config.time_format.format(time.with_timezone(&Local).time())
+        };
+
+        let (sunrise, sunset) = get_sunrise_and_sunset(
+            config.latitude,
+            config.longitude,
+            config.altitude,
+            now.year(),
+            now.month(),
+            now.day(),
+        );
+
+        let mut lines = vec![
+            format!(
+                "location: {}, {} ({})",
+                config.latitude,
+                config.longitude,
+                config.get_location_provider()
+            ),
+            format!("altitude: {}m", config.altitude),
+        ];
+
+        let twilight = get_civil_twilight(
+            config.latitude,
+            config.longitude,
+            config.altitude,
+            now.date_naive(),
+        )
+        .ok();
+        if let Some((dawn, _)) = twilight {
+            lines.push(format!("dawn: {}", local_time(dawn)));
+        }
+        lines.push(format!("sunrise: {}", local_time(sunrise)));
+        lines.push(format!("sunset: {}", local_time(sunset)));
+        if let Some((_, dusk)) = twilight {
+            lines.push(format!("dusk: {}", local_time(dusk)));
+        }
+
+        lines.push(format!(
+            "elevation: {:.1}°",
+            get_solar_elevation(config.latitude, config.longitude, now)
+        ));
+
+        lines.join("\n")
+    }
+
+    fn describe_status(&self) -> Vec<(&'static str, StatusValue)> {
+        let stats = self.errors.get_stats();
+        let local_time = |time: DateTime<Utc>| {
+            let time = time.with_timezone(&Local);
+            format!(
+                "{} {}",
+                time.format("%Y-%m-%d"),
+                self.config.time_format.format(time.time())
+            )
+        };
+
+        vec![
+            ("automatic", StatusValue::Flag(self.state.automatic)),
+            ("night", StatusValue::Flag(self.temperature.is_some())),
+            ("temperature", StatusValue::Temperature(self.temperature)),
+            (
+                "sun_elevation",
+                StatusValue::Angle(get_solar_elevation(
+                    self.config.latitude,
+                    self.config.longitude,
+                    Utc::now(),
+                )),
+            ),
+            ("astro", StatusValue::Flag(self.astro)),
+            ("reading", StatusValue::Flag(self.reading)),
+            ("tray_icon", StatusValue::Flag(!self.tray.is_gone())),
+            ("wind_down", StatusValue::Flag(self.wind_down)),
+            ("inhibitors", StatusValue::Inhibitors(self.get_inhibitors())),
+            ("reachable", StatusValue::Flag(!self.errors.is_failing())),
+            ("failed_commands", StatusValue::Number(stats.failed)),
+            ("retried_commands", StatusValue::Number(stats.retried)),
+            (
+                "last_error",
+                match &stats.last_error {
+                    Some(error) => StatusValue::Text(error.clone()),
+                    None => StatusValue::Missing,
+                },
+            ),
+            ("thread_failures", StatusValue::Number(self.worker_failures)),
+            (
+                "last_thread_failure",
+                match &self.last_worker_failure {
+                    Some(failure) => StatusValue::Text(failure.clone()),
+                    None => StatusValue::Missing,
+                },
+            ),
+            (
+                "last_success",
+                match stats.last_success {
+                    Some(time) => StatusValue::Text(local_time(time)),
+                    None => StatusValue::Missing,
+                },
+            ),
+        ]
+    }
+
+    // The `status_format` line. Without the schedule there is no next
+    // transition to show.
+    fn describe_status_line(&self) -> String {
+        let now = Utc::now();
+        let points = get_schedule_points(&self.config, now);
+        let until = match get_next_point(&points, now.time()) {
+            Some(point) if self.state.automatic => Some(self.format_point_time(point)),
+            _ => None,
         };
 
-        let tray_icon = match TrayIconBuilder::new().with_menu(Box::new(menu)).build() {
-            Ok(tray_icon) => tray_icon,
-            Err(e) => {
-                error!("Failed to build tray icon: {}", e);
-                return;
-            }
+        format_status_line(
+            &self.config.status_format,
+            self.temperature,
+            until.as_deref(),
+        )
+    }
+
+    // `version --verbose`, the config and the status, for a bug report
+    fn describe_diagnostics(&self) -> String {
+        let config_path = match Config::get_path() {
+            Ok(path) => path.display().to_string(),
+            Err(e) => format!("{:#}", e),
+        };
+        let now = Utc::now();
+        let points = get_schedule_points(&self.config, now);
+        let next = match get_next_point(&points, now.time()) {
+            Some(point) => self.format_point_time(point),
+            None => "none".to_string(),
+        };
+
+        [
+            describe_version(true),
+            format!("config: {}", config_path),
+            format!("location: {}", self.config.get_location_provider()),
+            format_status(&self.describe_status(), false),
+            format!("next transition: {}", next),
+        ]
+        .join("\n")
+    }
+
+    // Through `wl-copy`, which is around wherever Wayland clipboards are
+    fn copy_status(&mut self) {
+        let diagnostics = self.describe_diagnostics();
+        thread::spawn(move || {
+            let result = Command::new("wl-copy")
+                .stdin(Stdio::piped())
+                .spawn()
+                .and_then(|mut child| {
+                    if let Some(mut stdin) = child.stdin.take() {
+                        stdin.write_all(diagnostics.as_bytes())?;
+                    }
+                    child.wait()
+                });
+            match result {
+                Ok(status) if status.success() => info!("Copied the status to the clipboard"),
+                Ok(status) => error!("wl-copy exited with {}", status),
+                Err(e) => error!("Failed to run wl-copy: {}", e),
+            };
+        });
+    }
+
+    // What the scheduler would pick at `at`, for checking schedule points,
+    // offsets and days without a sunset
+    fn describe_schedule_at(&self, at: DateTime<Utc>) -> Vec<(&'static str, StatusValue)> {
+        let config = &self.config;
+        let (sunrise, sunset) = get_solar_times(config, at);
+        let local_time = |time: NaiveTime| {
+            let time = at.date_naive().and_time(time).and_utc();
+            StatusValue::Text(config.time_format.format(time.with_timezone(&Local).time()))
         };
+        let temperature = get_scheduled_temperature(config, at);
+
+        vec![
+            (
+                "at",
+                StatusValue::Text(
+                    at.with_timezone(&Local)
+                        .format("%Y-%m-%d %H:%M %Z")
+                        .to_string(),
+                ),
+            ),
+            ("sunrise", local_time(sunrise)),
+            ("sunset", local_time(sunset)),
+            (
+                "sun_elevation",
+                StatusValue::Angle(get_solar_elevation(config.latitude, config.longitude, at)),
+            ),
+            ("night", StatusValue::Flag(temperature.is_some())),
+            ("temperature", StatusValue::Temperature(temperature)),
+            (
+                "transition",
+                StatusValue::Flag(is_in_transition(config, at)),
+            ),
+        ]
+    }
 
-        if let Err(e) = tray_icon.set_icon(Some(icon)) {
-            error!("Failed to set icon: {}", e);
-            return;
+    // Returns the change if it may go ahead. One that follows the last
+    // within `min_command_interval_ms` is held back until the interval is
+    // over, and replaced by any that come after it, so a flood of toggles
+    // doesn't hammer hyprsunset or make the screen flicker but the last one
+    // still counts.
+    fn debounce(&mut self, event: Event) -> Option<Event> {
+        let interval = Duration::from_millis(self.config.min_command_interval_ms);
+        let now = Instant::now();
+        let remaining = match self.last_change {
+            Some(last) => interval.saturating_sub(now.duration_since(last)),
+            None => Duration::ZERO,
         };
+        if remaining.is_zero() {
+            self.last_change = Some(now);
+            return Some(event);
+        }
 
-        MenuEvent::set_event_handler(Some(move |event: MenuEvent| match event.id().as_ref() {
-            "enabled" => {
-                main_tx.send(Message::Night).unwrap();
+        debug!("Holding back a change that came too soon after the last");
+        match self.debounced.replace(event) {
+            Some(Event::IpcRequest { reply, .. }) => {
+                // The client may have given up already
+                let _ = reply.send("error: Replaced by a later command".to_string());
             }
-            "disabled" => {
-                main_tx.send(Message::Day).unwrap();
+            Some(_) => {}
+            None => {
+                let main_tx = self.main_tx.clone();
+                thread::spawn(move || {
+                    thread::sleep(remaining);
+                    // The main loop may be gone already
+                    let _ = main_tx.send(Event::DebounceEnded);
+                });
             }
-            _ => {}
-        }));
+        };
 
-        // Source: https://github.com/PlugOvr-ai/PlugOvr/blob/273d7ea0f00a725db5b40838e497bd3ecfe2c95e/src/ui/user_interface.rs#L313
-        glib::timeout_add_local(Duration::from_millis(100), move || {
-            while let Ok(message) = rx.try_recv() {
-                match message {
-                    Message::Night => {
-                        let enabled_icon = match convert_bytes_to_icon(ENABLED_ICON_BYTES) {
-                            Ok(icon) => icon,
-                            Err(e) => {
-                                error!("Failed to convert bytes to icon: {}", e);
-                                return glib::ControlFlow::Break;
-                            }
-                        };
-                        if let Err(e) = tray_icon.set_icon(Some(enabled_icon)) {
-                            error!("Failed to set icon: {}", e);
-                            return glib::ControlFlow::Break;
-                        };
-                        enable_item.set_enabled(false);
-                        disable_item.set_enabled(true);
+        None
+    }
+
+    fn handle_request(&mut self, request: Request) -> String {
+        let on_off = |enabled: bool| if enabled { "on" } else { "off" }.to_string();
+
+        match request {
+            Request::Automatic(None) => on_off(self.state.automatic),
+            Request::Automatic(Some(automatic)) => {
+                self.set_automatic(automatic);
+                on_off(self.state.automatic)
+            }
+            Request::ToggleAutomatic => {
+                self.set_automatic(!self.state.automatic);
+                on_off(self.state.automatic)
+            }
+            Request::Day => {
+                self.select_mode(Mode::Day);
+                "day".to_string()
+            }
+            Request::Night => {
+                self.select_mode(Mode::Night);
+                "night".to_string()
+            }
+            Request::Cycle => match self.cycle() {
+                Ok(temperature) => format_temperature(temperature),
+                Err(e) => format!("error: {}", e),
+            },
+            Request::Toggle => match self.toggle() {
+                Mode::Day => "day".to_string(),
+                Mode::Night => "night".to_string(),
+            },
+            Request::Temperature(change) => {
+                if let Some(change) = change {
+                    self.reset_modes();
+                    self.change_temperature(change);
+                    self.record(Cause::Manual);
+                }
+                match self.temperature {
+                    Some(temperature) => temperature.to_string(),
+                    None => "identity".to_string(),
+                }
+            }
+            Request::Schedule => get_schedule_points(&self.config, Utc::now())
+                .iter()
+                .map(|point| {
+                    let time = self.format_point_time(point);
+                    match &point.label {
+                        Some(label) => format!(
+                            "{} {} -> {}",
+                            time,
+                            label,
+                            format_temperature(point.temperature)
+                        ),
+                        None => format!("{} -> {}", time, format_temperature(point.temperature)),
                     }
-                    Message::Day => {
-                        let disabled_icon = match convert_bytes_to_icon(DISABLED_ICON_BYTES) {
-                            Ok(icon) => icon,
-                            Err(e) => {
-                                error!("Failed to convert bytes to icon: {}", e);
-                                return glib::ControlFlow::Break;
-                            }
-                        };
-                        if let Err(e) = tray_icon.set_icon(Some(disabled_icon)) {
-                            error!("Failed to set icon: {}", e);
-                            return glib::ControlFlow::Break;
-                        };
-
-                        enable_item.set_enabled(true);
-                        disable_item.set_enabled(false);
+                })
+                .collect::<Vec<String>>()
+                .join("\n"),
+            Request::Next => {
+                let now = Utc::now();
+                let points = get_schedule_points(&self.config, now);
+                let point = match get_next_point(&points, now.time()) {
+                    Some(point) => point,
+                    None => return "error: no transitions scheduled".to_string(),
+                };
+
+                let time = self.format_point_time(point);
+                let time = match &point.label {
+                    Some(label) => format!("{} {}", label, time),
+                    None => time,
+                };
+                let duration =
+                    Duration::from_secs(get_seconds_between(now.time(), point.time) as u64);
+
+                format!(
+                    "{} (in {}) -> {}",
+                    time,
+                    format_duration(duration),
+                    format_temperature(point.temperature)
+                )
+            }
+            Request::Where => self.describe_location(),
+            Request::Preview(temperature, duration) => self.preview(temperature, duration),
+            Request::Status { at: None, json } => format_status(&self.describe_status(), json),
+            Request::StatusLine => self.describe_status_line(),
+            Request::Status { at: Some(at), json } => {
+                format_status(&self.describe_schedule_at(at), json)
+            }
+            Request::Inhibit { id, caller, reason } => self.inhibit(id, caller, reason),
+            // Handed to the main loop as `Event::Subscribe` instead
+            Request::Subscribe(_) => "error: subscribe needs its own connection".to_string(),
+            Request::PreviewOff => {
+                self.end_preview(self.preview);
+                on_off(false)
+            }
+            // Deliberately not checked, a typo shouldn't count as hyprsunset
+            // being down
+            Request::Raw(command) => match self.client.query(&command) {
+                Ok(replies) => replies
+                    .iter()
+                    .map(|reply| reply.trim())
+                    .collect::<Vec<&str>>()
+                    .join("\n"),
+                Err(e) => format!("error: {:#}", e),
+            },
+            Request::Unlocked => {
+                self.unlocked();
+                if self.paused { "paused" } else { "applied" }.to_string()
+            }
+            Request::WindDown(None) => on_off(self.wind_down),
+            Request::WindDown(Some(wind_down)) => match self.set_wind_down(wind_down) {
+                Ok(_) => on_off(self.wind_down),
+                Err(e) => format!("error: {}", e),
+            },
+            Request::Astro(None) => on_off(self.astro),
+            Request::Astro(Some(astro)) => {
+                self.set_astro(astro);
+                on_off(self.astro)
+            }
+            Request::ToggleAstro => {
+                self.set_astro(!self.astro);
+                on_off(self.astro)
+            }
+            Request::Reading(None) => on_off(self.reading),
+            Request::Reading(Some(reading)) => {
+                self.set_reading(reading);
+                on_off(self.reading)
+            }
+            Request::ToggleReading => {
+                self.set_reading(!self.reading);
+                on_off(self.reading)
+            }
+        }
+    }
+
+    // Returns false once the daemon should shut down
+    fn handle(&mut self, event: Event) -> bool {
+        let change = match &event {
+            Event::Tray(action) => action.is_change(),
+            Event::IpcRequest { request, .. } => request.is_change(),
+            _ => false,
+        };
+        let event = if change {
+            match self.debounce(event) {
+                Some(event) => event,
+                None => return true,
+            }
+        } else {
+            event
+        };
+
+        match event {
+            Event::Solar(temperature) => {
+                self.scheduled = Some(temperature);
+                self.set_scheduled_temperature(temperature);
+            }
+            Event::Countdown(at) => self.notify_countdown(at),
+            Event::Postpone => self.postpone(),
+            Event::OverrideEnded(until) => self.end_override(until),
+            Event::Progress(progress) => {
+                if self.get_mode_gamma().is_none() {
+                    self.tray.send(TrayUpdate::Progress(progress));
+                }
+            }
+            Event::Tray(TrayAction::Day) => self.select_mode(Mode::Day),
+            Event::Tray(TrayAction::Night) => self.select_mode(Mode::Night),
+            Event::Tray(TrayAction::ToggleAutomatic) => self.set_automatic(!self.state.automatic),
+            Event::Tray(TrayAction::ToggleAstro) => self.set_astro(!self.astro),
+            Event::Tray(TrayAction::ToggleReading) => self.set_reading(!self.reading),
+            Event::Tray(TrayAction::SetLocation) => self.set_location(),
+            Event::Tray(TrayAction::OpenLog) => self.open_log(),
+            Event::Tray(TrayAction::CopyStatus) => self.copy_status(),
+            Event::Tray(TrayAction::DisableUntilSunrise) => self.disable_until_sunrise(),
+            Event::Tray(TrayAction::Reapply) => self.reapply(),
+            Event::Tray(TrayAction::SelectProfile(profile)) => self.select_profile(profile),
+            Event::IpcRequest { request, reply } => {
+                let _span = info_span!("command", ?request).entered();
+                let response = self.handle_request(request);
+                if reply.send(response).is_err() {
+                    error!("Failed to reply to control command");
+                };
+            }
+            Event::PreviewEnded(preview) => self.end_preview(preview),
+            Event::DebounceEnded => {
+                if let Some(event) = self.debounced.take() {
+                    return self.handle(event);
+                }
+            }
+            Event::InhibitReleased(id) => self.release_inhibitor(id),
+            Event::Subscribe(events) => self.subscribe(events),
+            Event::ConfigChanged(change) => {
+                match change {
+                    ConfigChange::Network(ssid) => {
+                        info!("Network changed to {:?}", ssid);
+                        self.ssid = ssid;
                     }
-                    Message::Shutdown => {
-                        return glib::ControlFlow::Break;
+                    ConfigChange::Timezone(timezone) => {
+                        info!("Timezone changed to {:?}", timezone);
+                        // A new offset from traveling is no clock change
+                        self.utc_offset = Local::now().offset().fix();
                     }
+                    ConfigChange::Location => info!("Location changed"),
                 };
+                self.reload();
             }
-            glib::ControlFlow::Continue
-        });
+            Event::Pause => {
+                info!("Paused");
+                self.paused = true;
+                self.update_tray_status();
+            }
+            Event::Resume => {
+                if self.paused {
+                    info!("Resumed");
+                    self.paused = false;
+                    self.update_tray_status();
+                    self.reconcile();
+                }
+            }
+            Event::Watchdog => {
+                // Also moves "tomorrow" along after midnight
+                self.update_tray_almanac();
+                self.check_runtime_files();
+                self.check_clock_change();
+                self.watchdog();
+            }
+            Event::WorkerDied(worker, message) => return self.restart_worker(worker, message),
+            Event::Unlocked => self.unlocked(),
+            Event::MonitorAdded(name) => {
+                info!("Monitor {} added", name);
+                self.reapply();
+            }
+            Event::Shutdown => return false,
+        };
+
+        true
+    }
+}
+
+fn spawn_watchdog(main_tx: Sender<Event>) {
+    supervisor::spawn(Worker::Watchdog, main_tx.clone(), move || {
+        loop {
+            thread::sleep(WATCHDOG_INTERVAL);
+            if main_tx.send(Event::Watchdog).is_err() {
+                break;
+            }
+        }
+    });
+}
 
-        gtk::main();
+// Everything worth pasting into a bug report
+fn describe_version(verbose: bool) -> String {
+    let version = format!("hyprsunset-overdrive {}", env!("CARGO_PKG_VERSION"));
+    if !verbose {
+        return version;
+    }
+
+    let mut features = Vec::new();
+    if cfg!(feature = "scripting") {
+        features.push("scripting");
+    }
+    if cfg!(feature = "svg") {
+        features.push("svg");
+    }
+
+    let hyprsunset = match Command::new("hyprsunset").arg("--version").output() {
+        Ok(output) if output.status.success() => {
+            String::from_utf8_lossy(&output.stdout).trim().to_string()
+        }
+        Ok(_) => "unknown".to_string(),
+        Err(_) => "not installed".to_string(),
+    };
+
+    [
+        version,
+        format!("commit: {}", env!("BUILD_GIT_COMMIT")),
+        format!("built: {}", env!("BUILD_DATE")),
+        format!(
+            "features: {}",
+            if features.is_empty() {
+                "none".to_string()
+            } else {
+                features.join(", ")
+            }
+        ),
+        format!("hyprsunset: {}", hyprsunset),
+    ]
+    .join("\n")
+}
+
+// Runs `setup` and waits for the picker to close
+fn run_setup() -> Result<()> {
+    let status = std::env::current_exe().and_then(|exe| Command::new(exe).arg("setup").status());
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => Err(anyhow!("The location picker exited with {}", status)),
+        Err(e) => Err(e).context("Failed to run the location picker"),
+    }
+}
+
+// Moves the location to the system timezone's with `follow_timezone`. Only
+// reads files, unlike the altitude lookup.
+fn follow_timezone(config: &mut Config) {
+    if !config.follow_timezone {
+        return;
+    }
+
+    match timezone::get_system_timezone()
+        .ok_or_else(|| anyhow!("Failed to find the system timezone"))
+        .and_then(|name| Ok((timezone::get_timezone_location(&name)?, name)))
+    {
+        Ok(((latitude, longitude), name)) => {
+            config.latitude = latitude;
+            config.longitude = longitude;
+            // Anything else would be a guess as well
+            config.altitude = 0.0;
+            config.location_provider = format!("timezone {}", name);
+        }
+        Err(e) => warn!(
+            "Failed to follow the timezone, using {} instead: {:#}",
+            config.location_provider, e
+        ),
+    };
+}
+
+// What to apply right away on startup, `Some(None)` for identity. `None`
+// leaves the screen alone, as the daemon would without a manual selection.
+fn get_startup_temperature(
+    config: &Config,
+    state: &State,
+    now: DateTime<Utc>,
+) -> Option<Option<i32>> {
+    match (state.automatic, state.mode) {
+        (true, _) => Some(get_scheduled_temperature(config, now).or(config.day_temperature)),
+        (false, Some(Mode::Night)) => Some(Some(config.temperature)),
+        (false, Some(Mode::Day)) => Some(config.day_temperature),
+        (false, None) => None,
+    }
+}
+
+// Applies what the saved `state` asks for with the `config` read from the
+// files alone, and only then calls `load` for the full config, which asks
+// NetworkManager for the network and may look up the altitude online. The
+// daemon applies the state again with its first event.
+fn apply_then_load<T>(
+    client: &mut Backend,
+    config: &Config,
+    state: &State,
+    now: DateTime<Utc>,
+    load: impl FnOnce() -> T,
+) -> T {
+    if let Some(temperature) = get_startup_temperature(config, state, now) {
+        let result = match temperature {
+            Some(temperature) => client.enable(temperature),
+            None => client.disable(),
+        };
+        match result {
+            Ok(_) => info!("Applied {} on startup", format_temperature(temperature)),
+            Err(e) => debug!("Failed to apply the startup temperature: {:#}", e),
+        };
+    }
+
+    load()
+}
+
+#[test]
+fn test_apply_then_load() {
+    use std::os::unix::net::UnixListener;
+
+    let sock_path = std::env::temp_dir().join(format!(
+        "hyprsunset-overdrive-test-startup-{}.sock",
+        std::process::id()
+    ));
+    let _ = fs::remove_file(&sock_path);
+    let listener = UnixListener::bind(&sock_path).unwrap();
+
+    let (commands_tx, commands_rx) = channel();
+    let server = thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut command = [0; 64];
+        let length = stream.read(&mut command).unwrap();
+        let _ = commands_tx.send(String::from_utf8_lossy(&command[..length]).into_owned());
+    });
+
+    let mut client = Backend::Hyprsunset(HyprsunsetClients::new(sock_path.clone(), None));
+    let config: Config = toml::from_str("temperature = 3000").unwrap();
+    // A manual selection, so the time doesn't matter
+    let state = State {
+        automatic: false,
+        mode: Some(Mode::Night),
+        profile: None,
+    };
+
+    // The loader blocks this thread, so the command must already be sent
+    let command = apply_then_load(&mut client, &config, &state, Utc::now(), || {
+        commands_rx.recv_timeout(Duration::from_secs(5))
     });
-    tx
+    assert_eq!(command.unwrap(), "temperature 3000");
+
+    server.join().unwrap();
+    fs::remove_file(&sock_path).unwrap();
+}
+
+// Loads the config for the network `ssid`, or with the `profile` picked
+// from the tray
+fn load_config(ssid: Option<&str>, profile: Option<&str>) -> Result<Config> {
+    let mut config = Config::load(ssid, profile)?;
+    follow_timezone(&mut config);
+
+    // A city already comes with its elevation
+    if config.lookup_altitude && config.city.is_none() && !config.follow_timezone {
+        match location::lookup_altitude(config.latitude, config.longitude) {
+            Ok(altitude) => config.altitude = altitude,
+            Err(e) => warn!(
+                "Failed to look up altitude, using {}m instead: {:#}",
+                config.altitude, e
+            ),
+        };
+    }
+
+    #[cfg(not(feature = "scripting"))]
+    if config.script.is_some() {
+        warn!("Ignoring script, this build does not support scripting");
+    }
+
+    Ok(config)
 }
 
 fn main() {
-    setup_logging();
-    match verify_hyprsunset_is_installed() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Failed to verify hyprsunset is installed: {}", e);
+    let args: Vec<String> = std::env::args().skip(1).collect();
+
+    // The CLI needs them as well, to find the control socket and the history
+    paths::init(
+        &Config::get_path()
+            .and_then(|path| Config::read(&path, None))
+            .unwrap_or_default(),
+    );
+
+    // Validates the config without talking to the daemon, e.g. from a
+    // pre-commit hook. Defaults to the config the daemon would load.
+    if args.first().map(String::as_str) == Some("check-config") {
+        let config_path = match args.get(1) {
+            Some(path) => Ok(PathBuf::from(path)),
+            None => Config::get_path(),
+        };
+
+        match config_path.and_then(|path| Config::read(&path, None).map(|_| path)) {
+            Ok(path) => println!("{}: OK", path.display()),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        };
+        return;
+    }
+
+    if matches!(
+        args.first().map(String::as_str),
+        Some("enable") | Some("disable")
+    ) {
+        match run_one_shot(&args) {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        };
+        return;
+    }
+
+    match args
+        .iter()
+        .map(String::as_str)
+        .collect::<Vec<&str>>()
+        .as_slice()
+    {
+        ["version" | "--version"] => {
+            println!("{}", describe_version(false));
             return;
         }
-    };
-    match wait_for_hyprsunset_to_start() {
-        Ok(_) => {}
-        Err(e) => {
-            error!("Failed to wait for hyprsunset to start: {}", e);
+        ["version" | "--version", "--verbose"] => {
+            println!("{}", describe_version(true));
             return;
         }
+        _ => {}
+    };
+
+    // Read from the state directory, works without the daemon
+    if args.first().map(String::as_str) == Some("history") {
+        if let Err(e) = history::print_history(&args[1..]) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Like `inhibit`, reads from the daemon until it goes away
+    if args == ["subscribe"] {
+        if let Err(e) = control::run_subscribe() {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Holds the connection to the daemon open, so it can't be forwarded
+    if args.first().map(String::as_str) == Some("inhibit") {
+        match control::run_inhibit(&args[1..]) {
+            Ok(code) => std::process::exit(code),
+            Err(e) => {
+                eprintln!("{:#}", e);
+                std::process::exit(1);
+            }
+        };
+    }
+
+    // Needs the running daemon for the previews
+    if args.first().map(String::as_str) == Some("calibrate") {
+        if let Err(e) = calibrate::calibrate() {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // The location picker, in its own process so GTK stays free for the tray
+    if args.first().map(String::as_str) == Some("setup") {
+        if let Err(e) = Config::get_path().and_then(|path| setup::run(&path)) {
+            eprintln!("{:#}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
+
+    // Follows the schedule without touching the screen, the state or the
+    // history, next to the real daemon
+    let dry_run = args == ["--dry-run"];
+
+    // Any other arguments are forwarded to the running daemon
+    if !args.is_empty() && !dry_run {
+        match control::send_control_command(&args.join(" ")) {
+            Ok(reply) => println!("{}", reply),
+            Err(e) => {
+                eprintln!("{}", e);
+                std::process::exit(1);
+            }
+        };
+        return;
+    }
+
+    // On first start, ask for the location instead of quietly using the
+    // default one. Without a display, or if the picker is skipped, the
+    // default config is written as before.
+    if !dry_run
+        && Config::get_path().is_ok_and(|path| !path.exists())
+        && let Err(e) = run_setup()
+    {
+        eprintln!("{:#}", e);
+    }
+
+    // Read before logging is set up, and again with logging once it is, so
+    // problems with the config still end up in the logs
+    let startup_config = Config::get_path()
+        .and_then(|path| Config::read(&path, None))
+        .unwrap_or_default();
+    setup_logging(startup_config.log_to_file, &startup_config.log_time_format);
+
+    // At session start, give bars and hyprsunset a head start instead of
+    // racing them
+    if startup_config.startup_delay_seconds > 0 {
+        info!(
+            "Waiting {}s before starting",
+            startup_config.startup_delay_seconds
+        );
+        thread::sleep(Duration::from_secs(startup_config.startup_delay_seconds));
+    }
+
+    // Other compositors go through the gamma command instead of hyprsunset
+    let backend = match startup_config.backend {
+        BackendKind::Auto if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() => {
+            BackendKind::Hyprsunset
+        }
+        BackendKind::Auto => BackendKind::Command,
+        backend => backend,
     };
+    if backend == BackendKind::Hyprsunset && !dry_run {
+        match verify_hyprsunset_is_installed() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to verify hyprsunset is installed: {}", e);
+                return;
+            }
+        };
+        match wait_for_hyprsunset_to_start() {
+            Ok(_) => {}
+            Err(e) => {
+                error!("Failed to wait for hyprsunset to start: {}", e);
+                return;
+            }
+        };
+    }
 
-    let (tx, rx) = channel::<Message>();
+    let (tx, rx) = channel::<Event>();
 
-    let mut signals = match Signals::new([SIGINT, SIGTERM]) {
+    let mut signals = match Signals::new([SIGINT, SIGTERM, SIGTSTP, SIGCONT]) {
         Ok(signals) => signals,
         Err(e) => {
             error!("Failed to create signal handler: {}", e);
@@ -488,130 +3285,263 @@ fn main() {
     let signal_tx = tx.clone();
     thread::spawn(move || {
         for signal in signals.forever() {
-            info!("Shutdown signal received: {:?}", signal);
-            signal_tx.send(Message::Shutdown).unwrap();
+            match signal {
+                SIGTSTP => {
+                    if signal_tx.send(Event::Pause).is_err() {
+                        break;
+                    }
+                    // Handling SIGTSTP replaces the default action, so stop
+                    // the process ourselves
+                    if let Err(e) = emulate_default_handler(SIGTSTP) {
+                        error!("Failed to stop: {}", e);
+                    };
+                }
+                SIGCONT => {
+                    if signal_tx.send(Event::Resume).is_err() {
+                        break;
+                    }
+                }
+                _ => {
+                    info!("Shutdown signal received: {:?}", signal);
+                    if signal_tx.send(Event::Shutdown).is_err() {
+                        break;
+                    }
+                }
+            };
         }
     });
 
-    let runtime_dir = match std::env::var("XDG_RUNTIME_DIR") {
+    let runtime_dir = match paths::get_runtime_dir() {
         Ok(dir) => dir,
-        Err(_) => {
-            error!("Failed to get XDG_RUNTIME_DIR");
+        Err(e) => {
+            error!("Failed to get the runtime directory: {:#}", e);
             return;
         }
     };
-    let lock_path = format!("{}/hyprsunset-overdrive.lock", runtime_dir);
-    let lock_file = match File::create(&lock_path) {
+    // Only an overridden one may be missing
+    if let Err(e) = fs::create_dir_all(&runtime_dir) {
+        error!("Failed to create {:?}: {}", runtime_dir, e);
+        return;
+    }
+    let lock_path = if dry_run {
+        runtime_dir.join("hyprsunset-overdrive-dry-run.lock")
+    } else {
+        runtime_dir.join("hyprsunset-overdrive.lock")
+    };
+    let lock_file = match acquire_lock(&lock_path) {
         Ok(file) => file,
-        Err(_) => {
-            error!("Failed to create lock file");
+        Err(e) => {
+            error!("Failed to acquire lock: {}", e);
+            error!("Exiting");
             return;
         }
     };
 
-    if lock_file.try_lock_exclusive().is_err() {
-        error!("Failed to acquire lock. Another instance is running.");
-        error!("Exiting");
-        return;
-    }
-
     info!("Lock acquired");
 
-    let config = match Config::load() {
-        Ok(config) => config,
+    let state = match State::load() {
+        Ok(state) => state,
         Err(e) => {
-            error!("Failed to load config: {}", e);
-            return;
+            error!("Failed to load state: {}", e);
+            State::default()
         }
     };
 
-    let _tx = tx.clone();
-    let tray_icon_tx = setup_tray_icon(_tx);
+    let mut startup_config = startup_config;
+    follow_timezone(&mut startup_config);
+
+    let mut client = if dry_run {
+        info!("Dry run, hyprsunset commands are only logged");
+        Backend::DryRun
+    } else if backend == BackendKind::Null {
+        match NullBackend::new() {
+            Ok(null) => {
+                info!("Null backend, recording commands to {:?}", null.get_path());
+                Backend::Null(null)
+            }
+            Err(e) => {
+                error!("Failed to set up the null backend: {:#}", e);
+                return;
+            }
+        }
+    } else if backend == BackendKind::Hyprsunset {
+        let hyprsunset_sock_path = match get_hyprsunset_socket_path() {
+            Ok(path) => path,
+            Err(e) => {
+                error!("Failed to get hyprsunset socket path: {}", e);
+                return;
+            }
+        };
+
+        // Hyprland's own, whatever `runtime_dir` says
+        let hypr_dir = startup_config.all_instances.then(|| {
+            PathBuf::from(std::env::var_os("XDG_RUNTIME_DIR").unwrap_or_default()).join("hypr")
+        });
+        Backend::Hyprsunset(HyprsunsetClients::new(hyprsunset_sock_path, hypr_dir))
+    } else {
+        info!(
+            "Not running under Hyprland, using {:?}",
+            startup_config.gamma_command
+        );
+        Backend::Command(GammaCommand::new(startup_config.gamma_command.clone()))
+    };
 
-    let hyprsunset_sock_path = match get_hyprsunset_socket_path() {
-        Ok(path) => path,
+    // Before anything that can take a while, such as the network, the tray
+    // and the scheduler, so there is no flash of blue at login
+    let loaded = apply_then_load(&mut client, &startup_config, &state, Utc::now(), || {
+        let ssid = network::get_current_ssid();
+        load_config(ssid.as_deref(), state.profile.as_deref()).map(|config| (ssid, config))
+    });
+    let (ssid, config) = match loaded {
+        Ok(loaded) => loaded,
         Err(e) => {
-            error!("Failed to get hyprsunset socket path: {}", e);
+            error!("Failed to load config: {}", e);
             return;
         }
     };
+    let automatic = Arc::new(AtomicBool::new(state.automatic));
 
-    let sunset_tx = tx.clone();
-    thread::spawn(move || {
-        loop {
-            let now = Utc::now();
-            let (sunrise, sunset) = get_sunrise_and_sunset(
-                config.latitude,
-                config.longitude,
-                config.altitude,
-                now.year(),
-                now.month(),
-                now.day(),
-            );
+    if let Err(e) = history::trim() {
+        error!("Failed to trim history: {:#}", e);
+    };
+
+    if config.wait_for_tray_host {
+        wait_for_tray_host();
+    }
 
-            info!("Sunrise: {:?}, Sunset: {:?}", sunrise, sunset);
+    // The real daemon keeps its tray icon and control socket
+    let _tx = tx.clone();
+    let tray = if dry_run {
+        TrayHandle::none()
+    } else {
+        let theme = config
+            .icon_theme_dir
+            .clone()
+            .map(|dir| IconTheme::new(dir, config.tray_icon_size));
+        setup_tray_icon(_tx, state.automatic, config.tray_badge, theme)
+    };
 
-            match get_part_of_day(now.time(), sunrise, sunset) {
-                ParOfDay::Daytime => {
-                    sunset_tx.send(Message::Day).unwrap();
-                }
-                ParOfDay::BeforeDaytime | ParOfDay::AfterDaytime => {
-                    sunset_tx.send(Message::Night).unwrap();
-                }
-            };
+    let control_tx = tx.clone();
+    let control_sock_path = if dry_run {
+        None
+    } else {
+        match control::setup_control_socket(control_tx) {
+            Ok(path) => Some(path),
+            Err(e) => {
+                error!("Failed to set up control socket: {}", e);
+                return;
+            }
+        }
+    };
 
-            let sleep_duration = get_duration_to_next_event(now.time(), sunrise, sunset);
+    let scheduler_tx = spawn_scheduler(config.clone(), automatic.clone(), tx.clone());
 
-            let sleep_seconds = sleep_duration.as_secs() as u64;
-            info!("Sleeping for {:.2} hours", sleep_seconds / 3600);
+    if !config.profiles.is_empty() {
+        spawn_network_watcher(tx.clone());
+    }
+    if config.follow_timezone {
+        spawn_timezone_watcher(tx.clone());
+    }
+    spawn_unlock_watcher(config.screen_lockers.clone(), tx.clone());
+    if std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE").is_some() {
+        spawn_monitor_watcher(tx.clone());
+    }
 
-            let mut slept_duration = Duration::from_secs(0);
-            while slept_duration < sleep_duration {
-                thread::sleep(Duration::from_secs(1));
-                slept_duration += Duration::from_secs(1);
-            }
+    spawn_watchdog(tx.clone());
+
+    let mut daemon = Daemon {
+        config,
+        state,
+        automatic,
+        client,
+        tray,
+        temperature: None,
+        astro: false,
+        reading: false,
+        errors: ErrorReporter::default(),
+        paused: false,
+        main_tx: tx.clone(),
+        scheduler_tx,
+        ssid,
+        preview: 0,
+        previewing: false,
+        override_until: None,
+        inhibitors: Vec::new(),
+        subscribers: Vec::new(),
+        published: None,
+        wind_down: false,
+        recorded: None,
+        worker_failures: 0,
+        last_worker_failure: None,
+        solar_table: SolarTable::default(),
+        scheduled: None,
+        scheduled_night: None,
+        dry_run,
+        utc_offset: Local::now().offset().fix(),
+        last_change: None,
+        debounced: None,
+        lock_path: lock_path.clone(),
+        lock_file,
+        control_sock_path,
+    };
 
-            // Small delay to prevent re-triggering due to time drift
-            thread::sleep(Duration::from_secs(60));
-        }
-    });
+    daemon.check_location();
+    daemon.update_tray_profiles();
 
-    let mut client = HyprsunsetClient::new(hyprsunset_sock_path);
+    // In manual mode the scheduler stays quiet, so restore the last manual
+    // selection ourselves.
+    if !daemon.state.automatic
+        && let Some(mode) = daemon.state.mode
+    {
+        daemon.set_mode(mode);
+        daemon.record(Cause::Manual);
+    }
 
     loop {
-        let message = match rx.recv() {
-            Ok(message) => message,
-            Err(e) => {
-                error!("Failed to receive message: {}", e);
-                return;
-            }
-        };
-
-        match message {
-            Message::Day => {
-                match client.disable() {
-                    Ok(_) => info!("Successfully disabled blue light filter"),
-                    Err(e) => error!("Failed to disable blue light filter: {}", e),
-                };
-                tray_icon_tx.send(Message::Day).unwrap();
-            }
-            Message::Night => {
-                match client.enable(config.temperature) {
-                    Ok(_) => info!("Successfully set blue light filter"),
-                    Err(e) => error!("Failed to set blue light filter: {}", e),
-                };
-                tray_icon_tx.send(Message::Night).unwrap();
+        let event = if daemon.errors.is_failing() {
+            match rx.recv_timeout(RETRY_INTERVAL) {
+                Ok(event) => event,
+                Err(RecvTimeoutError::Timeout) => {
+                    daemon.retry();
+                    continue;
+                }
+                Err(e) => {
+                    error!("Failed to receive event: {}", e);
+                    return;
+                }
             }
-            Message::Shutdown => {
-                break;
+        } else {
+            match rx.recv() {
+                Ok(event) => event,
+                Err(e) => {
+                    error!("Failed to receive event: {}", e);
+                    return;
+                }
             }
         };
+
+        if !daemon.handle(event) {
+            break;
+        }
     }
 
     // Cleanup
 
+    if daemon.scheduler_tx.send(()).is_err() {
+        error!("Failed to stop scheduler");
+    };
+    daemon.apply_exit_state();
+    daemon.tray.send(TrayUpdate::Shutdown);
+
+    if let Some(control_sock_path) = &daemon.control_sock_path {
+        match fs::remove_file(control_sock_path) {
+            Ok(_) => info!("Control socket removed"),
+            Err(e) => error!("Failed to remove control socket: {}", e),
+        };
+    }
+
     // Not required, but release early
-    drop(lock_file);
+    drop(daemon.lock_file);
 
     match fs::remove_file(lock_path) {
         Ok(_) => info!("Lock released"),
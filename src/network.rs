@@ -0,0 +1,104 @@
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, info};
+
+use crate::events::{ConfigChange, Event};
+use crate::supervisor::{self, Worker};
+
+// How often the SSID is checked
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// `nmcli -t` separates fields with ':' and escapes ':' and '\' in values
+fn unescape_field(field: &str) -> String {
+    let mut value = String::new();
+    let mut chars = field.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' => value.extend(chars.next()),
+            c => value.push(c),
+        }
+    }
+
+    value
+}
+
+// Picks the active network from `nmcli -t -f active,ssid dev wifi list`
+fn parse_active_ssid(output: &str) -> Option<String> {
+    output
+        .lines()
+        .find_map(|line| line.strip_prefix("yes:"))
+        .map(unescape_field)
+        .filter(|ssid| !ssid.is_empty())
+}
+
+// The SSID of the connected Wi-Fi network, asked from NetworkManager. Its
+// last scan is enough, without `--rescan no` nmcli may scan for networks
+// on every poll.
+pub fn get_current_ssid() -> Option<String> {
+    let output = match Command::new("nmcli")
+        .args([
+            "-t",
+            "-f",
+            "active,ssid",
+            "dev",
+            "wifi",
+            "list",
+            "--rescan",
+            "no",
+        ])
+        .output()
+    {
+        Ok(output) if output.status.success() => output,
+        Ok(output) => {
+            debug!("nmcli failed: {}", String::from_utf8_lossy(&output.stderr));
+            return None;
+        }
+        Err(e) => {
+            debug!("Failed to run nmcli: {}", e);
+            return None;
+        }
+    };
+
+    parse_active_ssid(&String::from_utf8_lossy(&output.stdout))
+}
+
+// Sends `ConfigChange::Network` whenever the connected network changes
+pub fn spawn_network_watcher(main_tx: Sender<Event>) {
+    supervisor::spawn(Worker::NetworkWatcher, main_tx.clone(), move || {
+        let mut current = get_current_ssid();
+        info!("Current network: {:?}", current);
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let ssid = get_current_ssid();
+            if ssid == current {
+                continue;
+            }
+
+            current = ssid.clone();
+            if main_tx
+                .send(Event::ConfigChanged(ConfigChange::Network(ssid)))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+}
+
+#[test]
+fn test_parse_active_ssid() {
+    let output = "no:Neighbours\nyes:ACME-Corp\nno:\n";
+    assert_eq!(parse_active_ssid(output), Some("ACME-Corp".to_string()));
+
+    assert_eq!(
+        parse_active_ssid("yes:Cafe\\: Free\n"),
+        Some("Cafe: Free".to_string())
+    );
+    assert_eq!(parse_active_ssid("no:ACME-Corp\n"), None);
+    assert_eq!(parse_active_ssid(""), None);
+}
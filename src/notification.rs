@@ -0,0 +1,505 @@
+use std::process::Command;
+use std::sync::{Mutex, PoisonError};
+use std::thread;
+use std::time::Duration;
+
+use chrono::{DateTime, Local, NaiveTime, TimeDelta};
+use serde::{Deserialize, Deserializer};
+use tracing::{debug, error};
+
+use crate::schedule::TimeFormat;
+
+// How often Do Not Disturb is checked while notifications are held back
+const DND_POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Urgency {
+    Low,
+    #[default]
+    Normal,
+    Critical,
+}
+
+impl Urgency {
+    fn as_str(&self) -> &'static str {
+        match self {
+            Urgency::Low => "low",
+            Urgency::Normal => "normal",
+            Urgency::Critical => "critical",
+        }
+    }
+}
+
+// `quiet_hours = "23:00-08:00"`, which may wrap around midnight
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct QuietHours {
+    start: NaiveTime,
+    end: NaiveTime,
+}
+
+impl QuietHours {
+    fn contains(&self, time: NaiveTime) -> bool {
+        if self.start <= self.end {
+            self.start <= time && time < self.end
+        } else {
+            time >= self.start || time < self.end
+        }
+    }
+}
+
+fn deserialize_quiet_hours<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<QuietHours>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let hours = String::deserialize(deserializer)?;
+    let parse = |time: &str| NaiveTime::parse_from_str(time.trim(), "%H:%M").ok();
+    let quiet_hours = hours.split_once('-').and_then(|(start, end)| {
+        Some(QuietHours {
+            start: parse(start)?,
+            end: parse(end)?,
+        })
+    });
+
+    match quiet_hours {
+        Some(quiet_hours) => Ok(Some(quiet_hours)),
+        None => Err(serde::de::Error::custom(format!(
+            "invalid quiet hours \"{}\", expected HH:MM-HH:MM",
+            hours
+        ))),
+    }
+}
+
+// The `[notifications]` table
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NotificationSettings {
+    // Turns off every notification
+    pub enabled: bool,
+    // When the schedule turns the filter on, and off
+    pub evening: bool,
+    pub morning: bool,
+    // When the clocks change for daylight saving time, moving the switch
+    pub clock_change: bool,
+    pub urgency: Urgency,
+    // Left to the notification daemon when not set
+    pub timeout_ms: Option<u32>,
+    pub show_icon: bool,
+    // Played with the evening notification, as a freedesktop sound name such
+    // as "complete", and/or by running a command such as `pw-play FILE`
+    pub sound: Option<String>,
+    pub sound_command: Option<String>,
+    // Notifications are dropped in between, in local time
+    #[serde(deserialize_with = "deserialize_quiet_hours")]
+    pub quiet_hours: Option<QuietHours>,
+    // How long before the filter turns on in the evening to warn, with a
+    // button to put it off
+    pub countdown_minutes: Option<u32>,
+}
+
+impl Default for NotificationSettings {
+    fn default() -> Self {
+        Self {
+            enabled: true,
+            evening: true,
+            morning: true,
+            clock_change: true,
+            urgency: Urgency::default(),
+            timeout_ms: None,
+            show_icon: true,
+            sound: None,
+            sound_command: None,
+            quiet_hours: None,
+            countdown_minutes: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Notification {
+    pub summary: String,
+    pub body: String,
+    // Name from the icon theme
+    pub icon: &'static str,
+    // Whether the configured sound goes with it
+    pub sound: bool,
+}
+
+// Notifications suppressed while Do Not Disturb is on. Only the latest is
+// kept, and shown together with the count once Do Not Disturb ends.
+struct Pending {
+    notification: Notification,
+    settings: NotificationSettings,
+    count: usize,
+}
+
+static PENDING: Mutex<Option<Pending>> = Mutex::new(None);
+
+// `makoctl mode` lists the active modes, one per line
+fn parse_mako_modes(output: &str) -> bool {
+    output.lines().any(|mode| mode.trim() == "do-not-disturb")
+}
+
+// Asks mako and swaync, whichever is running
+fn is_do_not_disturb() -> bool {
+    let run = |program: &str, args: &[&str]| {
+        Command::new(program)
+            .args(args)
+            .output()
+            .ok()
+            .filter(|output| output.status.success())
+            .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+    };
+
+    if let Some(modes) = run("makoctl", &["mode"]) {
+        return parse_mako_modes(&modes);
+    }
+
+    if let Some(dnd) = run("swaync-client", &["--get-dnd", "--skip-wait"]) {
+        return dnd.trim() == "true";
+    }
+
+    false
+}
+
+fn get_summary_body(pending: &Pending) -> String {
+    match pending.count {
+        1 => pending.notification.body.clone(),
+        count => format!(
+            "{}\n({} more while Do Not Disturb was on)",
+            pending.notification.body,
+            count - 1
+        ),
+    }
+}
+
+fn get_notify_send_args(
+    settings: &NotificationSettings,
+    notification: &Notification,
+    body: &str,
+) -> Vec<String> {
+    let mut args = vec![
+        "--app-name=hyprsunset-overdrive".to_string(),
+        format!("--urgency={}", settings.urgency.as_str()),
+    ];
+    if let Some(timeout) = settings.timeout_ms {
+        args.push(format!("--expire-time={}", timeout));
+    }
+    if settings.show_icon {
+        args.push(format!("--icon={}", notification.icon));
+    }
+    if let Some(sound) = settings.sound.as_ref().filter(|_| notification.sound) {
+        args.push(format!("--hint=string:sound-name:{}", sound));
+    }
+    args.push(notification.summary.clone());
+    args.push(body.to_string());
+
+    args
+}
+
+fn is_quiet_hours(settings: &NotificationSettings) -> bool {
+    settings
+        .quiet_hours
+        .is_some_and(|quiet_hours| quiet_hours.contains(Local::now().time()))
+}
+
+// Also covers notifications held back by Do Not Disturb until then
+fn send(settings: &NotificationSettings, notification: &Notification, body: &str) {
+    if is_quiet_hours(settings) {
+        debug!("Quiet hours, dropping {:?}", notification.summary);
+        return;
+    }
+
+    match Command::new("notify-send")
+        .args(get_notify_send_args(settings, notification, body))
+        .status()
+    {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("notify-send exited with {}", status),
+        Err(e) => error!("Failed to run notify-send: {}", e),
+    };
+
+    if let Some(command) = settings
+        .sound_command
+        .as_ref()
+        .filter(|_| notification.sound)
+    {
+        play_sound(command);
+    }
+}
+
+fn play_sound(command: &str) {
+    let args: Vec<&str> = command.split_whitespace().collect();
+    let Some((program, args)) = args.split_first() else {
+        return;
+    };
+
+    match Command::new(program).args(args).status() {
+        Ok(status) if status.success() => {}
+        Ok(status) => error!("{} exited with {}", program, status),
+        Err(e) => error!("Failed to run {}: {}", program, e),
+    };
+}
+
+// Holds the notification back. The first one starts waiting for Do Not
+// Disturb to end.
+fn suppress(settings: NotificationSettings, notification: Notification) {
+    // Nothing in here can be left half updated, so a panic elsewhere doesn't
+    // matter
+    let mut pending = PENDING.lock().unwrap_or_else(PoisonError::into_inner);
+    match pending.as_mut() {
+        Some(pending) => {
+            pending.notification = notification;
+            pending.settings = settings;
+            pending.count += 1;
+            return;
+        }
+        None => {
+            *pending = Some(Pending {
+                notification,
+                settings,
+                count: 1,
+            })
+        }
+    };
+    drop(pending);
+
+    loop {
+        thread::sleep(DND_POLL_INTERVAL);
+        if is_do_not_disturb() {
+            continue;
+        }
+
+        let pending = PENDING
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .take();
+        if let Some(pending) = pending {
+            send(
+                &pending.settings,
+                &pending.notification,
+                &get_summary_body(&pending),
+            );
+        }
+        break;
+    }
+}
+
+// Summary and body for a scheduled switch. `next_event` is the sunrise or
+// sunset that ends it, e.g. "Night mode on (3000K)" and "Sunrise tomorrow
+// at 06:41."
+pub fn get_transition_message(
+    temperature: Option<i32>,
+    next_event: (&str, DateTime<Local>),
+    now: DateTime<Local>,
+    time_format: TimeFormat,
+) -> Notification {
+    let summary = match temperature {
+        Some(temperature) => format!("Night mode on ({}K)", temperature),
+        None => "Night mode off".to_string(),
+    };
+
+    let (name, time) = next_event;
+    let day = if time.date_naive() == now.date_naive() {
+        "today"
+    } else if time.date_naive() == now.date_naive() + TimeDelta::days(1) {
+        "tomorrow"
+    } else {
+        "later"
+    };
+
+    let body = format!("{} {} at {}.", name, day, time_format.format(time.time()));
+
+    Notification {
+        summary,
+        body,
+        icon: if temperature.is_some() {
+            "weather-clear-night"
+        } else {
+            "weather-clear"
+        },
+        // The evening one is a cue to wrap up for the day
+        sound: temperature.is_some(),
+    }
+}
+
+// Shows a desktop notification through `notify-send`. Runs in the background
+// so a slow or missing notification daemon never blocks the caller.
+pub fn notify(settings: &NotificationSettings, notification: Notification) {
+    if !settings.enabled {
+        return;
+    }
+
+    let settings = settings.clone();
+    thread::spawn(move || {
+        if is_do_not_disturb() {
+            debug!(
+                "Do Not Disturb is on, holding back {:?}",
+                notification.summary
+            );
+            suppress(settings, notification);
+        } else {
+            send(&settings, &notification, &notification.body);
+        }
+    });
+}
+
+// Like `notify`, with a button labeled `label`. notify-send waits until the
+// notification is closed and prints "action" if the button was clicked, which
+// calls `on_click`. Not held back by Do Not Disturb, since the button would
+// be too late by then.
+pub fn notify_with_action<F>(
+    settings: &NotificationSettings,
+    notification: Notification,
+    label: &str,
+    on_click: F,
+) where
+    F: FnOnce() + Send + 'static,
+{
+    if !settings.enabled {
+        return;
+    }
+
+    let settings = settings.clone();
+    let action = format!("--action=action={}", label);
+    thread::spawn(move || {
+        if is_do_not_disturb() || is_quiet_hours(&settings) {
+            debug!("Dropping {:?}", notification.summary);
+            return;
+        }
+
+        let mut args = get_notify_send_args(&settings, &notification, &notification.body);
+        args.insert(0, action);
+        match Command::new("notify-send").args(args).output() {
+            Ok(output) if output.status.success() => {
+                if String::from_utf8_lossy(&output.stdout).trim() == "action" {
+                    on_click();
+                }
+            }
+            Ok(output) => error!("notify-send exited with {}", output.status),
+            Err(e) => error!("Failed to run notify-send: {}", e),
+        };
+    });
+}
+
+#[test]
+fn test_parse_mako_modes() {
+    assert!(parse_mako_modes("default\ndo-not-disturb\n"));
+    assert!(!parse_mako_modes("default\n"));
+}
+
+#[test]
+fn test_get_summary_body() {
+    let mut pending = Pending {
+        notification: Notification {
+            summary: "Can't reach hyprsunset — retrying".to_string(),
+            body: "Is it running?".to_string(),
+            icon: "dialog-warning",
+            sound: false,
+        },
+        settings: NotificationSettings::default(),
+        count: 1,
+    };
+    assert_eq!(get_summary_body(&pending), "Is it running?");
+
+    pending.count = 3;
+    assert_eq!(
+        get_summary_body(&pending),
+        "Is it running?\n(2 more while Do Not Disturb was on)"
+    );
+}
+
+#[test]
+fn test_get_transition_message() {
+    use chrono::TimeZone;
+
+    let now = Local.with_ymd_and_hms(2025, 6, 21, 18, 42, 0).unwrap();
+    let sunrise = Local.with_ymd_and_hms(2025, 6, 22, 6, 41, 0).unwrap();
+
+    let notification = get_transition_message(
+        Some(3000),
+        ("Sunrise", sunrise),
+        now,
+        TimeFormat::TwentyFourHour,
+    );
+    assert_eq!(notification.summary, "Night mode on (3000K)");
+    assert_eq!(notification.body, "Sunrise tomorrow at 06:41.");
+
+    let sunset = Local.with_ymd_and_hms(2025, 6, 22, 18, 42, 0).unwrap();
+    assert_eq!(
+        get_transition_message(None, ("Sunset", sunset), sunrise, TimeFormat::TwelveHour).body,
+        "Sunset today at 6:42 PM."
+    );
+}
+
+#[test]
+fn test_notification_settings() {
+    let settings: NotificationSettings = toml::from_str(
+        r#"urgency = "low"
+timeout_ms = 3000
+show_icon = false"#,
+    )
+    .unwrap();
+    assert!(settings.morning);
+
+    let notification = Notification {
+        summary: "Night mode off".to_string(),
+        body: "Sunset today at 18:42.".to_string(),
+        icon: "weather-clear",
+        sound: false,
+    };
+    assert_eq!(
+        get_notify_send_args(&settings, &notification, &notification.body),
+        [
+            "--app-name=hyprsunset-overdrive",
+            "--urgency=low",
+            "--expire-time=3000",
+            "Night mode off",
+            "Sunset today at 18:42."
+        ]
+    );
+
+    assert!(toml::from_str::<NotificationSettings>(r#"urgency = "loud""#).is_err());
+}
+
+#[test]
+fn test_notification_sound() {
+    let settings: NotificationSettings = toml::from_str(r#"sound = "complete""#).unwrap();
+    let now = Local::now();
+
+    let evening = get_transition_message(Some(3000), ("Sunrise", now), now, TimeFormat::default());
+    assert!(
+        get_notify_send_args(&settings, &evening, &evening.body)
+            .contains(&"--hint=string:sound-name:complete".to_string())
+    );
+
+    let morning = get_transition_message(None, ("Sunset", now), now, TimeFormat::default());
+    assert!(
+        !get_notify_send_args(&settings, &morning, &morning.body)
+            .iter()
+            .any(|arg| arg.starts_with("--hint"))
+    );
+}
+
+#[test]
+fn test_quiet_hours() {
+    let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+
+    let settings: NotificationSettings = toml::from_str(r#"quiet_hours = "23:00-08:00""#).unwrap();
+    let quiet_hours = settings.quiet_hours.unwrap();
+    assert!(quiet_hours.contains(time("23:30")));
+    assert!(quiet_hours.contains(time("06:41")));
+    assert!(!quiet_hours.contains(time("08:00")));
+    assert!(!quiet_hours.contains(time("18:42")));
+
+    let settings: NotificationSettings =
+        toml::from_str(r#"quiet_hours = "12:00 - 13:00""#).unwrap();
+    assert!(settings.quiet_hours.unwrap().contains(time("12:30")));
+    assert!(!settings.quiet_hours.unwrap().contains(time("23:30")));
+
+    assert!(toml::from_str::<NotificationSettings>(r#"quiet_hours = "23:00""#).is_err());
+    assert!(toml::from_str::<NotificationSettings>(r#"quiet_hours = "late-early""#).is_err());
+    assert_eq!(NotificationSettings::default().quiet_hours, None);
+}
@@ -0,0 +1,81 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use chrono::{DateTime, Utc};
+
+use crate::IDENTITY_TEMPERATURE;
+use crate::paths;
+
+// Accepts every command and appends it to a file in the state directory
+// instead of applying it, for developing without Hyprland
+pub struct NullBackend {
+    path: PathBuf,
+    // What a query would report
+    temperature: i32,
+}
+
+// One tab separated line per command, e.g.
+// "2025-06-21T15:42:00+00:00	temperature 3000"
+fn format_command(time: DateTime<Utc>, command: &str) -> String {
+    format!("{}\t{}", time.to_rfc3339(), command)
+}
+
+impl NullBackend {
+    pub fn new() -> Result<Self> {
+        Ok(Self {
+            path: paths::get_state_dir()?.join("null-backend.log"),
+            temperature: IDENTITY_TEMPERATURE,
+        })
+    }
+
+    pub fn get_path(&self) -> &PathBuf {
+        &self.path
+    }
+
+    fn record(&self, command: &str) -> Result<()> {
+        if let Some(parent) = self.path.parent() {
+            fs::create_dir_all(parent).context("Failed to create state directory")?;
+        };
+
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .context(format!("Failed to open {:?}", self.path))?;
+        match writeln!(file, "{}", format_command(Utc::now(), command)) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context(format!("Failed to write to {:?}", self.path)),
+        }
+    }
+
+    pub fn enable(&mut self, temperature: i32) -> Result<()> {
+        self.temperature = temperature;
+        self.record(&format!("temperature {}", temperature))
+    }
+
+    pub fn disable(&mut self) -> Result<()> {
+        self.temperature = IDENTITY_TEMPERATURE;
+        self.record("identity")
+    }
+
+    pub fn set_gamma(&mut self, gamma: i32) -> Result<()> {
+        self.record(&format!("gamma {}", gamma))
+    }
+
+    pub fn get_temperature(&self) -> i32 {
+        self.temperature
+    }
+}
+
+#[test]
+fn test_format_command() {
+    use chrono::TimeZone;
+
+    let time = Utc.with_ymd_and_hms(2025, 6, 21, 15, 42, 0).unwrap();
+    assert_eq!(
+        format_command(time, "temperature 3000"),
+        "2025-06-21T15:42:00+00:00\ttemperature 3000"
+    );
+}
@@ -0,0 +1,62 @@
+use std::path::PathBuf;
+use std::sync::OnceLock;
+
+use anyhow::{Result, anyhow};
+
+use crate::config::Config;
+
+// `runtime_dir`, `state_dir` and `log_dir` from the config
+#[derive(Debug, Default)]
+struct Overrides {
+    runtime_dir: Option<PathBuf>,
+    state_dir: Option<PathBuf>,
+    log_dir: Option<PathBuf>,
+}
+
+static OVERRIDES: OnceLock<Overrides> = OnceLock::new();
+
+// Takes the directories from the config. Only the first call counts, so the
+// daemon and every part of it agree on where the files are.
+pub fn init(config: &Config) {
+    let _ = OVERRIDES.set(Overrides {
+        runtime_dir: config.runtime_dir.clone(),
+        state_dir: config.state_dir.clone(),
+        log_dir: config.log_dir.clone(),
+    });
+}
+
+fn get_overrides() -> &'static Overrides {
+    OVERRIDES.get_or_init(Overrides::default)
+}
+
+// The lock file and the control socket, $XDG_RUNTIME_DIR by default
+pub fn get_runtime_dir() -> Result<PathBuf> {
+    if let Some(dir) = &get_overrides().runtime_dir {
+        return Ok(dir.clone());
+    }
+
+    match std::env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) => Ok(PathBuf::from(dir)),
+        Err(_) => Err(anyhow!("XDG_RUNTIME_DIR not set")),
+    }
+}
+
+// The saved state, the history and what the null backend records
+pub fn get_state_dir() -> Result<PathBuf> {
+    if let Some(dir) = &get_overrides().state_dir {
+        return Ok(dir.clone());
+    }
+
+    match dirs::state_dir() {
+        Some(dir) => Ok(dir.join("hyprsunset-overdrive")),
+        None => Err(anyhow!("Failed to find state directory")),
+    }
+}
+
+// Next to the lock file by default
+pub fn get_log_dir() -> Result<PathBuf> {
+    match &get_overrides().log_dir {
+        Some(dir) => Ok(dir.clone()),
+        None => get_runtime_dir(),
+    }
+}
@@ -0,0 +1,556 @@
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Datelike, Local, NaiveTime, TimeDelta, TimeZone, Utc, Weekday};
+use serde::{Deserialize, Deserializer};
+
+pub fn deserialize_optional_time<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<NaiveTime>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    match Option::<String>::deserialize(deserializer)? {
+        Some(time) => match NaiveTime::parse_from_str(&time, "%H:%M") {
+            Ok(time) => Ok(Some(time)),
+            Err(_) => Err(serde::de::Error::custom(format!(
+                "invalid time \"{}\", expected HH:MM",
+                time
+            ))),
+        },
+        None => Ok(None),
+    }
+}
+
+fn deserialize_time<'de, D>(deserializer: D) -> std::result::Result<NaiveTime, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let time = String::deserialize(deserializer)?;
+    NaiveTime::parse_from_str(&time, "%H:%M")
+        .map_err(|_| serde::de::Error::custom(format!("invalid time \"{}\", expected HH:MM", time)))
+}
+
+// Day names such as "mon" or "monday"
+fn deserialize_weekdays<'de, D>(deserializer: D) -> std::result::Result<Vec<Weekday>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|day| {
+            day.parse::<Weekday>().map_err(|_| {
+                serde::de::Error::custom(format!("invalid day \"{}\", expected e.g. \"mon\"", day))
+            })
+        })
+        .collect()
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum Temperature {
+    Kelvin(i32),
+    Name(String),
+}
+
+impl Temperature {
+    fn resolve<E: serde::de::Error>(self) -> std::result::Result<Option<i32>, E> {
+        match self {
+            Temperature::Kelvin(temperature) => Ok(Some(temperature)),
+            Temperature::Name(name) if name == "identity" => Ok(None),
+            Temperature::Name(name) => Err(E::custom(format!(
+                "invalid temperature \"{}\", expected a number or \"identity\"",
+                name
+            ))),
+        }
+    }
+}
+
+// Accepts a temperature in Kelvin or "identity", which disables the filter
+fn deserialize_temperature<'de, D>(deserializer: D) -> std::result::Result<Option<i32>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Temperature::deserialize(deserializer)?.resolve()
+}
+
+// A list of temperatures as accepted by `deserialize_temperature`
+pub fn deserialize_temperatures<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Vec<Option<i32>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    Vec::<Temperature>::deserialize(deserializer)?
+        .into_iter()
+        .map(Temperature::resolve)
+        .collect()
+}
+
+// How times of day are shown to the user
+#[derive(Debug, Clone, Copy, Default, PartialEq, Deserialize)]
+pub enum TimeFormat {
+    #[default]
+    #[serde(rename = "24h")]
+    TwentyFourHour,
+    #[serde(rename = "12h")]
+    TwelveHour,
+}
+
+impl TimeFormat {
+    pub fn format(&self, time: NaiveTime) -> String {
+        match self {
+            TimeFormat::TwentyFourHour => time.format("%H:%M").to_string(),
+            TimeFormat::TwelveHour => time.format("%-I:%M %p").to_string(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum SolarEvent {
+    Sunrise,
+    Sunset,
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SchedulePoint {
+    // Local time of day
+    #[serde(default, deserialize_with = "deserialize_optional_time")]
+    pub time: Option<NaiveTime>,
+    #[serde(default)]
+    pub solar_event: Option<SolarEvent>,
+    // Only used together with `solar_event`
+    #[serde(default)]
+    pub offset_minutes: i64,
+    #[serde(deserialize_with = "deserialize_temperature")]
+    pub temperature: Option<i32>,
+}
+
+// A recurring stretch of time with its own temperature, such as a morning of
+// deep work. Overrides the rest of the schedule while it lasts.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FocusBlock {
+    #[serde(default)]
+    pub name: Option<String>,
+    // Local times of day. A block that ends before it starts runs past
+    // midnight.
+    #[serde(deserialize_with = "deserialize_time")]
+    pub start: NaiveTime,
+    #[serde(deserialize_with = "deserialize_time")]
+    pub end: NaiveTime,
+    // The days the block starts on, every day when empty
+    #[serde(default, deserialize_with = "deserialize_weekdays")]
+    pub days: Vec<Weekday>,
+    #[serde(deserialize_with = "deserialize_temperature")]
+    pub temperature: Option<i32>,
+}
+
+impl FocusBlock {
+    pub fn is_active(&self, now: DateTime<Local>) -> bool {
+        let time = now.time();
+        let (inside, start_date) = if self.start < self.end {
+            (self.start <= time && time < self.end, now.date_naive())
+        } else if time >= self.start {
+            (true, now.date_naive())
+        } else {
+            (time < self.end, now.date_naive() - TimeDelta::days(1))
+        };
+
+        inside && (self.days.is_empty() || self.days.contains(&start_date.weekday()))
+    }
+
+    pub fn get_label(&self) -> &str {
+        self.name.as_deref().unwrap_or("focus block")
+    }
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Schedule {
+    #[serde(default)]
+    pub points: Vec<SchedulePoint>,
+    #[serde(default)]
+    pub blocks: Vec<FocusBlock>,
+}
+
+// A schedule point pinned to a UTC time of day
+#[derive(Debug, Clone, PartialEq)]
+pub struct ResolvedPoint {
+    pub time: NaiveTime,
+    // What the point is relative to. `None` for points at a fixed time.
+    pub label: Option<String>,
+    pub temperature: Option<i32>,
+}
+
+impl Schedule {
+    pub fn validate(&self) -> Result<()> {
+        for (i, point) in self.points.iter().enumerate() {
+            match (point.time, point.solar_event) {
+                (Some(_), Some(_)) => {
+                    return Err(anyhow!(
+                        "schedule point {} has both time and solar_event",
+                        i + 1
+                    ));
+                }
+                (None, None) => {
+                    return Err(anyhow!(
+                        "schedule point {} needs either time or solar_event",
+                        i + 1
+                    ));
+                }
+                _ => {}
+            };
+        }
+
+        for (i, block) in self.blocks.iter().enumerate() {
+            if block.start == block.end {
+                return Err(anyhow!("schedule block {} starts when it ends", i + 1));
+            }
+        }
+
+        Ok(())
+    }
+
+    // The first block covering `now`
+    pub fn get_active_block(&self, now: DateTime<Utc>) -> Option<&FocusBlock> {
+        let now = now.with_timezone(&Local);
+        self.blocks.iter().find(|block| block.is_active(now))
+    }
+
+    // Until the next start or end of any block, `None` without blocks
+    pub fn get_duration_to_next_block_change(&self, now: DateTime<Utc>) -> Option<Duration> {
+        let time = now.with_timezone(&Local).time();
+        self.blocks
+            .iter()
+            .flat_map(|block| [block.start, block.end])
+            .map(|boundary| get_seconds_between(time, boundary))
+            .filter(|seconds| *seconds > 0)
+            .min()
+            .map(|seconds| Duration::from_secs(seconds as u64))
+    }
+
+    // Pins the points to the day of `now`, sorted by time of day
+    pub fn resolve(
+        &self,
+        now: DateTime<Utc>,
+        sunrise: NaiveTime,
+        sunset: NaiveTime,
+    ) -> Vec<ResolvedPoint> {
+        let mut points: Vec<ResolvedPoint> = self
+            .points
+            .iter()
+            .filter_map(|point| {
+                let (time, label) = match (point.time, point.solar_event) {
+                    (Some(time), _) => (get_local_time_as_utc(time, now)?, None),
+                    (None, Some(event)) => {
+                        let (time, name) = match event {
+                            SolarEvent::Sunrise => (sunrise, "sunrise"),
+                            SolarEvent::Sunset => (sunset, "sunset"),
+                        };
+                        let offset = TimeDelta::minutes(point.offset_minutes);
+                        let label = match point.offset_minutes {
+                            0 => name.to_string(),
+                            minutes => format!("{}{:+}m", name, minutes),
+                        };
+                        (time + offset, Some(label))
+                    }
+                    (None, None) => return None,
+                };
+
+                Some(ResolvedPoint {
+                    time,
+                    label,
+                    temperature: point.temperature,
+                })
+            })
+            .collect();
+
+        points.sort_by_key(|point| point.time);
+        points
+    }
+}
+
+// Converts a local time of day on the day of `now` to a UTC time of day.
+// `None` if the local time does not exist, e.g. during a DST change.
+pub fn get_local_time_as_utc(time: NaiveTime, now: DateTime<Utc>) -> Option<NaiveTime> {
+    let local_date = now.with_timezone(&Local).date_naive();
+    let local = Local
+        .from_local_datetime(&local_date.and_time(time))
+        .earliest()?;
+
+    Some(local.with_timezone(&Utc).time())
+}
+
+// Seconds from `from` until the next time the clock reads `to`
+pub fn get_seconds_between(from: NaiveTime, to: NaiveTime) -> i64 {
+    (to - from).num_seconds().rem_euclid(24 * 60 * 60)
+}
+
+// Indices of the points before and after `time`, wrapping around midnight
+fn get_segment(points: &[ResolvedPoint], time: NaiveTime) -> (usize, usize) {
+    let next = points
+        .iter()
+        .position(|point| point.time > time)
+        .unwrap_or(0);
+    let previous = (next + points.len() - 1) % points.len();
+
+    (previous, next)
+}
+
+// Temperatures are interpolated linearly between consecutive points.
+// Identity is not a temperature, so a segment that starts or ends at
+// identity switches at the next point instead.
+pub fn get_temperature_at(points: &[ResolvedPoint], time: NaiveTime) -> Option<i32> {
+    if points.is_empty() {
+        return None;
+    }
+
+    let (previous, next) = get_segment(points, time);
+    let (from, to) = match (points[previous].temperature, points[next].temperature) {
+        (Some(from), Some(to)) => (from, to),
+        (from, _) => return from,
+    };
+
+    let elapsed = get_seconds_between(points[previous].time, time) as f64;
+    let span = match get_seconds_between(points[previous].time, points[next].time) {
+        0 => return Some(from),
+        span => span as f64,
+    };
+
+    Some(from + ((to - from) as f64 * elapsed / span).round() as i32)
+}
+
+pub fn is_interpolating(points: &[ResolvedPoint], time: NaiveTime) -> bool {
+    if points.is_empty() {
+        return false;
+    }
+
+    let (previous, next) = get_segment(points, time);
+    match (points[previous].temperature, points[next].temperature) {
+        (Some(from), Some(to)) => from != to,
+        _ => false,
+    }
+}
+
+// How far the transition at `time` has come, from 0 to 100. `None` if the
+// temperature isn't changing gradually.
+pub fn get_progress_at(points: &[ResolvedPoint], time: NaiveTime) -> Option<u8> {
+    if !is_interpolating(points, time) {
+        return None;
+    }
+
+    let (previous, next) = get_segment(points, time);
+    let elapsed = get_seconds_between(points[previous].time, time) as f64;
+    let span = get_seconds_between(points[previous].time, points[next].time) as f64;
+
+    Some((elapsed / span * 100.0).round() as u8)
+}
+
+pub fn get_duration_to_next_point(points: &[ResolvedPoint], time: NaiveTime) -> Duration {
+    if points.is_empty() {
+        return Duration::from_secs(24 * 60 * 60);
+    }
+
+    let (_, next) = get_segment(points, time);
+    Duration::from_secs(get_seconds_between(time, points[next].time) as u64)
+}
+
+// The first point after `time`, wrapping around midnight
+pub fn get_next_point(points: &[ResolvedPoint], time: NaiveTime) -> Option<&ResolvedPoint> {
+    if points.is_empty() {
+        return None;
+    }
+
+    let (_, next) = get_segment(points, time);
+    Some(&points[next])
+}
+
+// Compact duration such as "2h15m", rounded down to the minute
+pub fn format_duration(duration: Duration) -> String {
+    let minutes = duration.as_secs() / 60;
+    match (minutes / 60, minutes % 60) {
+        (0, minutes) => format!("{}m", minutes),
+        (hours, minutes) => format!("{}h{}m", hours, minutes),
+    }
+}
+
+pub fn format_temperature(temperature: Option<i32>) -> String {
+    match temperature {
+        Some(temperature) => format!("{}K", temperature),
+        None => "identity".to_string(),
+    }
+}
+
+#[cfg(test)]
+fn get_test_points() -> Vec<ResolvedPoint> {
+    let point = |time: &str, temperature: Option<i32>| ResolvedPoint {
+        time: NaiveTime::parse_from_str(time, "%H:%M").unwrap(),
+        label: None,
+        temperature,
+    };
+
+    // sunset -> 3400K, 22:00 -> 2800K, 00:00 -> 2300K, sunrise -> identity
+    vec![
+        point("00:00", Some(2300)),
+        point("06:00", None),
+        point("18:00", Some(3400)),
+        point("22:00", Some(2800)),
+    ]
+}
+
+#[test]
+fn test_get_temperature_at() {
+    let points = get_test_points();
+    let at =
+        |time: &str| get_temperature_at(&points, NaiveTime::parse_from_str(time, "%H:%M").unwrap());
+
+    assert_eq!(at("12:00"), None);
+    assert_eq!(at("18:00"), Some(3400));
+    assert_eq!(at("20:00"), Some(3100));
+    assert_eq!(at("23:00"), Some(2550));
+    // Holds the last temperature until switching to identity at sunrise
+    assert_eq!(at("03:00"), Some(2300));
+    assert_eq!(at("06:00"), None);
+}
+
+#[test]
+fn test_schedule_timing() {
+    let points = get_test_points();
+    let time = |time: &str| NaiveTime::parse_from_str(time, "%H:%M").unwrap();
+
+    assert!(is_interpolating(&points, time("20:00")));
+    assert!(!is_interpolating(&points, time("03:00")));
+    assert!(!is_interpolating(&points, time("12:00")));
+
+    assert_eq!(
+        get_duration_to_next_point(&points, time("12:00")),
+        Duration::from_secs(6 * 60 * 60)
+    );
+    assert_eq!(
+        get_duration_to_next_point(&points, time("23:00")),
+        Duration::from_secs(60 * 60)
+    );
+
+    assert_eq!(
+        get_next_point(&points, time("23:00")).unwrap().temperature,
+        Some(2300)
+    );
+    assert_eq!(get_next_point(&[], time("23:00")), None);
+
+    assert_eq!(get_progress_at(&points, time("20:00")), Some(50));
+    assert_eq!(get_progress_at(&points, time("03:00")), None);
+}
+
+#[test]
+fn test_format_duration() {
+    assert_eq!(format_duration(Duration::from_secs(59)), "0m");
+    assert_eq!(format_duration(Duration::from_secs(15 * 60)), "15m");
+    assert_eq!(format_duration(Duration::from_secs(135 * 60 + 30)), "2h15m");
+}
+
+#[test]
+fn test_parse_schedule() {
+    let schedule: Schedule = toml::from_str(
+        r#"[[points]]
+solar_event = "sunset"
+offset_minutes = -30
+temperature = 3400
+
+[[points]]
+time = "22:00"
+temperature = 2800
+
+[[points]]
+solar_event = "sunrise"
+temperature = "identity""#,
+    )
+    .unwrap();
+
+    assert_eq!(schedule.points.len(), 3);
+    assert_eq!(schedule.points[0].offset_minutes, -30);
+    assert_eq!(schedule.points[2].temperature, None);
+    assert!(schedule.validate().is_ok());
+
+    let invalid: Schedule = toml::from_str(
+        r#"[[points]]
+time = "22:00"
+solar_event = "sunset"
+temperature = 2800"#,
+    )
+    .unwrap();
+    assert!(invalid.validate().is_err());
+
+    assert!(
+        toml::from_str::<Schedule>(
+            r#"[[points]]
+time = "22:00"
+temperature = "warm""#,
+        )
+        .is_err()
+    );
+}
+
+#[test]
+fn test_time_format() {
+    #[derive(Deserialize)]
+    struct TimeFormatConfig {
+        time_format: TimeFormat,
+    }
+
+    let time = NaiveTime::from_hms_opt(18, 13, 0).unwrap();
+
+    assert_eq!(TimeFormat::TwentyFourHour.format(time), "18:13");
+    assert_eq!(TimeFormat::TwelveHour.format(time), "6:13 PM");
+    assert_eq!(
+        toml::from_str::<TimeFormatConfig>("time_format = \"12h\"")
+            .unwrap()
+            .time_format,
+        TimeFormat::TwelveHour
+    );
+}
+
+#[test]
+fn test_focus_blocks() {
+    let schedule: Schedule = toml::from_str(
+        r#"[[blocks]]
+name = "deep work"
+start = "09:00"
+end = "11:00"
+days = ["mon", "tue", "wed", "thu", "fri"]
+temperature = 4800
+
+[[blocks]]
+start = "23:00"
+end = "01:00"
+days = ["sat"]
+temperature = "identity""#,
+    )
+    .unwrap();
+    assert!(schedule.validate().is_ok());
+
+    let work = &schedule.blocks[0];
+    // 2025-06-23 is a Monday
+    assert!(work.is_active(Local.with_ymd_and_hms(2025, 6, 23, 9, 30, 0).unwrap()));
+    assert!(!work.is_active(Local.with_ymd_and_hms(2025, 6, 23, 11, 0, 0).unwrap()));
+    assert!(!work.is_active(Local.with_ymd_and_hms(2025, 6, 22, 9, 30, 0).unwrap()));
+    assert_eq!(work.get_label(), "deep work");
+
+    // Runs from Saturday night into Sunday
+    let late = &schedule.blocks[1];
+    assert!(late.is_active(Local.with_ymd_and_hms(2025, 6, 22, 0, 30, 0).unwrap()));
+    assert!(!late.is_active(Local.with_ymd_and_hms(2025, 6, 23, 0, 30, 0).unwrap()));
+
+    assert!(
+        toml::from_str::<Schedule>(
+            r#"[[blocks]]
+start = "09:00"
+end = "11:00"
+days = ["someday"]
+temperature = 4800"#,
+        )
+        .is_err()
+    );
+}
@@ -0,0 +1,282 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{Receiver, RecvTimeoutError, Sender, channel};
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+#[cfg(feature = "scripting")]
+use tracing::error;
+use tracing::{debug, info, info_span};
+
+use crate::events::{Event, Progress};
+#[cfg(feature = "scripting")]
+use crate::script::{Decision, Script};
+use crate::supervisor::{self, Worker};
+use crate::{
+    Config, format_log_time, get_duration_to_next_change, get_scheduled_temperature,
+    get_solar_times, get_transition_progress, is_in_transition,
+};
+
+// How often a script is polled, and how far ahead the speed of a transition
+// is measured
+const TRANSITION_STEP: Duration = Duration::from_secs(60);
+
+// During gradual transitions the temperature is updated about every
+// `KELVIN_STEP`, but no more often than `MIN_TRANSITION_STEP` for fast
+// transitions and no less often than `MAX_TRANSITION_STEP` for slow ones
+const KELVIN_STEP: f64 = 25.0;
+const MIN_TRANSITION_STEP: Duration = Duration::from_secs(5);
+const MAX_TRANSITION_STEP: Duration = Duration::from_secs(5 * 60);
+
+// Monotonic timers don't advance while the machine is suspended, so the
+// scheduler re-checks the wall clock at least this often.
+const MAX_SLEEP: Duration = Duration::from_secs(15 * 60);
+
+// How long to wait before the temperature has moved by `KELVIN_STEP`
+fn get_transition_step(kelvin_per_minute: f64) -> Duration {
+    if kelvin_per_minute.abs() < f64::EPSILON {
+        return MAX_TRANSITION_STEP;
+    }
+
+    Duration::from_secs_f64(KELVIN_STEP / kelvin_per_minute.abs() * 60.0)
+        .clamp(MIN_TRANSITION_STEP, MAX_TRANSITION_STEP)
+}
+
+// The next wakeup during a transition, from how fast the temperature is
+// changing right now. Never past the end of the transition.
+fn get_transition_deadline(config: &Config, now: DateTime<Utc>) -> DateTime<Utc> {
+    let later = now + TRANSITION_STEP;
+    let rate = match (
+        get_scheduled_temperature(config, now),
+        get_scheduled_temperature(config, later),
+    ) {
+        (Some(from), Some(to)) => (to - from) as f64 / TRANSITION_STEP.as_secs_f64() * 60.0,
+        _ => 0.0,
+    };
+
+    let step = get_transition_step(rate).min(get_duration_to_next_change(config, now));
+    now + step.max(MIN_TRANSITION_STEP)
+}
+
+// How long before the next change to wake up for the countdown notification,
+// if the change turns the filter on and is far enough away
+fn get_countdown(
+    config: &Config,
+    now: DateTime<Utc>,
+    sleep_duration: Duration,
+) -> Option<Duration> {
+    let countdown = Duration::from_secs(config.notifications.countdown_minutes? as u64 * 60);
+    let turns_on = get_scheduled_temperature(config, now).is_none()
+        && get_scheduled_temperature(config, now + sleep_duration + Duration::from_secs(60))
+            .is_some();
+
+    (turns_on && !countdown.is_zero() && sleep_duration > countdown).then_some(countdown)
+}
+
+// Blocks until the wall clock reaches `deadline`. Returns false if the
+// scheduler should stop instead.
+fn wait_until(rx: &Receiver<()>, deadline: DateTime<Utc>) -> bool {
+    loop {
+        let remaining = match (deadline - Utc::now()).to_std() {
+            Ok(remaining) if !remaining.is_zero() => remaining,
+            _ => return true,
+        };
+
+        match rx.recv_timeout(remaining.min(MAX_SLEEP)) {
+            Ok(()) | Err(RecvTimeoutError::Disconnected) => return false,
+            Err(RecvTimeoutError::Timeout) => {}
+        };
+    }
+}
+
+// Sends the scheduled temperature to the main loop whenever it changes.
+// Sending to, or dropping, the returned sender stops the scheduler.
+pub fn spawn_scheduler(
+    config: Config,
+    automatic: Arc<AtomicBool>,
+    main_tx: Sender<Event>,
+) -> Sender<()> {
+    let (tx, rx) = channel::<()>();
+
+    supervisor::spawn(Worker::Scheduler, main_tx.clone(), move || {
+        // The script engine isn't Send, so it lives on this thread
+        #[cfg(feature = "scripting")]
+        let script = config
+            .script
+            .as_ref()
+            .and_then(|path| match Script::load(path) {
+                Ok(script) => Some(script),
+                Err(e) => {
+                    error!("{:#}", e);
+                    None
+                }
+            });
+
+        // A script is polled, so only send changes
+        let mut last_scripted = None;
+        let mut last_progress = None;
+        // When the filter turns on, while waiting for the countdown before it
+        let mut countdown = None;
+
+        loop {
+            let now = Utc::now();
+            let deadline = {
+                let _span = info_span!("cycle").entered();
+
+                let (sunrise, sunset) = get_solar_times(&config, now);
+
+                #[cfg(feature = "scripting")]
+                let scripted = match script
+                    .as_ref()
+                    .map(|script| script.get_decision(&config, now))
+                {
+                    Some(Ok(Decision::Temperature(temperature))) => {
+                        Some(temperature.map(|temperature| config.clamp_temperature(temperature)))
+                    }
+                    Some(Ok(Decision::Schedule)) | None => None,
+                    Some(Err(e)) => {
+                        error!("{:#}", e);
+                        None
+                    }
+                };
+                #[cfg(not(feature = "scripting"))]
+                let scripted: Option<Option<i32>> = None;
+
+                let (temperature, progress) = match scripted {
+                    Some(temperature) => (temperature, None),
+                    None => {
+                        let temperature = get_scheduled_temperature(&config, now);
+                        let progress = temperature.zip(get_transition_progress(&config, now));
+                        (temperature, progress)
+                    }
+                };
+                debug!("Scheduled temperature: {:?}", temperature);
+
+                #[cfg(feature = "scripting")]
+                let polling = script.is_some();
+                #[cfg(not(feature = "scripting"))]
+                let polling = false;
+
+                if automatic.load(Ordering::Acquire) && last_scripted != Some(temperature) {
+                    // The main loop is gone while shutting down
+                    if main_tx.send(Event::Solar(temperature)).is_err() {
+                        break;
+                    }
+                    // Only once it was sent, so a decision made in manual
+                    // mode is still sent after switching back
+                    if polling {
+                        last_scripted = Some(temperature);
+                    }
+                }
+
+                if automatic.load(Ordering::Acquire) {
+                    let progress = progress.map(|(temperature, percent)| Progress {
+                        temperature,
+                        percent,
+                    });
+                    if progress != last_progress {
+                        if main_tx.send(Event::Progress(progress)).is_err() {
+                            break;
+                        }
+                        last_progress = progress;
+                    }
+                }
+
+                if polling {
+                    now + TRANSITION_STEP
+                } else if is_in_transition(&config, now) {
+                    get_transition_deadline(&config, now)
+                } else {
+                    info!(
+                        "Sunrise: {}, Sunset: {}",
+                        format_log_time(&config, sunrise, now),
+                        format_log_time(&config, sunset, now)
+                    );
+
+                    let sleep_duration = get_duration_to_next_change(&config, now);
+
+                    let sleep_seconds = sleep_duration.as_secs();
+                    let wake = now + sleep_duration;
+                    info!(
+                        "Sleeping for {:.2} hours, until {}",
+                        sleep_seconds as f64 / 3600.,
+                        format_log_time(&config, wake.time(), wake)
+                    );
+
+                    match get_countdown(&config, now, sleep_duration) {
+                        Some(countdown_duration) => {
+                            countdown = Some(wake);
+                            now + sleep_duration - countdown_duration
+                        }
+                        // Small delay to prevent re-triggering due to time drift
+                        None => now + sleep_duration + Duration::from_secs(60),
+                    }
+                }
+            };
+
+            if !wait_until(&rx, deadline) {
+                break;
+            }
+
+            if let Some(at) = countdown.take()
+                && automatic.load(Ordering::Acquire)
+                && main_tx.send(Event::Countdown(at)).is_err()
+            {
+                break;
+            }
+        }
+    });
+
+    tx
+}
+
+#[test]
+fn test_scheduler_stops_without_main_loop() {
+    let (main_tx, main_rx) = channel::<Event>();
+    drop(main_rx);
+
+    let tx = spawn_scheduler(Config::default(), Arc::new(AtomicBool::new(true)), main_tx);
+
+    // The thread ends instead of panicking, which drops its receiver
+    let stopped = (0..50).any(|_| {
+        std::thread::sleep(Duration::from_millis(20));
+        tx.send(()).is_err()
+    });
+    assert!(stopped);
+}
+
+#[test]
+fn test_get_transition_step() {
+    assert_eq!(get_transition_step(0.0), MAX_TRANSITION_STEP);
+    // 3500K over half an hour
+    assert_eq!(
+        get_transition_step(-3500.0 / 30.0),
+        Duration::from_secs_f64(25.0 / (3500.0 / 30.0) * 60.0)
+    );
+    assert_eq!(get_transition_step(1000.0), MIN_TRANSITION_STEP);
+    assert_eq!(get_transition_step(1.0), MAX_TRANSITION_STEP);
+}
+
+#[test]
+fn test_get_countdown() {
+    use chrono::TimeZone;
+
+    let mut config = Config::default();
+    // The afternoon in Nairobi, and the night after
+    let afternoon = Utc.with_ymd_and_hms(2025, 6, 21, 12, 0, 0).unwrap();
+    let night = Utc.with_ymd_and_hms(2025, 6, 21, 20, 0, 0).unwrap();
+    let until_sunset = get_duration_to_next_change(&config, afternoon);
+    let until_sunrise = get_duration_to_next_change(&config, night);
+    assert_eq!(get_countdown(&config, afternoon, until_sunset), None);
+
+    config.notifications.countdown_minutes = Some(15);
+    assert_eq!(
+        get_countdown(&config, afternoon, until_sunset),
+        Some(Duration::from_secs(15 * 60))
+    );
+    assert_eq!(get_countdown(&config, night, until_sunrise), None);
+
+    // Too close to count down
+    config.notifications.countdown_minutes = Some(24 * 60);
+    assert_eq!(get_countdown(&config, afternoon, until_sunset), None);
+}
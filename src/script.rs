@@ -0,0 +1,160 @@
+use std::fs;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::{Result, anyhow};
+use chrono::{DateTime, Local, Timelike, Utc};
+use hyprsunset_overdrive::solar::get_solar_elevation;
+use rhai::{AST, Dynamic, Engine, Map, Scope};
+
+use crate::Config;
+
+// What the script asked for
+#[derive(Debug, PartialEq)]
+pub enum Decision {
+    // `None` disables the filter
+    Temperature(Option<i32>),
+    // Fall back to the configured schedule
+    Schedule,
+}
+
+// A user script defining `fn temperature(ctx)`, see the README for the
+// fields of `ctx`.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self> {
+        let engine = Engine::new();
+        let ast = match engine.compile_file(path.to_path_buf()) {
+            Ok(ast) => ast,
+            Err(e) => return Err(anyhow!("Failed to compile {:?}: {}", path, e)),
+        };
+
+        Ok(Self { engine, ast })
+    }
+
+    pub fn get_decision(&self, config: &Config, now: DateTime<Utc>) -> Result<Decision> {
+        let ctx = get_context(config, now);
+
+        let result: Dynamic =
+            match self
+                .engine
+                .call_fn(&mut Scope::new(), &self.ast, "temperature", (ctx,))
+            {
+                Ok(result) => result,
+                Err(e) => return Err(anyhow!("Script failed: {}", e)),
+            };
+
+        parse_decision(result)
+    }
+}
+
+// Integers are temperatures, "identity" disables the filter and `()`
+// defers to the schedule
+fn parse_decision(result: Dynamic) -> Result<Decision> {
+    if result.is_unit() {
+        return Ok(Decision::Schedule);
+    }
+
+    if let Ok(temperature) = result.as_int() {
+        return Ok(Decision::Temperature(Some(temperature as i32)));
+    }
+
+    match result.into_string() {
+        Ok(name) if name == "identity" => Ok(Decision::Temperature(None)),
+        Ok(name) => Err(anyhow!("Script returned unknown value \"{}\"", name)),
+        Err(type_name) => Err(anyhow!("Script returned unexpected {}", type_name)),
+    }
+}
+
+fn get_context(config: &Config, now: DateTime<Utc>) -> Map {
+    let local = now.with_timezone(&Local);
+    let mut ctx = Map::new();
+
+    ctx.insert("datetime".into(), Dynamic::from(local.to_rfc3339()));
+    ctx.insert("hour".into(), Dynamic::from(local.hour() as i64));
+    ctx.insert("minute".into(), Dynamic::from(local.minute() as i64));
+    ctx.insert(
+        "elevation".into(),
+        Dynamic::from(get_solar_elevation(config.latitude, config.longitude, now)),
+    );
+    ctx.insert(
+        "battery".into(),
+        match get_battery_capacity() {
+            Some(capacity) => Dynamic::from(capacity),
+            None => Dynamic::UNIT,
+        },
+    );
+    ctx.insert(
+        "window_class".into(),
+        match get_active_window_class() {
+            Some(class) => Dynamic::from(class),
+            None => Dynamic::UNIT,
+        },
+    );
+
+    ctx
+}
+
+// Percentage of the first battery, if there is one
+fn get_battery_capacity() -> Option<i64> {
+    let entries = fs::read_dir("/sys/class/power_supply").ok()?;
+
+    entries.filter_map(|entry| entry.ok()).find_map(|entry| {
+        let kind = fs::read_to_string(entry.path().join("type")).ok()?;
+        if kind.trim() != "Battery" {
+            return None;
+        }
+
+        let capacity = fs::read_to_string(entry.path().join("capacity")).ok()?;
+        capacity.trim().parse().ok()
+    })
+}
+
+fn get_active_window_class() -> Option<String> {
+    let output = Command::new("hyprctl")
+        .args(["activewindow", "-j"])
+        .output()
+        .ok()?;
+
+    get_json_string(&String::from_utf8_lossy(&output.stdout), "class")
+}
+
+// Reads a top level string field without pulling in a JSON parser. Good
+// enough for hyprctl's output, which doesn't escape window classes.
+fn get_json_string(json: &str, key: &str) -> Option<String> {
+    let (_, rest) = json.split_once(&format!("\"{}\":", key))?;
+    let rest = rest.trim_start().strip_prefix('"')?;
+    let (value, _) = rest.split_once('"')?;
+
+    Some(value.to_string())
+}
+
+#[test]
+fn test_parse_decision() {
+    assert_eq!(
+        parse_decision(Dynamic::from(2800_i64)).unwrap(),
+        Decision::Temperature(Some(2800))
+    );
+    assert_eq!(
+        parse_decision(Dynamic::from("identity".to_string())).unwrap(),
+        Decision::Temperature(None)
+    );
+    assert_eq!(parse_decision(Dynamic::UNIT).unwrap(), Decision::Schedule);
+    assert!(parse_decision(Dynamic::from("warm".to_string())).is_err());
+}
+
+#[test]
+fn test_get_json_string() {
+    let json = r#"{
+    "address": "0x5f8a3c0",
+    "class": "firefox",
+    "title": "Mozilla Firefox"
+}"#;
+
+    assert_eq!(get_json_string(json, "class"), Some("firefox".to_string()));
+    assert_eq!(get_json_string("{}", "class"), None);
+}
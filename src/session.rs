@@ -0,0 +1,142 @@
+use std::fs;
+use std::io::{BufRead, BufReader};
+use std::os::unix::net::UnixStream;
+use std::path::PathBuf;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use tracing::{debug, warn};
+
+use crate::events::Event;
+use crate::supervisor::{self, Worker};
+
+// Short, so the screen is only briefly left at the wrong temperature when
+// unlocking resets the gamma tables. Only the locker's own /proc entry is
+// read this often.
+const POLL_INTERVAL: Duration = Duration::from_secs(2);
+
+// How often all of /proc is scanned for a locker that started
+const SCAN_INTERVAL: Duration = Duration::from_secs(10);
+
+// `comm` is the contents of /proc/PID/comm, and `lockers` the
+// `screen_lockers`
+fn is_locker(comm: &str, lockers: &[String]) -> bool {
+    lockers.iter().any(|locker| locker == comm.trim_end())
+}
+
+// Checks the name as well, in case the PID was reused after the locker
+// exited
+fn is_locker_running(pid: u32, lockers: &[String]) -> bool {
+    fs::read_to_string(format!("/proc/{}/comm", pid)).is_ok_and(|comm| is_locker(&comm, lockers))
+}
+
+// The PID of a running screen locker, from /proc
+fn find_locker(lockers: &[String]) -> Option<u32> {
+    if lockers.is_empty() {
+        return None;
+    }
+    let entries = fs::read_dir("/proc").ok()?;
+
+    entries
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| entry.file_name().to_str()?.parse().ok())
+        .find(|&pid| is_locker_running(pid, lockers))
+}
+
+// Sends `Event::Unlocked` whenever one of the `lockers` exits. A lock that
+// starts and ends between two scans goes unnoticed.
+pub fn spawn_unlock_watcher(lockers: Vec<String>, main_tx: Sender<Event>) {
+    supervisor::spawn(Worker::UnlockWatcher, main_tx.clone(), move || {
+        let mut locker = find_locker(&lockers);
+
+        loop {
+            let Some(pid) = locker else {
+                thread::sleep(SCAN_INTERVAL);
+                locker = find_locker(&lockers);
+                if let Some(pid) = locker {
+                    debug!("Screen locked by PID {}", pid);
+                }
+                continue;
+            };
+
+            thread::sleep(POLL_INTERVAL);
+            if is_locker_running(pid, &lockers) {
+                continue;
+            }
+
+            locker = None;
+            if main_tx.send(Event::Unlocked).is_err() {
+                break;
+            }
+        }
+    });
+}
+
+// A line from Hyprland's event socket, e.g. "monitoradded>>DP-1". The
+// "monitoraddedv2" line that comes with it is for the same monitor.
+fn parse_monitor_added(line: &str) -> Option<&str> {
+    line.strip_prefix("monitoradded>>")
+        .filter(|name| !name.is_empty())
+}
+
+fn get_event_socket_path() -> Option<PathBuf> {
+    let runtime_dir = std::env::var_os("XDG_RUNTIME_DIR")?;
+    let his = std::env::var_os("HYPRLAND_INSTANCE_SIGNATURE")?;
+    Some(
+        PathBuf::from(runtime_dir)
+            .join("hypr")
+            .join(his)
+            .join(".socket2.sock"),
+    )
+}
+
+// Sends `Event::MonitorAdded` for every monitor Hyprland adds, e.g. when one
+// is plugged in. Stops when Hyprland closes the socket.
+pub fn spawn_monitor_watcher(main_tx: Sender<Event>) {
+    supervisor::spawn(Worker::MonitorWatcher, main_tx.clone(), move || {
+        let Some(path) = get_event_socket_path() else {
+            return;
+        };
+        let socket = match UnixStream::connect(&path) {
+            Ok(socket) => socket,
+            Err(e) => {
+                warn!("Failed to connect to {}: {}", path.display(), e);
+                return;
+            }
+        };
+
+        for line in BufReader::new(socket).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let Some(name) = parse_monitor_added(&line) else {
+                continue;
+            };
+            if main_tx.send(Event::MonitorAdded(name.to_string())).is_err() {
+                return;
+            }
+        }
+        debug!("Hyprland closed its event socket");
+    });
+}
+
+#[test]
+fn test_is_locker() {
+    let lockers = ["hyprlock", "swaylock"].map(String::from);
+    assert!(is_locker("hyprlock\n", &lockers));
+    assert!(is_locker("swaylock", &lockers));
+    assert!(!is_locker("hyprland\n", &lockers));
+    assert!(!is_locker("gtklock\n", &lockers));
+}
+
+#[test]
+fn test_parse_monitor_added() {
+    assert_eq!(parse_monitor_added("monitoradded>>DP-1"), Some("DP-1"));
+    assert_eq!(
+        parse_monitor_added("monitoraddedv2>>1,DP-1,Dell U2720Q"),
+        None
+    );
+    assert_eq!(parse_monitor_added("monitorremoved>>DP-1"), None);
+    assert_eq!(parse_monitor_added("monitoradded>>"), None);
+}
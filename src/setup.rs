@@ -0,0 +1,221 @@
+use std::fs;
+use std::io::ErrorKind;
+use std::path::Path;
+
+use anyhow::{Context, Result, anyhow};
+use gtk::prelude::*;
+
+use crate::calibrate::set_top_level_value;
+use crate::config::Config;
+use crate::location::{self, Axis, find_city, parse_coordinate};
+
+// The keys a location is made of
+const LOCATION_KEYS: [&str; 5] = [
+    "city",
+    "follow_timezone",
+    "latitude",
+    "longitude",
+    "altitude",
+];
+
+// Comments on the default location, which no longer apply once it is changed
+const DEFAULT_LOCATION_COMMENTS: [&str; 2] = [
+    "# Coordinates for Nairobi, Kenya",
+    "# Altitude of Nairobi, Kenya in meters. You can set it as 0.",
+];
+
+// Where the sunrise and sunset come from, as picked on first start
+#[derive(Debug, Clone, PartialEq)]
+enum Location {
+    // `follow_timezone`, the closest thing to an automatic location
+    Timezone,
+    City(String),
+    Coordinates(f64, f64),
+}
+
+fn parse_coordinates(latitude: &str, longitude: &str) -> Result<(f64, f64)> {
+    Ok((
+        parse_coordinate(latitude, Axis::Latitude)?,
+        parse_coordinate(longitude, Axis::Longitude)?,
+    ))
+}
+
+// Replaces the location in the config, keeping everything else. Keys that
+// would take precedence over the new location are removed.
+fn set_location(contents: &str, location: &Location) -> String {
+    let values = match location {
+        Location::Timezone => [None, Some("true".to_string()), None, None, None],
+        Location::City(city) => [
+            Some(toml::Value::String(city.clone()).to_string()),
+            None,
+            None,
+            None,
+            None,
+        ],
+        Location::Coordinates(latitude, longitude) => [
+            None,
+            None,
+            Some(latitude.to_string()),
+            Some(longitude.to_string()),
+            // Better than Nairobi's
+            Some("0".to_string()),
+        ],
+    };
+
+    let contents: String = contents
+        .lines()
+        .filter(|line| !DEFAULT_LOCATION_COMMENTS.contains(&line.trim()))
+        .map(|line| format!("{}\n", line))
+        .collect();
+    LOCATION_KEYS
+        .into_iter()
+        .zip(values)
+        .fold(contents, |contents, (key, value)| {
+            set_top_level_value(&contents, key, value.as_deref())
+        })
+}
+
+// Shows the picker until a valid location is given. `None` if skipped.
+fn pick_location() -> Result<Option<Location>> {
+    if let Err(e) = gtk::init() {
+        return Err(anyhow!("Failed to initialize GTK: {}", e));
+    }
+
+    let dialog = gtk::Dialog::with_buttons(
+        Some("hyprsunset-overdrive"),
+        None::<&gtk::Window>,
+        gtk::DialogFlags::MODAL,
+        &[
+            ("Skip", gtk::ResponseType::Cancel),
+            ("Save", gtk::ResponseType::Accept),
+        ],
+    );
+    dialog.set_default_response(gtk::ResponseType::Accept);
+    dialog.set_resizable(false);
+
+    let content = dialog.content_area();
+    content.set_border_width(12);
+    content.set_spacing(6);
+
+    let intro = gtk::Label::new(Some(
+        "Where are you? The filter follows the sunset and sunrise there.",
+    ));
+    intro.set_xalign(0.0);
+
+    let timezone = gtk::RadioButton::with_label("Use automatic location (from the timezone)");
+    let city = gtk::RadioButton::with_label_from_widget(&timezone, "Pick a city");
+    let cities = gtk::ComboBoxText::with_entry();
+    for name in location::get_cities().map(|city| city.get_full_name()) {
+        cities.append_text(&name);
+    }
+    let coordinates = gtk::RadioButton::with_label_from_widget(&timezone, "Enter coordinates");
+    let latitude = gtk::Entry::new();
+    latitude.set_placeholder_text(Some("Latitude, e.g. 52.52 or 52°31'N"));
+    let longitude = gtk::Entry::new();
+    longitude.set_placeholder_text(Some("Longitude, e.g. 13.405 or 13°24'E"));
+    let error = gtk::Label::new(None);
+    error.set_xalign(0.0);
+
+    // Typing into a field picks its option
+    let city_button = city.clone();
+    cities.connect_changed(move |_| city_button.set_active(true));
+    for entry in [&latitude, &longitude] {
+        let coordinates_button = coordinates.clone();
+        entry.connect_changed(move |_| coordinates_button.set_active(true));
+    }
+
+    cities.set_margin_start(24);
+    latitude.set_margin_start(24);
+    longitude.set_margin_start(24);
+    content.add(&intro);
+    content.add(&timezone);
+    content.add(&city);
+    content.add(&cities);
+    content.add(&coordinates);
+    content.add(&latitude);
+    content.add(&longitude);
+    content.add(&error);
+    dialog.show_all();
+
+    let location = loop {
+        if dialog.run() != gtk::ResponseType::Accept {
+            break None;
+        }
+
+        let location = if city.is_active() {
+            let query = cities.active_text().map(|text| text.to_string());
+            find_city(query.as_deref().unwrap_or_default())
+                .map(|city| Location::City(city.get_full_name()))
+        } else if coordinates.is_active() {
+            parse_coordinates(&latitude.text(), &longitude.text())
+                .map(|(latitude, longitude)| Location::Coordinates(latitude, longitude))
+        } else {
+            Ok(Location::Timezone)
+        };
+
+        match location {
+            Ok(location) => break Some(location),
+            Err(e) => error.set_text(&format!("{:#}", e)),
+        };
+    };
+    dialog.close();
+
+    Ok(location)
+}
+
+// `setup`, run by the daemon on first start and from the tray menu. Writes
+// the picked location to the config, or nothing if the picker is skipped.
+// Without a config yet, that leaves the default config to the daemon.
+pub fn run(config_path: &Path) -> Result<()> {
+    let Some(location) = pick_location()? else {
+        return Ok(());
+    };
+
+    let contents = match fs::read_to_string(config_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == ErrorKind::NotFound => "temperature = 3000\n".to_string(),
+        Err(e) => return Err(e).context(format!("Failed to read {:?}", config_path)),
+    };
+    let contents = set_location(&contents, &location);
+    Config::parse(&contents).context("The picked location produced an invalid config")?;
+
+    if let Some(parent) = config_path.parent() {
+        fs::create_dir_all(parent).context("Failed to create config directory")?;
+    };
+    match fs::write(config_path, contents) {
+        Ok(_) => Ok(()),
+        Err(e) => Err(e).context(format!("Failed to write {:?}", config_path)),
+    }
+}
+
+#[test]
+fn test_parse_coordinates() {
+    assert_eq!(
+        parse_coordinates(" 52.52", "13.405 ").unwrap(),
+        (52.52, 13.405)
+    );
+    assert!(parse_coordinates("91", "0").is_err());
+    assert!(parse_coordinates("0", "east").is_err());
+}
+
+#[test]
+fn test_set_location() {
+    let contents = "temperature = 3000\n# Coordinates for Nairobi, Kenya\nlatitude = -1.2921\nlongitude = 36.8219\n# Altitude of Nairobi, Kenya in meters. You can set it as 0.\naltitude = 1795\n";
+
+    assert_eq!(
+        set_location(contents, &Location::City("Berlin, DE".to_string())),
+        "temperature = 3000\ncity = \"Berlin, DE\"\n"
+    );
+    assert_eq!(
+        set_location(contents, &Location::Coordinates(52.52, 13.405)),
+        "temperature = 3000\nlatitude = 52.52\nlongitude = 13.405\naltitude = 0\n"
+    );
+
+    let config = Config::parse(&set_location(
+        "city = \"Berlin\"\n\n[notifications]\nmorning = false\n",
+        &Location::Timezone,
+    ))
+    .unwrap();
+    assert!(config.follow_timezone && config.city.is_none());
+    assert!(!config.notifications.morning);
+}
@@ -0,0 +1,176 @@
+//! Sunrise, sunset and sun position calculations.
+
+use std::time::Duration;
+
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use sunrise::{Coordinates, DawnType, SolarDay, SolarEvent};
+
+use crate::error::ScheduleError;
+
+/// Where `now` falls relative to a day's sunrise and sunset.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PartOfDay {
+    BeforeDaytime,
+    Daytime,
+    AfterDaytime,
+}
+
+fn get_coordinates(latitude: f64, longitude: f64) -> Result<Coordinates, ScheduleError> {
+    Coordinates::new(latitude, longitude).ok_or(ScheduleError::InvalidCoordinates {
+        latitude,
+        longitude,
+    })
+}
+
+/// Sunrise and sunset on `date` at the given location, with `altitude` in
+/// meters. Fails if the coordinates are out of range.
+pub fn get_sunrise_and_sunset(
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    date: NaiveDate,
+) -> Result<(DateTime<Utc>, DateTime<Utc>), ScheduleError> {
+    let coord = get_coordinates(latitude, longitude)?;
+    let solarday = SolarDay::new(coord, date).with_altitude(altitude);
+
+    let sunrise = solarday.event_time(SolarEvent::Sunrise);
+    let sunset = solarday.event_time(SolarEvent::Sunset);
+
+    Ok((sunrise, sunset))
+}
+
+/// Classifies `now` against the sunrise and sunset of a single day.
+pub fn get_part_of_day(
+    now: DateTime<Utc>,
+    sunrise: DateTime<Utc>,
+    sunset: DateTime<Utc>,
+) -> PartOfDay {
+    if now < sunrise {
+        PartOfDay::BeforeDaytime
+    } else if now < sunset {
+        PartOfDay::Daytime
+    } else {
+        PartOfDay::AfterDaytime
+    }
+}
+
+/// Time until the next sunrise or sunset. After sunset, this is the time
+/// until the end of the day of `now`, when the next day's times apply.
+pub fn get_duration_to_next_event(
+    now: DateTime<Utc>,
+    sunrise: DateTime<Utc>,
+    sunset: DateTime<Utc>,
+) -> Duration {
+    let until = match get_part_of_day(now, sunrise, sunset) {
+        PartOfDay::BeforeDaytime => sunrise,
+        PartOfDay::Daytime => sunset,
+        PartOfDay::AfterDaytime => now
+            .date_naive()
+            .and_time(NaiveTime::from_hms_opt(23, 59, 59).unwrap())
+            .and_utc(),
+    };
+
+    (until - now).to_std().unwrap_or_default()
+}
+
+/// Start of civil dawn and end of civil dusk on `date`.
+pub fn get_civil_twilight(
+    latitude: f64,
+    longitude: f64,
+    altitude: f64,
+    date: NaiveDate,
+) -> Result<(NaiveTime, NaiveTime), ScheduleError> {
+    let coord = get_coordinates(latitude, longitude)?;
+    let solarday = SolarDay::new(coord, date).with_altitude(altitude);
+
+    let dawn = solarday
+        .event_time(SolarEvent::Dawn(DawnType::Civil))
+        .time();
+    let dusk = solarday
+        .event_time(SolarEvent::Dusk(DawnType::Civil))
+        .time();
+
+    Ok((dawn, dusk))
+}
+
+/// Elevation of the sun above the horizon in degrees, using the low precision
+/// formulas from the Astronomical Almanac. Refraction is not accounted for.
+pub fn get_solar_elevation(latitude: f64, longitude: f64, time: DateTime<Utc>) -> f64 {
+    // Days since J2000.0
+    let days = time.timestamp() as f64 / 86400.0 - 10957.5;
+
+    let mean_anomaly = (357.529 + 0.98560028 * days).to_radians();
+    let mean_longitude = 280.459 + 0.98564736 * days;
+    let ecliptic_longitude =
+        (mean_longitude + 1.915 * mean_anomaly.sin() + 0.020 * (2.0 * mean_anomaly).sin())
+            .to_radians();
+    let obliquity = (23.439 - 0.00000036 * days).to_radians();
+
+    let right_ascension =
+        (obliquity.cos() * ecliptic_longitude.sin()).atan2(ecliptic_longitude.cos());
+    let declination = (obliquity.sin() * ecliptic_longitude.sin()).asin();
+
+    let sidereal_time = (18.697374558 + 24.06570982441908 * days) * 15.0 + longitude;
+    let hour_angle = sidereal_time.to_radians() - right_ascension;
+
+    let latitude = latitude.to_radians();
+    (latitude.sin() * declination.sin() + latitude.cos() * declination.cos() * hour_angle.cos())
+        .asin()
+        .to_degrees()
+}
+
+#[test]
+fn test_get_solar_elevation() {
+    let date = NaiveDate::from_ymd_opt(2025, 4, 13).unwrap();
+    let solarday = SolarDay::new(Coordinates::new(-1.2921, 36.8219).unwrap(), date);
+
+    // The sun's upper rim touches the horizon at sunrise and sunset
+    for event in [SolarEvent::Sunrise, SolarEvent::Sunset] {
+        let elevation = get_solar_elevation(-1.2921, 36.8219, solarday.event_time(event));
+        assert!((elevation + 0.83).abs() < 0.5, "{}", elevation);
+    }
+
+    // Nearly overhead at noon close to the equator
+    let noon = date.and_hms_opt(9, 30, 0).unwrap().and_utc();
+    assert!(get_solar_elevation(-1.2921, 36.8219, noon) > 75.0);
+}
+
+#[test]
+fn test_part_of_day() {
+    let date = NaiveDate::from_ymd_opt(1970, 1, 1).unwrap();
+    let (sunrise, sunset) = get_sunrise_and_sunset(0., 0., 0., date).unwrap();
+    let at = |time: &str| {
+        date.and_time(NaiveTime::parse_from_str(time, "%H:%M").unwrap())
+            .and_utc()
+    };
+
+    assert_eq!(
+        get_part_of_day(at("01:30"), sunrise, sunset),
+        PartOfDay::BeforeDaytime
+    );
+    assert_eq!(
+        get_part_of_day(at("10:30"), sunrise, sunset),
+        PartOfDay::Daytime
+    );
+    assert_eq!(
+        get_duration_to_next_event(at("10:30"), sunrise, sunset),
+        Duration::from_secs(27428)
+    );
+    assert_eq!(
+        get_duration_to_next_event(at("23:30"), sunrise, sunset),
+        Duration::from_secs(1799)
+    );
+}
+
+#[test]
+fn test_invalid_coordinates() {
+    let date = NaiveDate::from_ymd_opt(2025, 6, 21).unwrap();
+    assert_eq!(
+        get_sunrise_and_sunset(91., 0., 0., date),
+        Err(ScheduleError::InvalidCoordinates {
+            latitude: 91.,
+            longitude: 0.
+        })
+    );
+    assert!(get_civil_twilight(0., 181., 0., date).is_err());
+}
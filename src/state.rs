@@ -0,0 +1,97 @@
+use std::fs;
+use std::path::PathBuf;
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use tracing::info;
+
+use crate::paths;
+
+#[derive(Debug, Clone, Copy, PartialEq, Deserialize, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    Day,
+    Night,
+}
+
+// State that should survive a restart of the daemon.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+#[serde(default)]
+pub struct State {
+    // When false, the scheduler does not touch the filter and the last
+    // manual selection sticks.
+    pub automatic: bool,
+    pub mode: Option<Mode>,
+    // Picked from the tray. `None` picks the profile by network.
+    pub profile: Option<String>,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        Self {
+            automatic: true,
+            mode: None,
+            profile: None,
+        }
+    }
+}
+
+impl State {
+    fn get_path() -> Result<PathBuf> {
+        Ok(paths::get_state_dir()?.join("state.toml"))
+    }
+
+    pub fn load() -> Result<Self> {
+        let state_path = Self::get_path()?;
+
+        if !state_path.exists() {
+            return Ok(Self::default());
+        }
+
+        let state_contents = match fs::read_to_string(&state_path) {
+            Ok(state_str) => state_str,
+            Err(e) => return Err(e).context("Failed to read state file"),
+        };
+
+        match toml::from_str(&state_contents) {
+            Ok(state) => {
+                info!("State loaded");
+                Ok(state)
+            }
+            Err(e) => Err(e).context("Failed to parse state file"),
+        }
+    }
+
+    pub fn save(&self) -> Result<()> {
+        let state_path = Self::get_path()?;
+
+        if let Some(parent) = state_path.parent() {
+            fs::create_dir_all(parent).context("Failed to create state directory")?;
+        };
+
+        let state_contents = match toml::to_string(self) {
+            Ok(state_str) => state_str,
+            Err(e) => return Err(e).context("Failed to serialize state"),
+        };
+
+        match fs::write(&state_path, state_contents) {
+            Ok(_) => Ok(()),
+            Err(e) => Err(e).context("Failed to write state file"),
+        }
+    }
+}
+
+#[test]
+fn test_state_round_trip() {
+    let state = State {
+        automatic: false,
+        mode: Some(Mode::Night),
+        profile: Some("office".to_string()),
+    };
+
+    let serialized = toml::to_string(&state).unwrap();
+    assert_eq!(toml::from_str::<State>(&serialized).unwrap(), state);
+
+    // Missing fields fall back to automatic mode
+    assert_eq!(toml::from_str::<State>("").unwrap(), State::default());
+}
@@ -0,0 +1,75 @@
+use std::any::Any;
+use std::fmt;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::Sender;
+use std::thread::{self, JoinHandle};
+
+use tracing::error;
+
+use crate::events::Event;
+
+// The threads next to the main loop
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Worker {
+    Scheduler,
+    Tray,
+    Control,
+    NetworkWatcher,
+    TimezoneWatcher,
+    UnlockWatcher,
+    MonitorWatcher,
+    Watchdog,
+}
+
+impl fmt::Display for Worker {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Worker::Scheduler => "scheduler",
+            Worker::Tray => "tray",
+            Worker::Control => "control socket",
+            Worker::NetworkWatcher => "network watcher",
+            Worker::TimezoneWatcher => "timezone watcher",
+            Worker::UnlockWatcher => "unlock watcher",
+            Worker::MonitorWatcher => "monitor watcher",
+            Worker::Watchdog => "watchdog",
+        };
+        f.write_str(name)
+    }
+}
+
+// What was passed to `panic!`
+fn get_panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        return message.to_string();
+    }
+
+    match payload.downcast_ref::<String>() {
+        Some(message) => message.clone(),
+        None => "unknown panic".to_string(),
+    }
+}
+
+// Spawns a thread that tells the main loop if it panics, instead of
+// silently leaving the daemon without it
+pub fn spawn<F>(worker: Worker, main_tx: Sender<Event>, f: F) -> JoinHandle<()>
+where
+    F: FnOnce() + Send + 'static,
+{
+    thread::spawn(move || {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(f)) {
+            let message = get_panic_message(payload.as_ref());
+            error!("The {} thread panicked: {}", worker, message);
+            // The main loop may be gone already
+            let _ = main_tx.send(Event::WorkerDied(worker, message));
+        }
+    })
+}
+
+#[test]
+fn test_get_panic_message() {
+    let payload = panic::catch_unwind(|| panic!("channel closed")).unwrap_err();
+    assert_eq!(get_panic_message(payload.as_ref()), "channel closed");
+
+    let payload = panic::catch_unwind(|| panic!("{} failed", "send")).unwrap_err();
+    assert_eq!(get_panic_message(payload.as_ref()), "send failed");
+}
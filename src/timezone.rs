@@ -0,0 +1,185 @@
+use std::fs;
+use std::sync::mpsc::Sender;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Result, anyhow};
+use chrono::FixedOffset;
+
+use crate::events::{ConfigChange, Event};
+use crate::supervisor::{self, Worker};
+
+// timedated replaces the /etc/localtime link when the timezone changes, so
+// checking the link is enough
+const POLL_INTERVAL: Duration = Duration::from_secs(30);
+
+// Lists the principal location of every timezone
+const ZONE_TABS: [&str; 2] = [
+    "/usr/share/zoneinfo/zone1970.tab",
+    "/usr/share/zoneinfo/zone.tab",
+];
+
+// The IANA name of the system timezone, e.g. "Europe/Berlin"
+pub fn get_system_timezone() -> Option<String> {
+    if let Ok(tz) = std::env::var("TZ") {
+        let tz = tz.trim_start_matches(':');
+        if !tz.is_empty() {
+            return Some(tz.to_string());
+        }
+    }
+
+    let target = fs::read_link("/etc/localtime").ok()?;
+    let target = target.to_string_lossy();
+    let (_, name) = target.split_once("zoneinfo/")?;
+
+    Some(name.trim_start_matches("posix/").to_string())
+}
+
+// How the clocks changed between two UTC offsets, e.g. "forward 1 hour" at
+// the start of daylight saving time. `None` if they didn't.
+pub fn describe_clock_change(before: FixedOffset, after: FixedOffset) -> Option<String> {
+    let change = after.local_minus_utc() - before.local_minus_utc();
+    let direction = match change {
+        0 => return None,
+        change if change > 0 => "forward",
+        _ => "back",
+    };
+
+    let minutes = change.abs() / 60;
+    let amount = match (minutes / 60, minutes % 60) {
+        (1, 0) => "1 hour".to_string(),
+        (hours, 0) => format!("{} hours", hours),
+        (0, minutes) => format!("{} minutes", minutes),
+        (hours, minutes) => format!("{}h{:02}m", hours, minutes),
+    };
+
+    Some(format!("{} {}", direction, amount))
+}
+
+// Degrees from ISO 6709 `±DDMM` or `±DDMMSS`, with `degree_digits` digits
+// for the degrees
+fn parse_iso6709_part(part: &str, degree_digits: usize) -> Option<f64> {
+    let (sign, digits) = match part.split_at_checked(1)? {
+        ("+", digits) => (1.0, digits),
+        ("-", digits) => (-1.0, digits),
+        _ => return None,
+    };
+
+    let degrees: f64 = digits.get(..degree_digits)?.parse().ok()?;
+    let minutes: f64 = digits.get(degree_digits..degree_digits + 2)?.parse().ok()?;
+    let seconds: f64 = match digits.get(degree_digits + 2..) {
+        Some("") | None => 0.0,
+        Some(seconds) => seconds.parse().ok()?,
+    };
+
+    Some(sign * (degrees + minutes / 60.0 + seconds / 3600.0))
+}
+
+// Coordinates such as "+5230+01322" as (latitude, longitude)
+fn parse_iso6709(coordinates: &str) -> Option<(f64, f64)> {
+    let split = coordinates.get(1..)?.find(['+', '-'])? + 1;
+    let (latitude, longitude) = coordinates.split_at(split);
+
+    Some((
+        parse_iso6709_part(latitude, 2)?,
+        parse_iso6709_part(longitude, 3)?,
+    ))
+}
+
+// Finds `timezone` in the contents of a zone.tab style file
+fn find_in_zone_tab(zone_tab: &str, timezone: &str) -> Option<(f64, f64)> {
+    zone_tab
+        .lines()
+        .filter(|line| !line.starts_with('#'))
+        .find_map(|line| {
+            let fields: Vec<&str> = line.split('\t').collect();
+            match fields.as_slice() {
+                [_, coordinates, name, ..] if *name == timezone => parse_iso6709(coordinates),
+                _ => None,
+            }
+        })
+}
+
+// The coordinates of the city a timezone is named after. Far from exact,
+// but close enough to keep sunrise and sunset roughly right while
+// traveling.
+pub fn get_timezone_location(timezone: &str) -> Result<(f64, f64)> {
+    for path in ZONE_TABS {
+        let Ok(zone_tab) = fs::read_to_string(path) else {
+            continue;
+        };
+
+        if let Some(location) = find_in_zone_tab(&zone_tab, timezone) {
+            return Ok(location);
+        }
+    }
+
+    Err(anyhow!("No location known for timezone {}", timezone))
+}
+
+// Sends `ConfigChange::Timezone` whenever the system timezone changes
+pub fn spawn_timezone_watcher(main_tx: Sender<Event>) {
+    supervisor::spawn(Worker::TimezoneWatcher, main_tx.clone(), move || {
+        let mut current = get_system_timezone();
+
+        loop {
+            thread::sleep(POLL_INTERVAL);
+
+            let timezone = get_system_timezone();
+            if timezone == current {
+                continue;
+            }
+
+            current = timezone.clone();
+            if main_tx
+                .send(Event::ConfigChanged(ConfigChange::Timezone(timezone)))
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+}
+
+#[test]
+fn test_parse_iso6709() {
+    assert_eq!(
+        parse_iso6709("+5230+01322"),
+        Some((52.5, 13.0 + 22.0 / 60.0))
+    );
+    assert_eq!(
+        parse_iso6709("-0117+03649"),
+        Some((-1.0 - 17.0 / 60.0, 36.0 + 49.0 / 60.0))
+    );
+    let (latitude, longitude) = parse_iso6709("+404251-0740023").unwrap();
+    assert!((latitude - 40.714).abs() < 0.001);
+    assert!((longitude + 74.006).abs() < 0.001);
+    assert_eq!(parse_iso6709("5230"), None);
+}
+
+#[test]
+fn test_find_in_zone_tab() {
+    let zone_tab = "# comment\n\
+        DE,DK,NO,SE,SJ\t+5230+01322\tEurope/Berlin\tmost of Germany\n\
+        KE,DJ,ER,ET,KM,MG,SO,TZ,UG,YT\t-0117+03649\tAfrica/Nairobi\n";
+
+    let (latitude, _) = find_in_zone_tab(zone_tab, "Africa/Nairobi").unwrap();
+    assert!((latitude + 1.283).abs() < 0.001);
+    assert_eq!(find_in_zone_tab(zone_tab, "Europe/Paris"), None);
+}
+
+#[test]
+fn test_describe_clock_change() {
+    let offset = |minutes| FixedOffset::east_opt(minutes * 60).unwrap();
+
+    assert_eq!(
+        describe_clock_change(offset(60), offset(120)).as_deref(),
+        Some("forward 1 hour")
+    );
+    // Lord Howe Island
+    assert_eq!(
+        describe_clock_change(offset(660), offset(630)).as_deref(),
+        Some("back 30 minutes")
+    );
+    assert_eq!(describe_clock_change(offset(-300), offset(-300)), None);
+}
@@ -0,0 +1,863 @@
+use std::process::Command;
+use std::sync::mpsc::Sender;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use futures_channel::mpsc::{UnboundedSender, unbounded};
+use futures_util::StreamExt;
+use tracing::{debug, debug_span, error, info, warn};
+use tray_icon::Icon;
+
+use crate::events::{Event, Progress, TrayAction, TrayUpdate};
+use crate::icon_theme::IconTheme;
+use crate::supervisor::{self, Worker};
+
+// Generated by build.rs from the PNGs in assets/
+include!(concat!(env!("OUT_DIR"), "/icons.rs"));
+
+// RGBA pixels of an icon
+#[derive(Debug, Clone, PartialEq)]
+struct IconData {
+    rgba: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+impl IconData {
+    fn new(rgba: &[u8], (width, height): (u32, u32)) -> Self {
+        Self {
+            rgba: rgba.to_vec(),
+            width,
+            height,
+        }
+    }
+
+    // Recolors the icon, keeping the brightness and transparency of each pixel
+    fn to_tinted(&self, [red, green, blue]: [u8; 3]) -> Self {
+        let tint = |value: u8, channel: u8| (value as u16 * channel as u16 / 255) as u8;
+        let rgba = self
+            .rgba
+            .chunks_exact(4)
+            .flat_map(|pixel| {
+                let [r, g, b, a] = [pixel[0], pixel[1], pixel[2], pixel[3]];
+                let value = r.max(g).max(b);
+                [tint(value, red), tint(value, green), tint(value, blue), a]
+            })
+            .collect();
+
+        Self {
+            rgba,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    // Strikes the icon through from the top left to the bottom right, in the
+    // color of its brightest pixel
+    fn to_crossed_out(&self) -> Self {
+        let color = self
+            .rgba
+            .chunks_exact(4)
+            .filter(|pixel| pixel[3] > 0)
+            .max_by_key(|pixel| pixel[0].max(pixel[1]).max(pixel[2]))
+            .map_or([255, 255, 255], |pixel| [pixel[0], pixel[1], pixel[2]]);
+        let (width, height) = (self.width as i64, self.height as i64);
+        let thickness = (height / 12).max(1);
+
+        let mut rgba = self.rgba.clone();
+        for (i, pixel) in rgba.chunks_exact_mut(4).enumerate() {
+            let (x, y) = (i as i64 % width, i as i64 / width);
+            if (x * height - y * width).abs() < thickness * width {
+                pixel.copy_from_slice(&[color[0], color[1], color[2], 255]);
+            }
+        }
+
+        Self {
+            rgba,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    // How many rows from the bottom `with_fill` leaves as they are
+    fn get_filled_rows(&self, percent: u8) -> u32 {
+        self.height * percent.min(100) as u32 / 100
+    }
+
+    // Fades out the rows above `percent` of the height, so the icon fills up
+    // from the bottom as a transition goes on
+    fn with_fill(&self, percent: u8) -> Self {
+        let faded_rows = (self.height - self.get_filled_rows(percent)) as usize;
+        let mut rgba = self.rgba.clone();
+        for pixel in rgba
+            .chunks_exact_mut(4)
+            .take(faded_rows * self.width as usize)
+        {
+            pixel[3] /= 3;
+        }
+
+        Self {
+            rgba,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    // Draws `text`, made of digits, in white on a dark box in the bottom
+    // right corner. Left as is if it doesn't fit.
+    fn with_badge(&self, text: &str) -> Self {
+        let digits: Vec<&[u8; 5]> = text
+            .bytes()
+            .filter(u8::is_ascii_digit)
+            .map(|digit| &DIGITS[(digit - b'0') as usize])
+            .collect();
+        let scale = (self.height / 16).max(1) as usize;
+        // One pixel of padding around the digits and between them
+        let (box_width, box_height) = ((digits.len() * 4 + 1) * scale, 7 * scale);
+        let (width, height) = (self.width as usize, self.height as usize);
+        if digits.is_empty() || box_width > width || box_height > height {
+            return self.clone();
+        }
+
+        let (left, top) = (width - box_width, height - box_height);
+        let mut rgba = self.rgba.clone();
+        for y in 0..box_height {
+            for x in 0..box_width {
+                // In font pixels, counting from the top left of the padding
+                let (column, row) = (x / scale, y / scale);
+                let lit = (1..=5).contains(&row)
+                    && column >= 1
+                    && (column - 1) % 4 < 3
+                    && digits
+                        .get((column - 1) / 4)
+                        .is_some_and(|digit| (digit[row - 1] >> (2 - (column - 1) % 4)) & 1 == 1);
+
+                let i = ((top + y) * width + left + x) * 4;
+                rgba[i..i + 4].copy_from_slice(if lit {
+                    &[255, 255, 255, 255]
+                } else {
+                    &[0, 0, 0, 255]
+                });
+            }
+        }
+
+        Self {
+            rgba,
+            width: self.width,
+            height: self.height,
+        }
+    }
+
+    fn to_icon(&self) -> Result<Icon> {
+        match Icon::from_rgba(self.rgba.clone(), self.width, self.height) {
+            Ok(icon) => Ok(icon),
+            Err(e) => Err(e).context("Failed to create icon"),
+        }
+    }
+}
+
+// 3x5 pixel digits for the badge, one row per entry with the leftmost pixel
+// in the highest of the three bits
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+// Built once at startup. Switching icons only copies the cached pixels.
+struct Icons {
+    enabled: IconData,
+    disabled: IconData,
+    astro: IconData,
+    reading: IconData,
+    warning: IconData,
+    paused: IconData,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum IconKind {
+    Enabled,
+    Disabled,
+    // Astronomy mode uses a red version of the enabled icon, so it stands
+    // out from the normal night state.
+    Astro,
+    // Reading mode gets a paper-like tint
+    Reading,
+    // Shown while hyprsunset can't be reached
+    Warning,
+    // A crossed out, grey moon while paused or toggled off until later
+    Paused,
+}
+
+// The icon `name` from the theme, or `fallback` if the theme doesn't have it
+// or it fails to load
+fn load_themed(
+    theme: Option<&IconTheme>,
+    name: &str,
+    fallback: impl FnOnce() -> IconData,
+) -> IconData {
+    match theme.and_then(|theme| theme.load(name)) {
+        Some(Ok((rgba, size))) => IconData::new(&rgba, size),
+        Some(Err(e)) => {
+            warn!("{:#}, using the embedded icon", e);
+            fallback()
+        }
+        None => fallback(),
+    }
+}
+
+impl Icons {
+    fn load(theme: Option<&IconTheme>) -> Self {
+        let enabled = load_themed(theme, "enabled", || {
+            IconData::new(ENABLED_ICON_RGBA, ENABLED_ICON_SIZE)
+        });
+        let disabled = load_themed(theme, "disabled", || {
+            IconData::new(DISABLED_ICON_RGBA, DISABLED_ICON_SIZE)
+        });
+        let astro = enabled.to_tinted([255, 0, 0]);
+        let reading = enabled.to_tinted([230, 190, 120]);
+        let warning = load_themed(theme, "warning", || disabled.to_tinted([255, 170, 0]));
+        let paused = load_themed(theme, "paused", || {
+            enabled.to_tinted([160, 160, 160]).to_crossed_out()
+        });
+
+        Self {
+            enabled,
+            disabled,
+            astro,
+            reading,
+            warning,
+            paused,
+        }
+    }
+
+    fn get(&self, kind: IconKind) -> &IconData {
+        match kind {
+            IconKind::Enabled => &self.enabled,
+            IconKind::Disabled => &self.disabled,
+            IconKind::Astro => &self.astro,
+            IconKind::Reading => &self.reading,
+            IconKind::Warning => &self.warning,
+            IconKind::Paused => &self.paused,
+        }
+    }
+}
+
+// What screen readers announce for the icon until the first status arrives
+const DESCRIPTION: &str = "hyprsunset-overdrive, blue light filter";
+
+// Menu labels mark their mnemonic with "&", which GTK gets as "_". Text
+// from elsewhere, such as a profile name, needs its underscores doubled to
+// be shown as they are.
+fn escape_label(text: &str) -> String {
+    text.replace('_', "__")
+}
+
+// How often the tray checks for a bar that started late or restarted
+const HOST_POLL_INTERVAL: Duration = Duration::from_secs(5);
+
+// Owned by the bar showing tray icons, e.g. waybar
+const WATCHER_NAME: &str = "org.kde.StatusNotifierWatcher";
+
+// `dbus-send --print-reply` answers NameHasOwner with e.g.
+// "method return ... \n   boolean true"
+fn parse_name_has_owner(output: &str) -> bool {
+    output
+        .split_whitespace()
+        .collect::<Vec<&str>>()
+        .ends_with(&["boolean", "true"])
+}
+
+// Whether a bar is ready to show the tray icon. Asks through dbus-send.
+pub fn is_tray_host_running() -> bool {
+    let output = Command::new("dbus-send")
+        .args([
+            "--session",
+            "--print-reply",
+            "--dest=org.freedesktop.DBus",
+            "/org/freedesktop/DBus",
+            "org.freedesktop.DBus.NameHasOwner",
+            &format!("string:{}", WATCHER_NAME),
+        ])
+        .output();
+
+    match output {
+        Ok(output) if output.status.success() => {
+            parse_name_has_owner(&String::from_utf8_lossy(&output.stdout))
+        }
+        Ok(_) => false,
+        Err(e) => {
+            debug!("Failed to run dbus-send: {}", e);
+            false
+        }
+    }
+}
+
+// The daemon's end of the tray. The tray is optional, so losing it is
+// reported once and doesn't get in the way of anything else.
+pub struct TrayHandle {
+    tx: UnboundedSender<TrayUpdate>,
+    gone: bool,
+}
+
+impl TrayHandle {
+    pub fn send(&mut self, update: TrayUpdate) {
+        if self.tx.unbounded_send(update).is_ok() || self.gone {
+            return;
+        }
+
+        warn!("The tray icon is gone, continuing without it");
+        self.gone = true;
+    }
+
+    pub fn is_gone(&self) -> bool {
+        self.gone
+    }
+
+    // For running without a tray icon
+    pub fn none() -> Self {
+        let (tx, _) = unbounded::<TrayUpdate>();
+        TrayHandle { tx, gone: true }
+    }
+}
+
+pub fn setup_tray_icon(
+    main_tx: Sender<Event>,
+    automatic: bool,
+    badge: bool,
+    theme: Option<IconTheme>,
+) -> TrayHandle {
+    let (tx, mut rx) = unbounded::<TrayUpdate>();
+    // Lets the tray tell itself about a new bar
+    let host_tx = tx.clone();
+
+    // We need gtk in order to build the tray icon in linux.
+    // Without gtk, the tray icon build will fail. You'll see an error
+    // message in the terminal.
+    // Also, this will be spawned in a separate thread as calling gtk::main()
+    // will block the main thread.
+    supervisor::spawn(Worker::Tray, main_tx.clone(), move || {
+        use glib;
+        use tray_icon::{
+            TrayIcon, TrayIconBuilder,
+            menu::{CheckMenuItem, Menu, MenuEvent, MenuItem, PredefinedMenuItem, Submenu},
+        };
+
+        if let Err(e) = gtk::init() {
+            error!("Failed to initialize GTK: {}", e);
+            return;
+        };
+
+        let icons = Icons::load(theme.as_ref());
+
+        let icon = match icons.get(IconKind::Enabled).to_icon() {
+            Ok(icon) => icon,
+            Err(e) => {
+                error!("Failed to create icon: {}", e);
+                return;
+            }
+        };
+
+        // Tray icons withoutmenus are not displayed on linux.
+        // Therefore, we need to addan empty menu to the tray icon.
+        // See: https://github.com/tauri-apps/tray-icon/blob/97723fd207add9c3bb0511cb0e4d04d8652a0027/src/lib.rs#L255
+        // See: https://github.com/libsdl-org/SDL/issues/12092
+
+        // Only there to be read
+        let sunrise_item = MenuItem::new("Sunrise", false, None);
+        let sunset_item = MenuItem::new("Sunset", false, None);
+
+        // Each label has its own mnemonic, for getting around the menu with
+        // the keyboard and a screen reader
+        let enable_item = MenuItem::with_id("enabled", "&Night", true, None);
+        let disable_item = MenuItem::with_id("disabled", "&Day", true, None);
+        let until_sunrise_item =
+            MenuItem::with_id("until-sunrise", "Disable until &tomorrow", true, None);
+        let reapply_item = MenuItem::with_id("reapply", "R&e-apply now", true, None);
+        let automatic_item =
+            CheckMenuItem::with_id("automatic", "&Automatic", true, automatic, None);
+        let astro_item = CheckMenuItem::with_id("astro", "A&stronomy mode", true, false, None);
+        let reading_item = CheckMenuItem::with_id("reading", "&Reading mode", true, false, None);
+        let location_item = MenuItem::with_id("location", "Set &location…", true, None);
+        let log_item = MenuItem::with_id("log", "Open lo&g", true, None);
+        let copy_status_item = MenuItem::with_id("copy-status", "&Copy status", true, None);
+        // Filled in once the daemon sends the profiles
+        let profile_menu = Submenu::with_id("profiles", "&Profile", false);
+        let by_network_item = CheckMenuItem::with_id("profile", "By &network", true, true, None);
+        if let Err(e) = profile_menu.append(&by_network_item) {
+            error!("Failed to append menu item: {}", e);
+            return;
+        };
+        let mut profile_items: Vec<CheckMenuItem> = Vec::new();
+
+        let menu = Menu::new();
+
+        if let Err(e) = menu.append_items(&[
+            &sunrise_item,
+            &sunset_item,
+            &PredefinedMenuItem::separator(),
+            &enable_item,
+            &PredefinedMenuItem::separator(),
+            &disable_item,
+            &until_sunrise_item,
+            &reapply_item,
+            &PredefinedMenuItem::separator(),
+            &automatic_item,
+            &astro_item,
+            &reading_item,
+            &PredefinedMenuItem::separator(),
+            &profile_menu,
+            &location_item,
+            &log_item,
+            &copy_status_item,
+        ]) {
+            error!("Failed to append menu item: {}", e);
+            return;
+        };
+
+        // The title would be shown next to the icon on Linux, so only the id
+        // and tooltip describe it
+        let build = |menu: &Menu| {
+            TrayIconBuilder::new()
+                .with_id("hyprsunset-overdrive")
+                .with_tooltip(DESCRIPTION)
+                .with_menu(Box::new(menu.clone()))
+                .build()
+        };
+
+        let mut tray_icon = match build(&menu) {
+            Ok(tray_icon) => tray_icon,
+            Err(e) => {
+                error!("Failed to build tray icon: {}", e);
+                return;
+            }
+        };
+
+        if let Err(e) = tray_icon.set_icon(Some(icon)) {
+            error!("Failed to set icon: {}", e);
+            return;
+        };
+
+        // A bar started after us never learns about the icon, so it is
+        // registered again whenever one appears
+        let mut host_running = is_tray_host_running();
+        glib::timeout_add_local(HOST_POLL_INTERVAL, move || {
+            let running = is_tray_host_running();
+            if running && !host_running && host_tx.unbounded_send(TrayUpdate::HostAppeared).is_err()
+            {
+                return glib::ControlFlow::Break;
+            }
+            host_running = running;
+            glib::ControlFlow::Continue
+        });
+
+        MenuEvent::set_event_handler(Some(move |event: MenuEvent| {
+            let action = match event.id().as_ref() {
+                "enabled" => TrayAction::Night,
+                "disabled" => TrayAction::Day,
+                "until-sunrise" => TrayAction::DisableUntilSunrise,
+                "reapply" => TrayAction::Reapply,
+                "automatic" => TrayAction::ToggleAutomatic,
+                "astro" => TrayAction::ToggleAstro,
+                "reading" => TrayAction::ToggleReading,
+                "location" => TrayAction::SetLocation,
+                "log" => TrayAction::OpenLog,
+                "copy-status" => TrayAction::CopyStatus,
+                "profile" => TrayAction::SelectProfile(None),
+                id => match id.strip_prefix("profile:") {
+                    Some(name) => TrayAction::SelectProfile(Some(name.to_string())),
+                    None => return,
+                },
+            };
+            // Only fails while shutting down
+            if let Err(e) = main_tx.send(Event::Tray(action)) {
+                debug!("Main loop is gone, dropping {:?}", e.0);
+            }
+        }));
+
+        // Only wakes up when a message arrives, instead of polling the
+        // channel on a timer.
+        glib::MainContext::default().spawn_local(async move {
+            // The warning icon takes precedence until the failure clears
+            let mut shown = IconKind::Enabled;
+            let mut warning = false;
+            let mut paused = None;
+            let mut progress = None;
+            let mut status = None;
+            let mut location_unset = false;
+            // Only kept with `tray_badge`
+            let mut temperature = None;
+
+            // The warnings are more important than the progress, which is
+            // more important than the status line
+            let set_tooltip = |tray_icon: &TrayIcon,
+                               warning: bool,
+                               paused: &Option<String>,
+                               location_unset: bool,
+                               progress: Option<Progress>,
+                               status: &Option<String>| {
+                let tooltip = match (warning, location_unset, progress) {
+                    (true, _, _) => Some("Can't reach hyprsunset".to_string()),
+                    _ if paused.is_some() => paused.clone(),
+                    (false, true, _) => {
+                        Some("Location not configured — schedule may be wrong".to_string())
+                    }
+                    (false, false, Some(progress)) => Some(format!(
+                        "Transitioning: {}K ({}%)",
+                        progress.temperature, progress.percent
+                    )),
+                    (false, false, None) => status.clone(),
+                };
+                if let Err(e) = tray_icon.set_tooltip(tooltip) {
+                    error!("Failed to set tooltip: {}", e);
+                };
+            };
+
+            let show_icon = |tray_icon: &TrayIcon, icon: &IconData| match icon.to_icon() {
+                Ok(icon) => match tray_icon.set_icon(Some(icon)) {
+                    Ok(_) => true,
+                    Err(e) => {
+                        error!("Failed to set icon: {}", e);
+                        false
+                    }
+                },
+                Err(e) => {
+                    error!("Failed to create icon: {}", e);
+                    false
+                }
+            };
+
+            while let Some(update) = rx.next().await {
+                let _span = debug_span!("tray", ?update).entered();
+
+                match update {
+                    TrayUpdate::Night => {
+                        shown = IconKind::Enabled;
+                        enable_item.set_enabled(false);
+                        disable_item.set_enabled(true);
+                    }
+                    TrayUpdate::Day => {
+                        shown = IconKind::Disabled;
+                        enable_item.set_enabled(true);
+                        disable_item.set_enabled(false);
+                    }
+                    TrayUpdate::Automatic(enabled) => {
+                        automatic_item.set_checked(enabled);
+                        continue;
+                    }
+                    TrayUpdate::Astro(enabled) => {
+                        astro_item.set_checked(enabled);
+                        if !enabled {
+                            continue;
+                        }
+                        shown = IconKind::Astro;
+                    }
+                    TrayUpdate::Reading(enabled) => {
+                        reading_item.set_checked(enabled);
+                        if !enabled {
+                            continue;
+                        }
+                        shown = IconKind::Reading;
+                    }
+                    TrayUpdate::BackendUp | TrayUpdate::BackendDown => {
+                        warning = update == TrayUpdate::BackendDown;
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                    }
+                    // The icon is only drawn again once another row fills
+                    TrayUpdate::Progress(current) => {
+                        let icon = icons.get(shown);
+                        let rows = |progress: Option<Progress>| {
+                            progress.map(|progress| icon.get_filled_rows(progress.percent))
+                        };
+                        let redraw = rows(current) != rows(progress);
+                        progress = current;
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                        if !redraw {
+                            continue;
+                        }
+                    }
+                    TrayUpdate::Status(line) => {
+                        status = Some(line);
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                        continue;
+                    }
+                    TrayUpdate::Temperature(current) => {
+                        if !badge || temperature == current {
+                            continue;
+                        }
+                        temperature = current;
+                    }
+                    TrayUpdate::Almanac { sunrise, sunset } => {
+                        sunrise_item.set_text(sunrise);
+                        sunset_item.set_text(sunset);
+                        continue;
+                    }
+                    TrayUpdate::Paused(current) => {
+                        if paused == current {
+                            continue;
+                        }
+                        paused = current;
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                    }
+                    TrayUpdate::LocationUnset(unset) => {
+                        location_unset = unset;
+                        location_item.set_text(if unset {
+                            "Location not configured — set &location…"
+                        } else {
+                            "Set &location…"
+                        });
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                        continue;
+                    }
+                    // Clicking checks the item by itself, so every item is
+                    // set again to keep exactly one checked
+                    TrayUpdate::Profiles {
+                        names,
+                        picked,
+                        active,
+                    } => {
+                        for item in profile_items.drain(..) {
+                            if let Err(e) = profile_menu.remove(&item) {
+                                error!("Failed to remove menu item: {}", e);
+                            };
+                        }
+                        for name in names.iter() {
+                            let item = CheckMenuItem::with_id(
+                                format!("profile:{}", name),
+                                escape_label(name),
+                                true,
+                                picked.as_ref() == Some(name),
+                                None,
+                            );
+                            match profile_menu.append(&item) {
+                                Ok(_) => profile_items.push(item),
+                                Err(e) => error!("Failed to append menu item: {}", e),
+                            };
+                        }
+
+                        by_network_item.set_checked(picked.is_none());
+                        by_network_item.set_text(match (&picked, &active) {
+                            (None, Some(active)) => {
+                                format!("By &network ({})", escape_label(active))
+                            }
+                            _ => "By &network".to_string(),
+                        });
+                        profile_menu.set_enabled(!names.is_empty());
+                        continue;
+                    }
+                    TrayUpdate::HostAppeared => {
+                        info!("Tray host appeared, registering the tray icon again");
+                        match build(&menu) {
+                            // Replacing the old icon unregisters it
+                            Ok(rebuilt) => tray_icon = rebuilt,
+                            Err(e) => {
+                                error!("Failed to build tray icon: {}", e);
+                                continue;
+                            }
+                        };
+                        set_tooltip(
+                            &tray_icon,
+                            warning,
+                            &paused,
+                            location_unset,
+                            progress,
+                            &status,
+                        );
+                    }
+                    TrayUpdate::Shutdown => {
+                        gtk::main_quit();
+                        return;
+                    }
+                };
+
+                let kind = match (warning, &paused) {
+                    (true, _) => IconKind::Warning,
+                    (false, Some(_)) => IconKind::Paused,
+                    (false, None) => shown,
+                };
+                let mut icon = icons.get(kind).clone();
+                if let Some(progress) = progress.filter(|_| !warning) {
+                    icon = icon.with_fill(progress.percent);
+                }
+                if let Some(temperature) = temperature {
+                    // "30" for 3000K
+                    icon = icon.with_badge(&(temperature / 100).to_string());
+                }
+                if !show_icon(&tray_icon, &icon) {
+                    return;
+                }
+            }
+        });
+
+        gtk::main();
+    });
+
+    TrayHandle { tx, gone: false }
+}
+
+#[test]
+fn test_embedded_icons() {
+    let icons = Icons::load(None);
+
+    for icon in [
+        &icons.enabled,
+        &icons.disabled,
+        &icons.astro,
+        &icons.reading,
+        &icons.warning,
+        &icons.paused,
+    ] {
+        assert!(icon.width > 0 && icon.height > 0);
+        assert_eq!(icon.rgba.len(), (icon.width * icon.height * 4) as usize);
+    }
+}
+
+#[test]
+fn test_tinted_icon() {
+    let icon = IconData {
+        rgba: vec![10, 200, 30, 255, 0, 0, 0, 0],
+        width: 2,
+        height: 1,
+    };
+
+    assert_eq!(
+        icon.to_tinted([255, 0, 0]).rgba,
+        vec![200, 0, 0, 255, 0, 0, 0, 0]
+    );
+    assert_eq!(
+        icon.to_tinted([255, 170, 0]).rgba,
+        vec![200, 133, 0, 255, 0, 0, 0, 0]
+    );
+}
+
+#[test]
+fn test_tray_handle_without_tray() {
+    let (tx, rx) = unbounded::<TrayUpdate>();
+    let mut tray = TrayHandle { tx, gone: false };
+
+    tray.send(TrayUpdate::Night);
+    assert!(!tray.is_gone());
+
+    // E.g. GTK failed to initialize
+    drop(rx);
+    tray.send(TrayUpdate::Day);
+    tray.send(TrayUpdate::Shutdown);
+    assert!(tray.is_gone());
+}
+
+#[test]
+fn test_parse_name_has_owner() {
+    let reply = "method return time=1750521600.0 sender=org.freedesktop.DBus -> destination=:1.42 serial=3 reply_serial=2\n   boolean true\n";
+    assert!(parse_name_has_owner(reply));
+    assert!(!parse_name_has_owner(&reply.replace("true", "false")));
+    assert!(!parse_name_has_owner(""));
+}
+
+#[test]
+fn test_badge() {
+    let icon = IconData {
+        rgba: vec![0; 16 * 16 * 4],
+        width: 16,
+        height: 16,
+    };
+    let pixel = |icon: &IconData, x: usize, y: usize| {
+        let i = (y * 16 + x) * 4;
+        icon.rgba[i..i + 4].to_vec()
+    };
+
+    // A 5x7 box in the corner, with the top of the 1 in the middle column
+    let badged = icon.with_badge("1");
+    assert_eq!(pixel(&badged, 10, 9), [0, 0, 0, 0]);
+    assert_eq!(pixel(&badged, 11, 9), [0, 0, 0, 255]);
+    assert_eq!(pixel(&badged, 12, 10), [0, 0, 0, 255]);
+    assert_eq!(pixel(&badged, 13, 10), [255, 255, 255, 255]);
+
+    // Too wide for the icon
+    assert_eq!(icon.with_badge("12345"), icon);
+}
+
+#[test]
+fn test_escape_label() {
+    assert_eq!(escape_label("home_office"), "home__office");
+    assert_eq!(escape_label("office"), "office");
+}
+
+#[test]
+fn test_fill() {
+    let icon = IconData {
+        rgba: vec![255; 2 * 4 * 4],
+        width: 2,
+        height: 4,
+    };
+    let alphas =
+        |icon: &IconData| -> Vec<u8> { icon.rgba.chunks(4).map(|pixel| pixel[3]).collect() };
+
+    assert_eq!(
+        alphas(&icon.with_fill(50)),
+        [85, 85, 85, 85, 255, 255, 255, 255]
+    );
+    assert_eq!(alphas(&icon.with_fill(100)), alphas(&icon));
+    assert_eq!(icon.get_filled_rows(0), 0);
+}
+
+#[test]
+fn test_crossed_out() {
+    let icon = IconData {
+        rgba: [[0, 0, 0, 0], [10, 20, 30, 255]].repeat(8).concat(),
+        width: 4,
+        height: 4,
+    };
+    let alphas =
+        |icon: &IconData| -> Vec<u8> { icon.rgba.chunks(4).map(|pixel| pixel[3]).collect() };
+
+    let crossed_out = icon.to_crossed_out();
+    assert_eq!(
+        alphas(&crossed_out),
+        [
+            255, 255, 0, 255, 0, 255, 0, 255, 0, 255, 255, 255, 0, 255, 0, 255
+        ]
+    );
+    assert_eq!(&crossed_out.rgba[..4], [10, 20, 30, 255]);
+}